@@ -1,74 +1,170 @@
-use glium::glutin::{VirtualKeyCode, ElementState, MouseButton, CursorState};
-use vecmath::{Vector2, Vector3, vec3_normalized, vec3_square_len};
+use glium::glutin::CursorState;
+use vecmath::{Vector2, Vector3};
 use model::Model;
+use settings::Settings;
+use input::{Action, InputState};
+use shared::{NetworkMessage, PlayerInput};
+use prediction::Prediction;
+use interpolation::{Snapshot, SnapshotBuffer};
 use std::fmt::{Debug, Formatter, Error as DebugError};
 
-// TODO: attach a move speed to an entity
-const MOVE_SPEED: f32 = 5f32;
-// TODO: Make this a config variable so the user can change it
-const ROTATE_SPEED: f32 = 0.005f32;
-
 pub struct GameState {
-	pub keyboard: KeyboardState,
+	pub input: InputState,
 	pub mouse: MouseState,
 	pub player: Option<Entity>,
 	pub entities: Vec<Entity>,
+	/// Set when logging in failed or we lost the connection, so the login panel can show it
+	pub login_error: Option<String>,
+	pub settings: Settings,
+	/// How far the simulation is between the last step and the next one, see FixedTimestep::alpha
+	pub render_alpha: f32,
+	/// The inputs of the player that the server didn't acknowledge yet
+	pub prediction: Prediction,
+	/// How far the clock of the server is ahead of ours in seconds, measured by the pings of the Network
+	pub clock_offset: f64,
+	/// The smoothed time in seconds it takes a message to get to the server and back
+	pub round_trip_time: f64,
 }
 
 impl GameState {
-	pub fn new() -> GameState {
+	pub fn new(settings: Settings) -> GameState {
 		GameState {
-			keyboard: KeyboardState::new(),
+			input: InputState::new(),
 			mouse: MouseState::new(),
-			player: Some(Entity { id: 0, position: [0.0, 0.0, -10.0], rotation: [0.0, 0.0, 0.0], model: None }),
+			// The player is created when the server identifies us after logging in
+			player: None,
 			entities: Vec::new(),
+			login_error: None,
+			settings: settings,
+			render_alpha: 0.0,
+			prediction: Prediction::new(),
+			clock_offset: 0.0,
+			round_trip_time: 0.0,
 		}
 	}
 
-	pub fn update(&mut self, delta_time: f32) {
-		if let Some(ref mut player) = self.player {
+	/// The time by the clock of the server, when now is the time by ours
+	pub fn server_time(&self, now: f64) -> f64 {
+		now + self.clock_offset
+	}
+
+	/// True while one of the chords bound to the action is held
+	pub fn is_action_active(&self, action: Action) -> bool {
+		self.settings.input_map.is_active(action, &self.input)
+	}
+
+	/// True only in the frame one of the chords bound to the action was pressed
+	pub fn was_action_triggered(&self, action: Action) -> bool {
+		self.settings.input_map.was_triggered(action, &self.input)
+	}
 
-			let mut transformation = [0.0f32, 0.0f32, 0.0f32];
+	/// Called every frame, turns the camera with the mouse
+	/// Looking around is not part of the fixed step, so it responds at the frame rate instead of the simulation rate
+	pub fn update(&mut self) {
+		let is_looking = self.is_action_active(Action::Look);
+		self.mouse.set_looking(is_looking);
+
+		if let Some(ref mut player) = self.player {
 			let mut rotation = [0.0f32, 0.0f32, 0.0f32];
-			if self.keyboard.is_pressed(VirtualKeyCode::A) {
-				transformation[0] -= 1.0f32;
-			}
-			if self.keyboard.is_pressed(VirtualKeyCode::D) {
-				transformation[0] += 1.0f32;
-			}
-			if self.keyboard.is_pressed(VirtualKeyCode::W) {
-				transformation[2] += 1.0f32;
-			}
-			if self.keyboard.is_pressed(VirtualKeyCode::S) {
-				transformation[2] -= 1.0f32;
-			}
 			if self.mouse.is_dragging {
 				rotation[0] = self.mouse.drag_difference[1];
 				rotation[1] = self.mouse.drag_difference[0];
+				if self.settings.invert_y {
+					rotation[0] = -rotation[0];
+				}
 			}
 
-			player.rotation[0] += rotation[0] * ROTATE_SPEED;
-			player.rotation[1] += rotation[1] * ROTATE_SPEED;
-			player.rotation[2] += rotation[2] * ROTATE_SPEED;
+			let sensitivity = self.settings.mouse_sensitivity;
+			player.rotation[0] += rotation[0] * sensitivity;
+			player.rotation[1] += rotation[1] * sensitivity;
+			player.rotation[2] += rotation[2] * sensitivity;
+		}
+	}
+
+	/// What the player wants to do in the next simulation step
+	pub fn current_input(&self) -> PlayerInput {
+		let mut input = PlayerInput::new();
+		if self.is_action_active(Action::StrafeLeft) {
+			input.strafe -= 1;
+		}
+		if self.is_action_active(Action::StrafeRight) {
+			input.strafe += 1;
+		}
+		if self.is_action_active(Action::MoveForward) {
+			input.forward += 1;
+		}
+		if self.is_action_active(Action::MoveBack) {
+			input.forward -= 1;
+		}
+		if let Some(ref player) = self.player {
+			input.rotation = player.rotation;
+		}
+		input
+	}
+
+	/// Run a single fixed simulation step
+	/// Returns the Input that has to be sent to the server, if there is a player to move
+	pub fn step(&mut self) -> Option<NetworkMessage> {
+		let input = self.current_input();
+		match self.player {
+			Some(ref mut player) => {
+				player.previous_position = player.position;
+				Some(self.prediction.predict(&mut player.position, &mut player.rotation, input))
+			},
+			None => None
+		}
+	}
 
-			if vec3_square_len(transformation) != 0.0f32 {
-				transformation = vec3_normalized(transformation);
-			}
+	/// Remember where the server says a remote entity is, it is drawn there after the interpolation delay
+	/// Entities that weren't spawned, or were removed already, are ignored
+	pub fn receive_entity_state(&mut self, uid: u32, position: Vector3<f32>, rotation: Vector3<f32>, time: f64) {
+		if let Some(entity) = self.entities.iter_mut().find(|e| e.id == uid) {
+			entity.snapshots.push(Snapshot {
+				time: time,
+				position: position,
+				rotation: rotation,
+			});
+		}
+	}
 
-			let rotated_transformation = {
-				let sin_angle = (-player.rotation[1]).sin();
-				let cos_angle = (-player.rotation[1]).cos();
+	/// Add an entity that came into view, an entity that was already there starts over without its old snapshots
+	pub fn spawn_entity(&mut self, uid: u32, position: Vector3<f32>, rotation: Vector3<f32>, time: f64) {
+		self.entities.retain(|e| e.id != uid);
+		let mut entity = Entity::empty();
+		entity.id = uid;
+		entity.position = position;
+		entity.previous_position = position;
+		entity.rotation = rotation;
+		entity.snapshots.push(Snapshot {
+			time: time,
+			position: position,
+			rotation: rotation,
+		});
+		self.entities.push(entity);
+	}
 
-				[
-					transformation[0] * cos_angle - transformation[2] * sin_angle,
-					0.0f32,
-					transformation[0] * sin_angle + transformation[2] * cos_angle
-				]
-			};
+	/// Move the remote entities to where they were interpolation_delay seconds before time
+	/// The snapshots are timed by the clock of the server, time is by ours
+	pub fn update_entities(&mut self, time: f64) {
+		let render_time = self.server_time(time) - self.settings.interpolation_delay as f64;
+		for entity in &mut self.entities {
+			entity.snapshots.discard_before(render_time);
+			if let Some((position, rotation)) = entity.snapshots.sample(render_time) {
+				entity.position = position;
+				entity.previous_position = position;
+				entity.rotation = rotation;
+			}
+		}
+	}
 
-			player.position[0] += rotated_transformation[0] * delta_time / 1_000_000f32 * MOVE_SPEED;
-			player.position[1] += rotated_transformation[1] * delta_time / 1_000_000f32 * MOVE_SPEED;
-			player.position[2] += rotated_transformation[2] * delta_time / 1_000_000f32 * MOVE_SPEED;
+	/// Correct the player with the position the server calculated after handling last_sequence
+	pub fn reconcile(&mut self, last_sequence: u32, position: Vector3<f32>) {
+		if let Some(ref mut player) = self.player {
+			self.prediction.reconcile(&mut player.position, last_sequence, position);
+			if last_sequence == 0 {
+				// This is where the player starts, so there is nothing to blend from
+				player.previous_position = player.position;
+			}
 		}
 	}
 }
@@ -77,8 +173,12 @@ impl GameState {
 pub struct Entity {
 	pub id: u32,
 	pub position: Vector3<f32>,
+	/// The position before the last simulation step
+	pub previous_position: Vector3<f32>,
 	pub rotation: Vector3<f32>,
 	pub model: Option<Model>,
+	/// What the server told us about this entity, only used for remote entities
+	pub snapshots: SnapshotBuffer,
 }
 
 impl Entity {
@@ -86,10 +186,21 @@ impl Entity {
 		Entity {
 			id: 0,
 			position: [0.0, 0.0, 0.0],
+			previous_position: [0.0, 0.0, 0.0],
 			rotation: [0.0, 0.0, 0.0],
-			model: None
+			model: None,
+			snapshots: SnapshotBuffer::new(),
 		}
 	}
+
+	/// The position between the previous and the current simulation step
+	pub fn interpolated_position(&self, alpha: f32) -> Vector3<f32> {
+		[
+			self.previous_position[0] + (self.position[0] - self.previous_position[0]) * alpha,
+			self.previous_position[1] + (self.position[1] - self.previous_position[1]) * alpha,
+			self.previous_position[2] + (self.position[2] - self.previous_position[2]) * alpha,
+		]
+	}
 }
 
 impl Debug for Entity {
@@ -98,41 +209,6 @@ impl Debug for Entity {
 	}
 }
 
-// TODO: Move this to it's own file
-pub struct KeyboardState {
-	keys: Vec<VirtualKeyCode>,
-}
-
-impl KeyboardState {
-	pub fn new() -> KeyboardState {
-		KeyboardState {
-			keys: Vec::new()
-		}
-	}
-	pub fn is_pressed(&self, code: VirtualKeyCode) -> bool {
-		self.keys.contains(&code)
-	}
-
-	fn add_key(&mut self, code: VirtualKeyCode) {
-		if !self.is_pressed(code) {
-			self.keys.push(code);
-		}
-	}
-
-	fn remove_key(&mut self, code: VirtualKeyCode) {
-		if let Some(index) = self.keys.iter().position(|x| *x == code) {
-			self.keys.remove(index);
-		}
-	}
-
-	pub fn update(&mut self, code: VirtualKeyCode, state: ElementState) {
-		match state {
-			ElementState::Pressed => self.add_key(code),
-			ElementState::Released => self.remove_key(code)
-		};
-	}
-}
-
 // TODO: Move this to it's own file
 pub struct MouseState {
 	pub drag_difference: Vector2<f32>,
@@ -173,16 +249,9 @@ impl MouseState {
 		}
 	}
 
-	pub fn mouse_button(&mut self, button: MouseButton, state: ElementState) {
-		match state {
-			ElementState::Pressed => if let MouseButton::Right = button {
-				self.is_dragging = true;
-				self.desired_cursor_state = CursorState::Hide;
-			},
-			ElementState::Released => if let MouseButton::Right = button {
-				self.is_dragging = false;
-				self.desired_cursor_state = CursorState::Normal;
-			}
-		};
+	/// Hide the cursor and turn the camera while the player is looking around
+	pub fn set_looking(&mut self, is_looking: bool) {
+		self.is_dragging = is_looking;
+		self.desired_cursor_state = if is_looking { CursorState::Hide } else { CursorState::Normal };
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,250 @@
+use glium::glutin::{VirtualKeyCode, MouseButton, ElementState};
+
+/// Something the player wants to do, the game only ever asks about actions and never about keys
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+	MoveForward,
+	MoveBack,
+	StrafeLeft,
+	StrafeRight,
+	Jump,
+	/// Turn the camera by moving the mouse
+	Look,
+	OpenChat,
+	OpenSettings,
+	Quit,
+}
+
+/// Every action, in the order they are written to the settings file
+pub const ACTIONS: &'static [Action] = &[
+	Action::MoveForward,
+	Action::MoveBack,
+	Action::StrafeLeft,
+	Action::StrafeRight,
+	Action::Jump,
+	Action::Look,
+	Action::OpenChat,
+	Action::OpenSettings,
+	Action::Quit,
+];
+
+impl Action {
+	/// The name of the action in the settings file
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Action::MoveForward => "move_forward",
+			Action::MoveBack => "move_back",
+			Action::StrafeLeft => "strafe_left",
+			Action::StrafeRight => "strafe_right",
+			Action::Jump => "jump",
+			Action::Look => "look",
+			Action::OpenChat => "open_chat",
+			Action::OpenSettings => "open_settings",
+			Action::Quit => "quit",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Action> {
+		ACTIONS.iter().find(|a| a.name() == name).cloned()
+	}
+}
+
+/// A single key or mouse button
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+	Key(VirtualKeyCode),
+	Mouse(MouseButton),
+}
+
+// The keys that can be bound, the name of a key in the settings file is the name of the VirtualKeyCode
+const BINDABLE_KEYS: &'static [VirtualKeyCode] = &[
+	VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D, VirtualKeyCode::E, VirtualKeyCode::F,
+	VirtualKeyCode::G, VirtualKeyCode::H, VirtualKeyCode::I, VirtualKeyCode::J, VirtualKeyCode::K, VirtualKeyCode::L,
+	VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O, VirtualKeyCode::P, VirtualKeyCode::Q, VirtualKeyCode::R,
+	VirtualKeyCode::S, VirtualKeyCode::T, VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X,
+	VirtualKeyCode::Y, VirtualKeyCode::Z,
+	VirtualKeyCode::Key0, VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3, VirtualKeyCode::Key4,
+	VirtualKeyCode::Key5, VirtualKeyCode::Key6, VirtualKeyCode::Key7, VirtualKeyCode::Key8, VirtualKeyCode::Key9,
+	VirtualKeyCode::F1, VirtualKeyCode::F2, VirtualKeyCode::F3, VirtualKeyCode::F4, VirtualKeyCode::F5, VirtualKeyCode::F6,
+	VirtualKeyCode::F7, VirtualKeyCode::F8, VirtualKeyCode::F9, VirtualKeyCode::F10, VirtualKeyCode::F11, VirtualKeyCode::F12,
+	VirtualKeyCode::Up, VirtualKeyCode::Down, VirtualKeyCode::Left, VirtualKeyCode::Right,
+	VirtualKeyCode::Escape, VirtualKeyCode::Space, VirtualKeyCode::Return, VirtualKeyCode::Tab, VirtualKeyCode::Back,
+	VirtualKeyCode::LShift, VirtualKeyCode::RShift, VirtualKeyCode::LControl, VirtualKeyCode::RControl,
+	VirtualKeyCode::LAlt, VirtualKeyCode::RAlt,
+];
+
+const MOUSE_BUTTONS: &'static [(MouseButton, &'static str)] = &[
+	(MouseButton::Left, "MouseLeft"),
+	(MouseButton::Right, "MouseRight"),
+	(MouseButton::Middle, "MouseMiddle"),
+];
+
+impl Input {
+	/// Keys are named after their VirtualKeyCode, mouse buttons are MouseLeft, MouseRight and MouseMiddle
+	pub fn name(&self) -> String {
+		match *self {
+			Input::Key(key) => format!("{:?}", key),
+			Input::Mouse(button) => match MOUSE_BUTTONS.iter().find(|b| b.0 == button) {
+				Some(&(_, name)) => name.to_string(),
+				None => format!("{:?}", button)
+			}
+		}
+	}
+
+	/// Names are not case sensitive
+	pub fn from_name(name: &str) -> Option<Input> {
+		let inputs = BINDABLE_KEYS.iter().map(|k| Input::Key(*k))
+			.chain(MOUSE_BUTTONS.iter().map(|b| Input::Mouse(b.0)));
+		for input in inputs {
+			if input.name().eq_ignore_ascii_case(name) {
+				return Some(input);
+			}
+		}
+		None
+	}
+}
+
+/// One or more inputs that have to be held at the same time, written as "LControl+Q" in the settings file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chord {
+	pub inputs: Vec<Input>,
+}
+
+impl Chord {
+	pub fn new(inputs: Vec<Input>) -> Chord {
+		Chord {
+			inputs: inputs,
+		}
+	}
+
+	pub fn single(input: Input) -> Chord {
+		Chord::new(vec![input])
+	}
+
+	pub fn name(&self) -> String {
+		self.inputs.iter().map(|i| i.name()).collect::<Vec<_>>().join("+")
+	}
+
+	pub fn from_name(name: &str) -> Option<Chord> {
+		let mut inputs = Vec::new();
+		for part in name.split('+') {
+			match Input::from_name(part.trim()) {
+				Some(input) => inputs.push(input),
+				None => return None
+			}
+		}
+		Some(Chord::new(inputs))
+	}
+
+	fn is_held(&self, state: &InputState) -> bool {
+		self.inputs.iter().all(|i| state.is_pressed(*i))
+	}
+
+	// A chord contains another if it needs all of the inputs of the other and more
+	fn contains(&self, other: &Chord) -> bool {
+		self.inputs.len() > other.inputs.len() && other.inputs.iter().all(|i| self.inputs.contains(i))
+	}
+}
+
+/// Which chords trigger which actions, an action can have any amount of chords
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputMap {
+	pub bindings: Vec<(Action, Chord)>,
+}
+
+impl InputMap {
+	pub fn new() -> InputMap {
+		let key = |action, key| (action, Chord::single(Input::Key(key)));
+		InputMap {
+			bindings: vec![
+				key(Action::MoveForward, VirtualKeyCode::W),
+				key(Action::MoveBack, VirtualKeyCode::S),
+				key(Action::StrafeLeft, VirtualKeyCode::A),
+				key(Action::StrafeRight, VirtualKeyCode::D),
+				key(Action::Jump, VirtualKeyCode::Space),
+				(Action::Look, Chord::single(Input::Mouse(MouseButton::Right))),
+				key(Action::OpenChat, VirtualKeyCode::Return),
+				key(Action::OpenSettings, VirtualKeyCode::F1),
+				key(Action::Quit, VirtualKeyCode::Escape),
+			],
+		}
+	}
+
+	/// All the chords of the given action
+	pub fn chords(&self, action: Action) -> Vec<&Chord> {
+		self.bindings.iter().filter(|b| b.0 == action).map(|b| &b.1).collect()
+	}
+
+	/// Replace all the chords of the given action
+	pub fn bind(&mut self, action: Action, chords: Vec<Chord>) {
+		self.bindings.retain(|b| b.0 != action);
+		for chord in chords {
+			self.bindings.push((action, chord));
+		}
+		// Keep the bindings in the order of ACTIONS, so the same bindings always compare equal
+		self.bindings.sort_by_key(|b| ACTIONS.iter().position(|a| *a == b.0));
+	}
+
+	// The chords that are held, without the ones that are part of a bigger chord that is also held
+	// This way holding LControl+W doesn't also move forward when W is bound to MoveForward
+	fn active_chords(&self, state: &InputState) -> Vec<&(Action, Chord)> {
+		let held: Vec<&(Action, Chord)> = self.bindings.iter().filter(|b| b.1.is_held(state)).collect();
+		held.iter().filter(|b| !held.iter().any(|other| other.1.contains(&b.1))).cloned().collect()
+	}
+
+	/// True while any of the chords of the action is held
+	pub fn is_active(&self, action: Action, state: &InputState) -> bool {
+		self.active_chords(state).iter().any(|b| b.0 == action)
+	}
+
+	/// True only in the frame that one of the chords of the action was completed
+	pub fn was_triggered(&self, action: Action, state: &InputState) -> bool {
+		self.active_chords(state).iter()
+			.any(|b| b.0 == action && b.1.inputs.iter().any(|i| state.was_pressed(*i)))
+	}
+}
+
+/// Which keys and mouse buttons are held down
+pub struct InputState {
+	pressed: Vec<Input>,
+	// Pressed since the last reset
+	just_pressed: Vec<Input>,
+}
+
+impl InputState {
+	pub fn new() -> InputState {
+		InputState {
+			pressed: Vec::new(),
+			just_pressed: Vec::new(),
+		}
+	}
+
+	pub fn is_pressed(&self, input: Input) -> bool {
+		self.pressed.contains(&input)
+	}
+
+	pub fn was_pressed(&self, input: Input) -> bool {
+		self.just_pressed.contains(&input)
+	}
+
+	pub fn update(&mut self, input: Input, state: ElementState) {
+		match state {
+			ElementState::Pressed => if !self.is_pressed(input) {
+				self.pressed.push(input);
+				self.just_pressed.push(input);
+			},
+			ElementState::Released => self.pressed.retain(|i| *i != input)
+		};
+	}
+
+	/// Called at the end of every frame
+	pub fn reset(&mut self) {
+		self.just_pressed.clear();
+	}
+
+	/// Release everything, for when the window loses focus and we won't hear about released keys
+	pub fn clear(&mut self) {
+		self.pressed.clear();
+		self.just_pressed.clear();
+	}
+}
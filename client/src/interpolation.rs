@@ -0,0 +1,109 @@
+use vecmath::Vector3;
+use std::f32::consts::PI;
+
+/// The amount of snapshots kept per entity, older ones are dropped even if they were never used
+pub const MAX_SNAPSHOTS: usize = 32;
+
+/// Seconds we keep moving an entity past its last snapshot before it stops and waits for the server
+/// Guessing further than this makes an entity that stopped slide through walls when packets are late
+pub const MAX_EXTRAPOLATION: f64 = 0.25;
+
+/// The position and rotation of an entity at the time we received it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+	pub time: f64,
+	pub position: Vector3<f32>,
+	pub rotation: Vector3<f32>,
+}
+
+/// The recent snapshots of a remote entity
+/// Entities are drawn a little in the past, so there is almost always a snapshot on both sides of the drawn time to blend between
+pub struct SnapshotBuffer {
+	// Oldest first
+	snapshots: Vec<Snapshot>,
+}
+
+impl SnapshotBuffer {
+	pub fn new() -> SnapshotBuffer {
+		SnapshotBuffer {
+			snapshots: Vec::new(),
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.snapshots.len()
+	}
+
+	/// Add a snapshot, a snapshot with the same time as an existing one replaces it
+	pub fn push(&mut self, snapshot: Snapshot) {
+		match self.snapshots.iter().position(|s| s.time >= snapshot.time) {
+			Some(index) if self.snapshots[index].time == snapshot.time => self.snapshots[index] = snapshot,
+			Some(index) => self.snapshots.insert(index, snapshot),
+			None => self.snapshots.push(snapshot)
+		}
+		if self.snapshots.len() > MAX_SNAPSHOTS {
+			let excess = self.snapshots.len() - MAX_SNAPSHOTS;
+			self.snapshots.drain(..excess);
+		}
+	}
+
+	/// Drop the snapshots that can't be used anymore to sample at time or later
+	/// The last snapshot before time is kept, it is needed to blend towards the next one
+	pub fn discard_before(&mut self, time: f64) {
+		let older = self.snapshots.iter().take_while(|s| s.time <= time).count();
+		if older > 1 {
+			self.snapshots.drain(..older - 1);
+		}
+	}
+
+	/// The position and rotation at the given time
+	/// Before the first snapshot this is the first snapshot, after the last one the entity keeps moving for at most MAX_EXTRAPOLATION seconds
+	pub fn sample(&self, time: f64) -> Option<(Vector3<f32>, Vector3<f32>)> {
+		let first = match self.snapshots.first() {
+			Some(s) => s,
+			None => return None
+		};
+		if time <= first.time {
+			return Some((first.position, first.rotation));
+		}
+		if let Some(index) = self.snapshots.iter().position(|s| s.time > time) {
+			let from = &self.snapshots[index - 1];
+			let to = &self.snapshots[index];
+			let alpha = ((time - from.time) / (to.time - from.time)) as f32;
+			return Some(blend(from, to, alpha));
+		}
+
+		// We ran out of snapshots, continue in the direction of the last two
+		let last = &self.snapshots[self.snapshots.len() - 1];
+		if self.snapshots.len() < 2 {
+			return Some((last.position, last.rotation));
+		}
+		let previous = &self.snapshots[self.snapshots.len() - 2];
+		let ahead = (time - last.time).min(MAX_EXTRAPOLATION);
+		let alpha = ((last.time - previous.time + ahead) / (last.time - previous.time)) as f32;
+		Some(blend(previous, last, alpha))
+	}
+}
+
+// Blend between two snapshots, an alpha above 1 continues past the second one
+fn blend(from: &Snapshot, to: &Snapshot, alpha: f32) -> (Vector3<f32>, Vector3<f32>) {
+	let mut position = [0.0; 3];
+	let mut rotation = [0.0; 3];
+	for i in 0..3 {
+		position[i] = from.position[i] + (to.position[i] - from.position[i]) * alpha;
+		rotation[i] = from.rotation[i] + angle_difference(from.rotation[i], to.rotation[i]) * alpha;
+	}
+	(position, rotation)
+}
+
+/// The smallest angle that turns from a to b, so turning from 350 to 10 degrees goes through 0 instead of 180
+pub fn angle_difference(a: f32, b: f32) -> f32 {
+	let difference = (b - a) % (2.0 * PI);
+	if difference > PI {
+		difference - 2.0 * PI
+	} else if difference < -PI {
+		difference + 2.0 * PI
+	} else {
+		difference
+	}
+}
@@ -9,6 +9,7 @@ extern crate image;
 extern crate time;
 extern crate vecmath;
 extern crate shared;
+extern crate toml;
 
 #[macro_use]
 mod error;
@@ -18,15 +19,21 @@ mod game_state;
 mod network;
 mod ui;
 mod handler;
+mod settings;
+mod input;
+mod prediction;
+mod interpolation;
 #[cfg(test)]
 mod test;
 
 use game_state::{Entity, GameState};
 use render::*;
 use glium::Surface;
-use glium::glutin::{VirtualKeyCode, Event};
+use glium::glutin::Event;
 use shared::*;
 use model::Model;
+use settings::{Settings, SETTINGS_FILE};
+use input::{Action, Input};
 use std::io::Write;
 use std::fs;
 use std::error::Error;
@@ -43,30 +50,148 @@ fn log_error(e: error::GameError) -> Result<(), error::GameError> {
 	Ok(())
 }
 
+// Show the current settings in the settings panel
+fn fill_settings_panel(ui: &mut ui::UI, settings: &Settings) {
+	ui.set_text("server", format!("{}:{}", settings.server_host, settings.server_port));
+	ui.set_text("sensitivity", settings.mouse_sensitivity);
+	ui.set_text("fov", settings.fov);
+	ui.set_text("invert_y", if settings.invert_y { "Invert Y: on" } else { "Invert Y: off" });
+}
+
+// Read the settings panel into the settings, nothing is changed if any of the values is invalid
+fn read_settings_panel(ui: &ui::UI, settings: &mut Settings) -> Result<(), String> {
+	let server = ui.get_text("server").unwrap_or_else(String::new);
+	let (host, port) = match server.rfind(':') {
+		Some(index) => (server[..index].trim().to_string(), server[index + 1..].trim().parse::<u16>().ok()),
+		None => (server.trim().to_string(), Some(settings.server_port))
+	};
+	let port = match port {
+		Some(p) if p > 0 => p,
+		_ => return Err("The server should look like host:port".to_string())
+	};
+	if host.is_empty() {
+		return Err("The server should look like host:port".to_string());
+	}
+	let sensitivity = match ui.get_text("sensitivity").and_then(|s| s.trim().parse::<f32>().ok()) {
+		Some(s) if s > 0.0 => s,
+		_ => return Err("The mouse sensitivity should be a number above 0".to_string())
+	};
+	let fov = match ui.get_text("fov").and_then(|s| s.trim().parse::<f32>().ok()) {
+		Some(f) if f >= 30.0 && f <= 120.0 => f,
+		_ => return Err("The field of view should be between 30 and 120 degrees".to_string())
+	};
+	settings.server_host = host;
+	settings.server_port = port;
+	settings.mouse_sensitivity = sensitivity;
+	settings.fov = fov;
+	Ok(())
+}
+
+fn handle_ui_action(action: ui::UIAction, ui: &mut ui::UI, network: &mut network::Network, game_state: &mut GameState, settings_open: &mut bool) -> Result<(), error::GameError> {
+	match action {
+		ui::UIAction::ToggleInvertY => {
+			game_state.settings.invert_y = !game_state.settings.invert_y;
+			fill_settings_panel(ui, &game_state.settings);
+			return Ok(());
+		},
+		ui::UIAction::SaveSettings => {
+			let old_server = (game_state.settings.server_host.clone(), game_state.settings.server_port);
+			if let Err(e) = read_settings_panel(ui, &mut game_state.settings) {
+				ui.set_text("settings_status", e);
+				return Ok(());
+			}
+			if old_server != (game_state.settings.server_host.clone(), game_state.settings.server_port) {
+				network.set_server(&game_state.settings.server_host, game_state.settings.server_port);
+			}
+			match game_state.settings.save(SETTINGS_FILE) {
+				Ok(()) => ui.set_text("settings_status", "Saved"),
+				Err(e) => ui.set_text("settings_status", format!("Could not save the settings: {}", e))
+			}
+			return Ok(());
+		},
+		ui::UIAction::CloseSettings => {
+			*settings_open = false;
+			return Ok(());
+		},
+		ui::UIAction::Login | ui::UIAction::Register => {}
+	}
+
+	if !network.is_connected() {
+		ui.set_text("status", "Not connected to the server");
+		return Ok(());
+	}
+	let username = ui.get_text("username").unwrap_or_else(String::new);
+	let password = ui.get_text("password").unwrap_or_else(String::new);
+	let message = match action {
+		ui::UIAction::Login => NetworkMessage::Login { username: username, password: password },
+		ui::UIAction::Register => NetworkMessage::Register { username: username, password: password },
+		_ => return Ok(())
+	};
+	ui.set_text("status", "Logging in...");
+	try!(network.send(message));
+	Ok(())
+}
+
 fn run() -> Result<(), error::GameError> {
-	let mut display_data = try!(DisplayData::new());
+	// Don't overwrite a settings file we couldn't read, the player probably wants to fix it
+	let (settings, save_on_exit) = match Settings::load(SETTINGS_FILE) {
+		Ok(s) => (s, true),
+		Err(e) => {
+			println!("{}, using the default settings", e);
+			(Settings::new(), false)
+		}
+	};
+	let mut display_data = try!(DisplayData::new(&settings));
 	try!(handler::texture::init(&display_data));
-	let mut game_state = GameState::new();
 	let model = try!(Model::new_cube(&display_data));
-	let mut network = network::Network::new();
+	let mut network = network::Network::new(&settings.server_host, settings.server_port);
+	let mut game_state = GameState::new(settings);
+	let mut settings_open = false;
 
 	let mut last_time = time::precise_time_ns();
+	let mut timestep = FixedTimestep::new();
 	let mut ui = ui::UI::new();
 
 	let size = try!(display_data.get_screen_dimensions());
 	try!(ui.resize(&display_data, size.0, size.1));
 	try!(ui.load(&display_data, ui::UIView::Login));
-	loop {
+	'game: loop {
 
 		let time_now = time::precise_time_ns();
 		let diff: f32 = ((time_now - last_time) / 1000) as f32;
 		last_time = time_now;
 
-		game_state.update(diff);
+		// The simulation runs in fixed steps, so movement doesn't depend on the frame rate
+		game_state.update();
+		for _ in 0..timestep.advance(diff as f64 / 1_000_000.0) {
+			if let Some(input) = game_state.step() {
+				try!(network.send(input));
+			}
+		}
+		game_state.render_alpha = timestep.alpha();
+		game_state.update_entities(time::precise_time_s());
 		try!(display_data.update(&mut game_state));
 		network.update(&mut game_state);
 		ui.update(diff);
 
+		// Stay on the login panel until the server gave us a player
+		let desired_view = if settings_open {
+			ui::UIView::Settings
+		} else if game_state.player.is_some() {
+			ui::UIView::None
+		} else {
+			ui::UIView::Login
+		};
+		if ui.view != desired_view {
+			try!(ui.load(&display_data, desired_view));
+			if desired_view == ui::UIView::Settings {
+				fill_settings_panel(&mut ui, &game_state.settings);
+			}
+		}
+		if let Some(error) = game_state.login_error.take() {
+			ui.set_text("status", error);
+		}
+
 		let mut target = display_data.display.draw();
 		if game_state.player.is_some() {
 			target.clear_color_and_depth((0.0, 0.0, 1.0, 1.0), 1.0);
@@ -89,14 +214,6 @@ fn run() -> Result<(), error::GameError> {
 
 		game_state.mouse.reset();
 
-		if let Some(ref player) = game_state.player {
-			try!(network.send_throttled(NetworkMessage::SetPosition {
-				uid: 0,
-				position: player.position,
-				rotation: player.rotation,
-			}, 100));
-		}
-
 		let mut new_size = None;
 		for ev in display_data.display.poll_events() {
 			if ui.handle_event(&ev) {
@@ -107,24 +224,39 @@ fn run() -> Result<(), error::GameError> {
 			// And allow certain elements to override each other
 			// For example: When typing in a textbox, you don't want to move your character or hit any other buttons
 			match ev {
-				Event::Closed => return Ok(()),
-				Event::KeyboardInput(state, _, Some(key)) => {
-					game_state.keyboard.update(key, state);
-
-					if game_state.keyboard.is_pressed(VirtualKeyCode::Escape) {
-						return Ok(());
-					}
-				}
+				Event::Closed => break 'game,
+				Event::KeyboardInput(state, _, Some(key)) => game_state.input.update(Input::Key(key), state),
 				Event::MouseMoved(x, y) => game_state.mouse.mouse_moved(x, y, try!(display_data.get_screen_dimensions())),
-				Event::MouseInput(state, button) => game_state.mouse.mouse_button(button, state),
+				Event::MouseInput(state, button) => game_state.input.update(Input::Mouse(button), state),
+				// We don't hear about keys that are released while another window has focus
+				Event::Focused(false) => game_state.input.clear(),
 				Event::Resized(width, height) => new_size = Some((width, height)),
 				_ => ()
 			}
 		}
 
+		if game_state.was_action_triggered(Action::Quit) {
+			break 'game;
+		}
+		if game_state.was_action_triggered(Action::OpenSettings) {
+			settings_open = !settings_open;
+		}
+		game_state.input.reset();
+
+		for action in ui.take_actions() {
+			try!(handle_ui_action(action, &mut ui, &mut network, &mut game_state, &mut settings_open));
+		}
+
 		if let Some(size) = new_size {
 			display_data.resize(size.0, size.1);
 			try!(ui.resize(&display_data, size.0, size.1));
+			// Start with the same window size next time
+			game_state.settings.resolution = size;
 		}
 	}
+
+	if save_on_exit {
+		try!(game_state.settings.save(SETTINGS_FILE));
+	}
+	Ok(())
 }
@@ -1,51 +1,108 @@
-use shared::{ ClientSocket, NetworkMessage };
+use shared::{ ClientSocket, ClientError, NetworkMessage, MessageHandler, PlayerInput, EntityDelta, SnapshotDecoder, dispatch, hello };
+use vecmath::Vector3;
 use std::sync::mpsc::{ Receiver, channel };
 use game_state::{ GameState, Entity };
+use prediction::Prediction;
 use std::thread;
 use error;
 use time;
 
+/// The version of this client, the server logs it when the protocol versions don't match
+pub const CLIENT_BUILD: &'static str = env!("CARGO_PKG_VERSION");
+
+/// Seconds between the pings we send to the server
+pub const PING_INTERVAL: f64 = 1.0;
+
+/// Seconds we wait before reconnecting, this doubles with every attempt that fails until MAX_RECONNECT_DELAY
+pub const INITIAL_RECONNECT_DELAY: f64 = 1.0;
+pub const MAX_RECONNECT_DELAY: f64 = 30.0;
+
 pub struct Network {
 	socket: ClientSocket,
 	last_connect_time: Option<f64>,
 	is_connecting: bool,
 	connect_receiver: Option<Receiver<ClientSocket>>,
-	last_send_messages: Vec<(NetworkMessage, u64)>,
+	// Set when the server refused our Hello, there is no point in reconnecting after that
+	rejected_reason: Option<String>,
+	// The WorldSnapshots of the server are deltas against the ones we received before
+	snapshots: SnapshotDecoder,
+	// None until the server welcomed us, it doesn't read anything but Hello before that
+	last_ping_time: Option<f64>,
+	// The token of our player on the server, so we can take it back after losing the connection
+	session: Option<String>,
+	reconnect_delay: f64,
+	// Set when the server told us which player is ours, inputs from before that would be for nobody
+	identified: bool,
 }
 
 impl Network {
-	pub fn new() -> Network {
+	pub fn new(host: &str, port: u16) -> Network {
 		Network {
-			socket: ClientSocket::create("localhost", 8080),
+			socket: ClientSocket::create(host, port),
 			last_connect_time: None,
 			is_connecting: false,
 			connect_receiver: None,
-			last_send_messages: Vec::new(),
+			rejected_reason: None,
+			snapshots: SnapshotDecoder::new(),
+			last_ping_time: None,
+			session: None,
+			reconnect_delay: INITIAL_RECONNECT_DELAY,
+			identified: false,
 		}
 	}
 
+	/// Connect to a different server, dropping the current connection
+	pub fn set_server(&mut self, host: &str, port: u16) {
+		self.disconnect();
+		self.socket = ClientSocket::create(host, port);
+		self.rejected_reason = None;
+		self.last_connect_time = None;
+		// Sessions only mean something to the server that handed them out
+		self.session = None;
+		self.reconnect_delay = INITIAL_RECONNECT_DELAY;
+	}
+
 	fn disconnect(&mut self){
 		self.socket.disconnect();
 		self.is_connecting = false;
 		self.connect_receiver = None;
+		self.snapshots = SnapshotDecoder::new();
+		self.last_ping_time = None;
+		self.identified = false;
 
 		self.last_connect_time = Some(time::precise_time_s());
 	}
 
 	fn attempt_connect(&mut self) {
+		if self.rejected_reason.is_some() {
+			return;
+		}
 		if self.is_connecting {
+			let mut connected_socket = None;
 			if let Some(ref receiver) = self.connect_receiver {
 				if let Ok(socket) = receiver.try_recv() {
-					self.is_connecting = false;
-					self.socket = socket;
-					self.last_connect_time = Some(time::precise_time_s());
+					connected_socket = Some(socket);
+				}
+			}
+			if let Some(socket) = connected_socket {
+				self.is_connecting = false;
+				self.socket = socket;
+				self.last_connect_time = Some(time::precise_time_s());
+				if self.socket.is_connected() {
+					if let Err(e) = self.socket.send(hello(CLIENT_BUILD)) {
+						println!("Could not send hello: {:?}", e);
+						self.disconnect();
+					}
+				} else {
+					// Reset when the server welcomes us, so only attempts that fail make us wait longer
+					self.reconnect_delay = (self.reconnect_delay * 2.0).min(MAX_RECONNECT_DELAY);
 				}
 			}
 			return;
 		}
 		let should_connect = match self.last_connect_time {
 			None => true,
-			Some(t) => time::precise_time_s() - t > 1f64
+			Some(t) => time::precise_time_s() - t > self.reconnect_delay
 		};
 		if should_connect {
 			self.is_connecting = true;
@@ -59,98 +116,250 @@ impl Network {
 		}
 	}
 
-	fn handle_message(&mut self, message: NetworkMessage, game_state: &mut GameState){
-		// TODO: Find a better solution for this
-		// because this function is going to be massive if we all put it in here
-		if message == NetworkMessage::Ping {
-			if let Err(e) = self.socket.send(NetworkMessage::Ping) {
+	pub fn send(&mut self, message: NetworkMessage) -> Result<(), error::GameError> {
+		if let NetworkMessage::Input { .. } = message {
+			if !self.identified {
+				return Ok(());
+			}
+		}
+		if self.socket.is_connected() {
+			// A connection that can't be written to is as good as gone, update reconnects and resumes the session
+			if let Err(e) = self.socket.send(message) {
 				println!("Socket error: {:?}", e);
 				self.disconnect();
-				return;
 			}
 		}
-		if let NetworkMessage::Identify(uid) = message {
-			if let Some(ref mut player) = game_state.player {
-				player.id = uid;
-			} else {
-				game_state.player = Some(Entity {
-					position: [0.0, 0.0, 0.0],
-					rotation: [0.0, 0.0, 0.0],
-					id: uid,
-					model: None,
-				});
+		Ok(())
+	}
+
+	pub fn is_connected(&self) -> bool {
+		self.socket.is_connected()
+	}
+
+	fn ping(&mut self) -> Result<(), ClientError> {
+		let now = time::precise_time_s();
+		self.last_ping_time = Some(now);
+		let ping = self.socket.pings.ping(now);
+		self.socket.send(ping)
+	}
+
+	pub fn update(&mut self, game_state: &mut GameState) {
+		if !self.socket.is_connected() {
+			// With a session the server keeps our player for a while, so we keep it as well until resuming fails
+			if self.session.is_some() {
+				game_state.entities.clear();
+			} else if game_state.player.is_some() {
+				game_state.player = None;
+				game_state.entities.clear();
+				game_state.login_error = Some("Lost the connection to the server".to_string());
 			}
+			self.attempt_connect();
 			return;
 		}
-		if let NetworkMessage::RemoveEntity { uid } = message{
-			if let Some(index) = game_state.entities.iter().position(|x| x.id == uid) {
-				game_state.entities.remove(index);
-			}
+		if let Err(e) = self.socket.flush() {
+			println!("Socket error: {:?}", e);
+			self.disconnect();
+			return;
 		}
-		if let NetworkMessage::SetPosition { uid, position, .. } = message {
-			if let Some(ref player) = game_state.player {
-				if player.id == uid {
+		if let Some(last_ping_time) = self.last_ping_time {
+			if time::precise_time_s() - last_ping_time > PING_INTERVAL {
+				if let Err(e) = self.ping() {
+					println!("Socket error: {:?}", e);
+					self.disconnect();
 					return;
 				}
 			}
-			let mut found = false;
-			{
-				let item = game_state.entities.iter_mut().find(|c| c.id == uid);
-				if let Some(c) = item {
-					c.position = position;
-					found = true;
+		}
+		match self.socket.poll_messages() {
+			Ok(messages) => {
+				for message in messages {
+					if let Err(e) = dispatch(self, game_state, message) {
+						println!("Socket error: {:?}", e);
+						self.disconnect();
+					}
+					// Handling a message can disconnect us, the rest belongs to the old connection
+					if !self.socket.is_connected() {
+						break;
+					}
 				}
+			},
+			Err(e) => {
+				println!("Socket error: {:?}", e);
+				self.disconnect();
 			}
-			if !found {
-				let e = Entity {
-					position: position,
-					rotation: [0.0, 0.0, 0.0],
-					id: uid,
-					model: None,
-				};
-				game_state.entities.push(e);
+		}
+	}
+}
+// When the message that just arrived was sent, by the clock of the server
+fn sent_time(game_state: &GameState) -> f64 {
+	game_state.server_time(time::precise_time_s()) - game_state.round_trip_time / 2.0
+}
+
+impl MessageHandler for Network {
+	type Context = GameState;
+	type Error = ClientError;
+
+	fn handle_hello(&mut self, _: &mut GameState, _: u32, _: String, _: Vec<String>) -> Result<(), ClientError> {
+		println!("Ignoring Hello from the server");
+		Ok(())
+	}
+
+	fn handle_welcome(&mut self, _: &mut GameState, protocol_version: u32, capabilities: Vec<String>) -> Result<(), ClientError> {
+		println!("Connected with protocol version {}, capabilities: {:?}", protocol_version, capabilities);
+		self.reconnect_delay = INITIAL_RECONNECT_DELAY;
+		if let Some(token) = self.session.clone() {
+			if let Err(e) = self.socket.send(NetworkMessage::Resume { token: token }) {
+				return Err(e);
 			}
 		}
+		// The sooner we know the clock of the server, the sooner the entities move smoothly
+		self.ping()
 	}
 
-	pub fn send_throttled(&mut self, message: NetworkMessage, delay_in_ms: u64) -> Result<(), error::GameError> {
-		let position = self.last_send_messages.iter_mut().position(|x| x.0.is_same_type_as(&message)).unwrap_or_else(||self.last_send_messages.len());
-		if position != self.last_send_messages.len() {
-			let time = self.last_send_messages[position].1;
-			if time > ::time::precise_time_ns() - delay_in_ms * 1_000_000u64 {
+	fn handle_reject(&mut self, game_state: &mut GameState, reason: String) -> Result<(), ClientError> {
+		println!("Server rejected the connection: {}", reason);
+		game_state.login_error = Some(reason.clone());
+		self.rejected_reason = Some(reason);
+		self.disconnect();
+		Ok(())
+	}
+
+	fn handle_disconnect(&mut self, game_state: &mut GameState, reason: String) -> Result<(), ClientError> {
+		println!("The server closed the connection: {}", reason);
+		// Resuming would hide why the server closed the connection, logging in again still takes the player back
+		self.session = None;
+		game_state.player = None;
+		game_state.entities.clear();
+		game_state.login_error = Some(reason);
+		self.disconnect();
+		Ok(())
+	}
+
+	fn handle_ping(&mut self, _: &mut GameState, sequence: u32, _: f64) -> Result<(), ClientError> {
+		self.socket.send(NetworkMessage::Pong { sequence: sequence, time: time::precise_time_s() })
+	}
+
+	fn handle_pong(&mut self, game_state: &mut GameState, sequence: u32, server_time: f64) -> Result<(), ClientError> {
+		if self.socket.pings.pong(sequence, server_time, time::precise_time_s()) {
+			game_state.clock_offset = self.socket.pings.clock_offset().unwrap_or(0.0);
+			game_state.round_trip_time = self.socket.pings.rtt.unwrap_or(0.0);
+		}
+		Ok(())
+	}
+
+	fn handle_identify(&mut self, game_state: &mut GameState, uid: u32) -> Result<(), ClientError> {
+		self.identified = true;
+		game_state.prediction = Prediction::new();
+		if let Some(ref mut player) = game_state.player {
+			player.id = uid;
+		} else {
+			let mut player = Entity::empty();
+			player.id = uid;
+			game_state.player = Some(player);
+		}
+		Ok(())
+	}
+
+	fn handle_remove_entity(&mut self, game_state: &mut GameState, uid: u32) -> Result<(), ClientError> {
+		if let Some(index) = game_state.entities.iter().position(|x| x.id == uid) {
+			game_state.entities.remove(index);
+		}
+		// The server made the same change to its snapshots, the next deltas are made without the entity
+		self.snapshots.forget(uid);
+		Ok(())
+	}
+
+	fn handle_spawn_entity(&mut self, game_state: &mut GameState, uid: u32, position: Vector3<f32>, rotation: Vector3<f32>) -> Result<(), ClientError> {
+		let sent_time = sent_time(game_state);
+		game_state.spawn_entity(uid, position, rotation, sent_time);
+		Ok(())
+	}
+
+	fn handle_set_position(&mut self, game_state: &mut GameState, uid: u32, position: Vector3<f32>, rotation: Vector3<f32>) -> Result<(), ClientError> {
+		if let Some(ref player) = game_state.player {
+			if player.id == uid {
 				return Ok(());
 			}
-			self.last_send_messages.remove(position);
 		}
-		self.last_send_messages.push((message.clone(), time::precise_time_ns()));
-		try!(self.send(message));
+		let sent_time = sent_time(game_state);
+		game_state.receive_entity_state(uid, position, rotation, sent_time);
 		Ok(())
 	}
 
-	pub fn send(&mut self, message: NetworkMessage) -> Result<(), error::GameError> {
-		if self.socket.is_connected() {
-			// TODO: Disconnect on error?
-			try!(self.socket.send(message));
-		}
+	fn handle_register(&mut self, _: &mut GameState, _: String, _: String) -> Result<(), ClientError> {
+		println!("Ignoring Register from the server");
 		Ok(())
 	}
 
-	pub fn update(&mut self, game_state: &mut GameState) {
-		if !self.socket.is_connected() {
-			self.attempt_connect();
-			return;
+	fn handle_login(&mut self, _: &mut GameState, _: String, _: String) -> Result<(), ClientError> {
+		println!("Ignoring Login from the server");
+		Ok(())
+	}
+
+	fn handle_login_result(&mut self, game_state: &mut GameState, error: Option<String>) -> Result<(), ClientError> {
+		// On success the server identifies us, which creates the player
+		if error.is_some() {
+			// This is also the answer to Resume, when our player is gone
+			self.session = None;
+			game_state.player = None;
+			game_state.entities.clear();
 		}
-		while match self.socket.get_message() {
-			Ok(Some(message)) => {
-				self.handle_message(message, game_state);
-				true
-			},
-			Ok(None) => false,
-			Err(e) => {
-				println!("Socket error: {:?}", e);
-				self.disconnect();
-				false
+		game_state.login_error = error;
+		Ok(())
+	}
+
+	fn handle_server_shutdown(&mut self, game_state: &mut GameState) -> Result<(), ClientError> {
+		println!("The server is shutting down");
+		self.session = None;
+		game_state.player = None;
+		game_state.entities.clear();
+		game_state.login_error = Some("The server shut down".to_string());
+		self.disconnect();
+		Ok(())
+	}
+
+	fn handle_input(&mut self, _: &mut GameState, _: u32, _: PlayerInput) -> Result<(), ClientError> {
+		println!("Ignoring Input from the server");
+		Ok(())
+	}
+
+	fn handle_player_state(&mut self, game_state: &mut GameState, last_sequence: u32, position: Vector3<f32>, _: Vector3<f32>) -> Result<(), ClientError> {
+		game_state.reconcile(last_sequence, position);
+		Ok(())
+	}
+
+	fn handle_world_snapshot(&mut self, game_state: &mut GameState, sequence: u32, server_time: f64, baseline: u32, entities: Vec<EntityDelta>) -> Result<(), ClientError> {
+		let state = match self.snapshots.decode(sequence, baseline, &entities) {
+			Some(s) => s,
+			None => {
+				// Not acknowledging it makes the server keep using a baseline we do have
+				println!("Ignoring snapshot {} with unknown baseline {}", sequence, baseline);
+				return Ok(());
 			}
-		} {}
+		};
+		if let Err(e) = self.socket.send(NetworkMessage::SnapshotAck { sequence: sequence }) {
+			return Err(e);
+		}
+
+		// Entities are spawned and removed by their own messages, the snapshot only moves the ones we have
+		for (uid, entity) in state {
+			game_state.receive_entity_state(uid, entity.position(), entity.rotation(), server_time);
+		}
+		Ok(())
 	}
-}
\ No newline at end of file
+
+	fn handle_snapshot_ack(&mut self, _: &mut GameState, _: u32) -> Result<(), ClientError> {
+		println!("Ignoring SnapshotAck from the server");
+		Ok(())
+	}
+
+	fn handle_session(&mut self, _: &mut GameState, token: String) -> Result<(), ClientError> {
+		self.session = Some(token);
+		Ok(())
+	}
+
+	fn handle_resume(&mut self, _: &mut GameState, _: String) -> Result<(), ClientError> {
+		println!("Ignoring Resume from the server");
+		Ok(())
+	}
+}
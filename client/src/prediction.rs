@@ -0,0 +1,46 @@
+use shared::{NetworkMessage, PlayerInput, apply_input};
+use vecmath::Vector3;
+
+/// Moves the player straight away instead of waiting for the server
+/// Every input is kept until the server acknowledges it, so when the server puts the player somewhere else
+/// we can start from the position of the server and apply the inputs it didn't handle yet
+pub struct Prediction {
+	next_sequence: u32,
+	// (sequence, input), oldest first
+	pending: Vec<(u32, PlayerInput)>,
+}
+
+impl Prediction {
+	pub fn new() -> Prediction {
+		Prediction {
+			next_sequence: 1,
+			pending: Vec::new(),
+		}
+	}
+
+	/// Apply the input to the player and return the message that tells the server about it
+	pub fn predict(&mut self, position: &mut Vector3<f32>, rotation: &mut Vector3<f32>, input: PlayerInput) -> NetworkMessage {
+		let sequence = self.next_sequence;
+		self.next_sequence += 1;
+		apply_input(position, rotation, &input);
+		self.pending.push((sequence, input));
+		NetworkMessage::Input { sequence: sequence, input: input }
+	}
+
+	/// Put the player where the server says it was after last_sequence, and replay everything the server didn't handle yet
+	/// The rotation is not replayed, the camera belongs to the player and the server only follows it
+	pub fn reconcile(&mut self, position: &mut Vector3<f32>, last_sequence: u32, server_position: Vector3<f32>) {
+		self.pending.retain(|p| p.0 > last_sequence);
+
+		let mut rotation = [0.0, 0.0, 0.0];
+		*position = server_position;
+		for &(_, ref input) in &self.pending {
+			apply_input(position, &mut rotation, input);
+		}
+	}
+
+	/// The amount of inputs the server didn't acknowledge yet
+	pub fn pending_count(&self) -> usize {
+		self.pending.len()
+	}
+}
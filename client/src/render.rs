@@ -4,6 +4,7 @@ use glium::backend::glutin_backend::{GlutinFacade, WinRef};
 use glium::glutin::{ WindowBuilder, CursorState };
 use std::f32::consts;
 use game_state::GameState;
+use settings::Settings;
 use vecmath::{ Vector3, Matrix4, vec3_dot, mat4_id };
 use model::Model;
 use std::io::Cursor;
@@ -15,6 +16,8 @@ pub struct DisplayData<'a> {
 	pub display: GlutinFacade,
 	pub program: Program,
 	pub perspective: Matrix4<f32>,
+	/// The vertical field of view in degrees that the perspective was made with
+	pub fov: f32,
 
 	// TODO: Move this to a seperate camera struct
 	pub camera_position: Vector3<f32>,
@@ -36,10 +39,14 @@ pub struct DisplayData<'a> {
 const ARIAL_FONT: &'static [u8] = include_bytes!("C:/Windows/Fonts/ARIAL.TTF");
 
 impl<'a> DisplayData<'a> {
-	pub fn new() -> Result<DisplayData<'a>, GameError> {
-		let display = try!(WindowBuilder::new()
-			.with_depth_buffer(24)
-			.build_glium());
+	pub fn new(settings: &Settings) -> Result<DisplayData<'a>, GameError> {
+		let mut builder = WindowBuilder::new()
+			.with_dimensions(settings.resolution.0, settings.resolution.1)
+			.with_depth_buffer(24);
+		if settings.vsync {
+			builder = builder.with_vsync();
+		}
+		let display = try!(builder.build_glium());
 
 		let vertex_shader_src = include_str!("../assets/shaders/default.vert");
 		let fragment_shader_src = include_str!("../assets/shaders/default.frag");
@@ -55,7 +62,7 @@ impl<'a> DisplayData<'a> {
 			let window: WinRef = try_get!(display.get_window(), "Could not get window");
 			let (width, height) = try_get!(window.get_inner_size_pixels(), "Could not get window pixel size");
 
-			DisplayData::get_perspective(width, height)
+			DisplayData::get_perspective(width, height, settings.fov)
 		};
 
 		// TODO: Do something with the light
@@ -77,6 +84,7 @@ impl<'a> DisplayData<'a> {
 			display: display,
 			program: program,
 			perspective: perspective,
+			fov: settings.fov,
 			light: light,
 			draw_parameters: params,
 			camera_position: [0.0, 0.0, -10.0],
@@ -97,13 +105,13 @@ impl<'a> DisplayData<'a> {
 	}
 
 	pub fn resize(&mut self, width: u32, height: u32) {
-		self.perspective = DisplayData::get_perspective(width, height);
+		self.perspective = DisplayData::get_perspective(width, height, self.fov);
 	}
 
-	fn get_perspective(width: u32, height: u32) -> Matrix4<f32> {
+	fn get_perspective(width: u32, height: u32, fov_degrees: f32) -> Matrix4<f32> {
 		let aspect_ratio = height as f32 / width as f32;
 
-		let fov: f32 = consts::PI / 3.0;
+		let fov: f32 = fov_degrees * consts::PI / 180.0;
 		let zfar = 1024.0;
 		let znear = 0.1;
 
@@ -138,6 +146,13 @@ impl<'a> DisplayData<'a> {
 			}
 		};
 
+		// The field of view can be changed in the settings while playing
+		if self.fov != game_state.settings.fov {
+			self.fov = game_state.settings.fov;
+			let (width, height) = try!(self.get_screen_dimensions());
+			self.resize(width, height);
+		}
+
 		if let Some(ref mut player) = game_state.player {
 			if let None = player.model {
 				// TODO: We should load the model when the player logs in
@@ -153,7 +168,7 @@ impl<'a> DisplayData<'a> {
 
 		// TODO: Make the camera follow the player
 		if let Some(ref player) = game_state.player {
-			self.camera_position = player.position;
+			self.camera_position = player.interpolated_position(game_state.render_alpha);
 			// TODO: Make the camera movable around the player
 			self.camera_position[2] -= 1f32;
 			self.camera_rotation = player.rotation;
@@ -0,0 +1,173 @@
+use input::{Action, Chord, InputMap, ACTIONS};
+use toml::{Parser, Table, Value};
+use shared::toml_value::{string, integer, float, float_between, positive, boolean};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use error::GameError;
+
+/// Where the settings are loaded from at startup and saved to on exit
+pub const SETTINGS_FILE: &'static str = "settings.toml";
+
+/// Everything the player can change about the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+	pub server_host: String,
+	pub server_port: u16,
+	/// Seconds other players are drawn in the past, so there is a snapshot on both sides to blend between
+	/// Higher values hide more lag, but show the others further behind where they really are
+	pub interpolation_delay: f32,
+	/// Radians the camera turns for every pixel the mouse moves
+	pub mouse_sensitivity: f32,
+	pub invert_y: bool,
+	/// The vertical field of view in degrees
+	pub fov: f32,
+	/// The size of the window when the game starts
+	pub resolution: (u32, u32),
+	pub vsync: bool,
+	pub input_map: InputMap,
+}
+
+impl Settings {
+	pub fn new() -> Settings {
+		Settings {
+			server_host: "localhost".to_string(),
+			server_port: 8080,
+			interpolation_delay: 0.1,
+			mouse_sensitivity: 0.005,
+			invert_y: false,
+			fov: 60.0,
+			resolution: (1024, 768),
+			vsync: true,
+			input_map: InputMap::new(),
+		}
+	}
+
+	/// Load the settings file, or the default settings if there is no file yet
+	/// Errors are Strings instead of GameErrors, because they are shown to the player and not logged
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Settings, String> {
+		let path = path.as_ref();
+		let mut text = String::new();
+		match File::open(path) {
+			Ok(mut file) => if let Err(e) = file.read_to_string(&mut text) {
+				return Err(format!("Could not read {}: {}", path.display(), e));
+			},
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Settings::new()),
+			Err(e) => return Err(format!("Could not open {}: {}", path.display(), e))
+		}
+		Settings::from_toml(&text).map_err(|e| format!("{}: {}", path.display(), e))
+	}
+
+	/// Write the settings to a temporary file first, so a crash while saving doesn't lose the old settings
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), GameError> {
+		let path = path.as_ref();
+		let temp_path = path.with_extension("toml.tmp");
+		{
+			let mut file = try!(File::create(&temp_path));
+			try!(file.write_all(self.to_toml().as_bytes()));
+			try!(file.sync_all());
+		}
+		try!(fs::rename(&temp_path, path));
+		Ok(())
+	}
+
+	/// Settings that are missing from the text keep their default value
+	pub fn from_toml(text: &str) -> Result<Settings, String> {
+		let mut parser = Parser::new(text);
+		let table = match parser.parse() {
+			Some(t) => t,
+			None => {
+				let error = &parser.errors[0];
+				let (line, column) = parser.to_linecol(error.lo);
+				return Err(format!("line {}, column {}: {}", line + 1, column + 1, error.desc));
+			}
+		};
+
+		// The try! of this crate makes GameErrors, so the errors are collected instead, which stops at the first one
+		let mut settings = Settings::new();
+		let applied: Result<(), String> = table.iter().map(|(section, value)| match *value {
+			Value::Table(ref values) => values.iter().map(|(key, value)| settings.apply(section, key, value)).collect(),
+			_ => Err(format!("Expected a [{}] section", section))
+		}).collect();
+		applied.map(|_| settings)
+	}
+
+	fn apply(&mut self, section: &str, key: &str, value: &Value) -> Result<(), String> {
+		let name = format!("{}.{}", section, key);
+		let name = name.as_str();
+		match (section, key) {
+			("network", "host") => string(name, value).map(|v| self.server_host = v),
+			("network", "port") => integer(name, value, 1, u16::max_value() as i64).map(|v| self.server_port = v as u16),
+			("network", "interpolation_delay") => match float(name, value) {
+				Ok(v) if v < 0.0 => Err(format!("{} can't be negative, not {}", name, v)),
+				Ok(v) => {
+					self.interpolation_delay = v as f32;
+					Ok(())
+				},
+				Err(e) => Err(e)
+			},
+			// The same limits as the settings screen
+			("mouse", "sensitivity") => positive(name, value).map(|v| self.mouse_sensitivity = v as f32),
+			("mouse", "invert_y") => boolean(name, value).map(|v| self.invert_y = v),
+			("display", "fov") => float_between(name, value, 30.0, 120.0).map(|v| self.fov = v as f32),
+			("display", "width") => integer(name, value, 1, u32::max_value() as i64).map(|v| self.resolution.0 = v as u32),
+			("display", "height") => integer(name, value, 1, u32::max_value() as i64).map(|v| self.resolution.1 = v as u32),
+			("display", "vsync") => boolean(name, value).map(|v| self.vsync = v),
+			("keys", action) => match Action::from_name(action) {
+				Some(action) => chords(name, value).map(|v| self.input_map.bind(action, v)),
+				None => {
+					println!("Ignoring unknown action {}", name);
+					Ok(())
+				}
+			},
+			_ => {
+				// The file is written back on exit, so an old or misspelled setting disappears instead of breaking the game
+				println!("Ignoring unknown setting {}", name);
+				Ok(())
+			}
+		}
+	}
+
+	pub fn to_toml(&self) -> String {
+		let mut network = Table::new();
+		network.insert("host".to_string(), Value::String(self.server_host.clone()));
+		network.insert("port".to_string(), Value::Integer(self.server_port as i64));
+		network.insert("interpolation_delay".to_string(), Value::Float(self.interpolation_delay as f64));
+
+		let mut mouse = Table::new();
+		mouse.insert("sensitivity".to_string(), Value::Float(self.mouse_sensitivity as f64));
+		mouse.insert("invert_y".to_string(), Value::Boolean(self.invert_y));
+
+		let mut display = Table::new();
+		display.insert("fov".to_string(), Value::Float(self.fov as f64));
+		display.insert("width".to_string(), Value::Integer(self.resolution.0 as i64));
+		display.insert("height".to_string(), Value::Integer(self.resolution.1 as i64));
+		display.insert("vsync".to_string(), Value::Boolean(self.vsync));
+
+		// Every action is written, so an action without chords stays unbound instead of getting its default back
+		let mut keys = Table::new();
+		for action in ACTIONS {
+			let chords = self.input_map.chords(*action).iter().map(|c| Value::String(c.name())).collect();
+			keys.insert(action.name().to_string(), Value::Array(chords));
+		}
+
+		let mut root = Table::new();
+		root.insert("network".to_string(), Value::Table(network));
+		root.insert("mouse".to_string(), Value::Table(mouse));
+		root.insert("display".to_string(), Value::Table(display));
+		root.insert("keys".to_string(), Value::Table(keys));
+		Value::Table(root).to_string()
+	}
+}
+
+// Either a single chord or a list of chords, like "W" or ["W", "Up"]
+fn chords(name: &str, value: &Value) -> Result<Vec<Chord>, String> {
+	let names: Result<Vec<String>, String> = match *value {
+		Value::String(ref s) => Ok(vec![s.clone()]),
+		Value::Array(ref values) => values.iter().map(|v| string(name, v)).collect(),
+		_ => Err(format!("{} should be a key or a list of keys, not {}", name, value.type_str()))
+	};
+	names.and_then(|names| names.iter().map(|chord| {
+		Chord::from_name(chord).ok_or_else(|| format!("{} contains a key that can't be bound: {}", name, chord))
+	}).collect())
+}
@@ -0,0 +1,92 @@
+use input::{Action, Chord, Input, InputMap, InputState};
+use glium::glutin::{VirtualKeyCode, MouseButton, ElementState};
+
+fn key(key: VirtualKeyCode) -> Input {
+	Input::Key(key)
+}
+
+#[test]
+fn default_bindings() {
+	let map = InputMap::new();
+	let mut state = InputState::new();
+	assert!(!map.is_active(Action::MoveForward, &state));
+
+	state.update(key(VirtualKeyCode::W), ElementState::Pressed);
+	state.update(Input::Mouse(MouseButton::Right), ElementState::Pressed);
+	assert!(map.is_active(Action::MoveForward, &state));
+	assert!(map.is_active(Action::Look, &state));
+	assert!(!map.is_active(Action::MoveBack, &state));
+
+	state.update(key(VirtualKeyCode::W), ElementState::Released);
+	assert!(!map.is_active(Action::MoveForward, &state));
+}
+
+#[test]
+fn triggered_only_in_the_first_frame() {
+	let map = InputMap::new();
+	let mut state = InputState::new();
+	state.update(key(VirtualKeyCode::Escape), ElementState::Pressed);
+	assert!(map.was_triggered(Action::Quit, &state));
+
+	state.reset();
+	assert!(map.is_active(Action::Quit, &state));
+	assert!(!map.was_triggered(Action::Quit, &state));
+
+	// Key repeat sends more presses, but that doesn't trigger the action again
+	state.update(key(VirtualKeyCode::Escape), ElementState::Pressed);
+	assert!(!map.was_triggered(Action::Quit, &state));
+}
+
+#[test]
+fn chords_need_every_input() {
+	let mut map = InputMap::new();
+	map.bind(Action::Quit, vec![Chord::new(vec![key(VirtualKeyCode::LControl), key(VirtualKeyCode::Q)])]);
+	let mut state = InputState::new();
+
+	state.update(key(VirtualKeyCode::Q), ElementState::Pressed);
+	assert!(!map.was_triggered(Action::Quit, &state));
+	state.update(key(VirtualKeyCode::LControl), ElementState::Pressed);
+	assert!(map.was_triggered(Action::Quit, &state));
+}
+
+#[test]
+fn chords_hide_the_actions_of_their_parts() {
+	let mut map = InputMap::new();
+	map.bind(Action::OpenChat, vec![Chord::new(vec![key(VirtualKeyCode::LControl), key(VirtualKeyCode::W)])]);
+	let mut state = InputState::new();
+
+	state.update(key(VirtualKeyCode::LControl), ElementState::Pressed);
+	state.update(key(VirtualKeyCode::W), ElementState::Pressed);
+	assert!(map.is_active(Action::OpenChat, &state));
+	assert!(!map.is_active(Action::MoveForward, &state));
+
+	state.update(key(VirtualKeyCode::LControl), ElementState::Released);
+	assert!(map.is_active(Action::MoveForward, &state));
+}
+
+#[test]
+fn multiple_chords_per_action() {
+	let mut map = InputMap::new();
+	map.bind(Action::MoveForward, vec![Chord::single(key(VirtualKeyCode::W)), Chord::single(key(VirtualKeyCode::Up))]);
+	let mut state = InputState::new();
+	state.update(key(VirtualKeyCode::Up), ElementState::Pressed);
+	assert!(map.is_active(Action::MoveForward, &state));
+}
+
+#[test]
+fn clear_releases_everything() {
+	let map = InputMap::new();
+	let mut state = InputState::new();
+	state.update(key(VirtualKeyCode::W), ElementState::Pressed);
+	state.clear();
+	assert!(!map.is_active(Action::MoveForward, &state));
+}
+
+#[test]
+fn input_names() {
+	assert_eq!(Input::from_name(&key(VirtualKeyCode::LShift).name()), Some(key(VirtualKeyCode::LShift)));
+	assert_eq!(Input::from_name("escape"), Some(key(VirtualKeyCode::Escape)));
+	assert_eq!(Input::from_name("MouseRight"), Some(Input::Mouse(MouseButton::Right)));
+	assert_eq!(Input::from_name(""), None);
+	assert_eq!(Chord::from_name("LControl + Q").map(|c| c.name()), Some("LControl+Q".to_string()));
+}
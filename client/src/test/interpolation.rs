@@ -0,0 +1,119 @@
+use interpolation::{Snapshot, SnapshotBuffer, MAX_EXTRAPOLATION, MAX_SNAPSHOTS, angle_difference};
+use std::f32::consts::PI;
+
+fn snapshot(time: f64, x: f32, yaw: f32) -> Snapshot {
+	Snapshot {
+		time: time,
+		position: [x, 0.0, 0.0],
+		rotation: [0.0, yaw, 0.0],
+	}
+}
+
+fn close(a: f32, b: f32) -> bool {
+	(a - b).abs() < 0.0001
+}
+
+#[test]
+fn empty_buffer_has_no_sample() {
+	assert_eq!(SnapshotBuffer::new().sample(1.0), None);
+}
+
+#[test]
+fn samples_between_snapshots_are_blended() {
+	let mut buffer = SnapshotBuffer::new();
+	buffer.push(snapshot(1.0, 0.0, 0.0));
+	buffer.push(snapshot(2.0, 10.0, 1.0));
+
+	let (position, rotation) = buffer.sample(1.25).unwrap();
+	assert!(close(position[0], 2.5));
+	assert!(close(rotation[1], 0.25));
+	assert_eq!(buffer.sample(2.0), Some(([10.0, 0.0, 0.0], [0.0, 1.0, 0.0])));
+}
+
+#[test]
+fn samples_before_the_first_snapshot_use_the_first() {
+	let mut buffer = SnapshotBuffer::new();
+	buffer.push(snapshot(1.0, 3.0, 0.5));
+	buffer.push(snapshot(2.0, 10.0, 1.0));
+	assert_eq!(buffer.sample(0.0), Some(([3.0, 0.0, 0.0], [0.0, 0.5, 0.0])));
+}
+
+#[test]
+fn late_samples_are_extrapolated_for_a_while() {
+	let mut buffer = SnapshotBuffer::new();
+	buffer.push(snapshot(1.0, 0.0, 0.0));
+	buffer.push(snapshot(2.0, 1.0, 0.0));
+
+	let (position, _) = buffer.sample(2.1).unwrap();
+	assert!(close(position[0], 1.1));
+
+	// After MAX_EXTRAPOLATION the entity stops
+	let (limit, _) = buffer.sample(2.0 + MAX_EXTRAPOLATION).unwrap();
+	let (later, _) = buffer.sample(100.0).unwrap();
+	assert!(close(limit[0], 1.0 + MAX_EXTRAPOLATION as f32));
+	assert_eq!(limit, later);
+}
+
+#[test]
+fn a_single_snapshot_is_not_extrapolated() {
+	let mut buffer = SnapshotBuffer::new();
+	buffer.push(snapshot(1.0, 4.0, 0.0));
+	assert_eq!(buffer.sample(1.5), Some(([4.0, 0.0, 0.0], [0.0, 0.0, 0.0])));
+}
+
+#[test]
+fn snapshots_are_kept_in_order() {
+	let mut buffer = SnapshotBuffer::new();
+	buffer.push(snapshot(2.0, 10.0, 0.0));
+	buffer.push(snapshot(1.0, 0.0, 0.0));
+	buffer.push(snapshot(2.0, 20.0, 0.0));
+	assert_eq!(buffer.len(), 2);
+
+	let (position, _) = buffer.sample(1.5).unwrap();
+	assert!(close(position[0], 10.0));
+}
+
+#[test]
+fn discarding_keeps_the_snapshot_before_the_time() {
+	let mut buffer = SnapshotBuffer::new();
+	for i in 0..5 {
+		buffer.push(snapshot(i as f64, i as f32, 0.0));
+	}
+	buffer.discard_before(2.5);
+	assert_eq!(buffer.len(), 3);
+
+	let (position, _) = buffer.sample(2.5).unwrap();
+	assert!(close(position[0], 2.5));
+
+	buffer.discard_before(0.0);
+	assert_eq!(buffer.len(), 3);
+}
+
+#[test]
+fn buffer_size_is_limited() {
+	let mut buffer = SnapshotBuffer::new();
+	for i in 0..(MAX_SNAPSHOTS + 10) {
+		buffer.push(snapshot(i as f64, i as f32, 0.0));
+	}
+	assert_eq!(buffer.len(), MAX_SNAPSHOTS);
+	// The oldest ones were dropped
+	assert_eq!(buffer.sample(0.0), Some(([10.0, 0.0, 0.0], [0.0, 0.0, 0.0])));
+}
+
+#[test]
+fn rotation_takes_the_short_way_around() {
+	let mut buffer = SnapshotBuffer::new();
+	buffer.push(snapshot(0.0, 0.0, PI - 0.1));
+	buffer.push(snapshot(1.0, 0.0, -PI + 0.1));
+
+	let (_, rotation) = buffer.sample(0.5).unwrap();
+	assert!(close(rotation[1], PI));
+}
+
+#[test]
+fn angle_differences() {
+	assert!(close(angle_difference(0.0, 1.0), 1.0));
+	assert!(close(angle_difference(1.0, 0.0), -1.0));
+	assert!(close(angle_difference(0.1, 2.0 * PI - 0.1), -0.2));
+	assert!(close(angle_difference(0.0, 4.0 * PI + 0.5), 0.5));
+}
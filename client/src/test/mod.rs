@@ -1,2 +1,6 @@
+pub mod input;
+pub mod interpolation;
 pub mod network;
-pub mod world;
\ No newline at end of file
+pub mod prediction;
+pub mod settings;
+pub mod world;
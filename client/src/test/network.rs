@@ -1,3 +1,29 @@
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+use network::Network;
+use game_state::GameState;
+use settings::Settings;
+use shared::NetworkMessage;
+
+// A Network that is connected to the returned listener, which never reads anything
+fn connected_network() -> (Network, TcpListener) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let port = listener.local_addr().unwrap().port();
+	let mut network = Network::new("127.0.0.1", port);
+	let mut game_state = GameState::new(Settings::new());
+	for _ in 0..100 {
+		network.update(&mut game_state);
+		if network.is_connected() {
+			break;
+		}
+		thread::sleep(Duration::from_millis(10));
+	}
+	assert!(network.is_connected());
+	(network, listener)
+}
+
 #[test]
 fn test_network() {
 	// TODO:
@@ -11,4 +37,19 @@ fn test_network() {
 	// assert!(world.entities[0].rotation.x == 4.0);
 	// assert!(world.entities[0].rotation.y == 5.0);
 	// assert!(world.entities[0].rotation.z == 6.0);
-}
\ No newline at end of file
+}
+
+#[test]
+fn send_errors_disconnect_instead_of_failing() {
+	let (mut network, _listener) = connected_network();
+	// The server doesn't read, so the send buffer fills up eventually
+	let password: String = (0..64 * 1024).map(|_| 'x').collect();
+	for _ in 0..1000 {
+		let message = NetworkMessage::Login { username: "alice".to_string(), password: password.clone() };
+		assert!(network.send(message).is_ok());
+		if !network.is_connected() {
+			return;
+		}
+	}
+	panic!("The send buffer never filled up");
+}
@@ -0,0 +1,85 @@
+use prediction::Prediction;
+use shared::{NetworkMessage, PlayerInput, apply_input};
+
+fn forward() -> PlayerInput {
+	PlayerInput { forward: 1, strafe: 0, rotation: [0.0, 0.0, 0.0] }
+}
+
+fn right() -> PlayerInput {
+	PlayerInput { forward: 0, strafe: 1, rotation: [0.0, 0.0, 0.0] }
+}
+
+// What the server does with the same inputs
+fn simulate(start: [f32; 3], inputs: &[PlayerInput]) -> [f32; 3] {
+	let mut position = start;
+	let mut rotation = [0.0, 0.0, 0.0];
+	for input in inputs {
+		apply_input(&mut position, &mut rotation, input);
+	}
+	position
+}
+
+#[test]
+fn predict_sends_increasing_sequences() {
+	let mut prediction = Prediction::new();
+	let mut position = [0.0, 0.0, 0.0];
+	let mut rotation = [0.0, 0.0, 0.0];
+	let first = prediction.predict(&mut position, &mut rotation, forward());
+	let second = prediction.predict(&mut position, &mut rotation, forward());
+	assert_eq!(first, NetworkMessage::Input { sequence: 1, input: forward() });
+	assert_eq!(second, NetworkMessage::Input { sequence: 2, input: forward() });
+	assert_eq!(position, simulate([0.0, 0.0, 0.0], &[forward(), forward()]));
+	assert_eq!(prediction.pending_count(), 2);
+}
+
+#[test]
+fn agreeing_server_changes_nothing() {
+	let mut prediction = Prediction::new();
+	let mut position = [0.0, 0.0, 0.0];
+	let mut rotation = [0.0, 0.0, 0.0];
+	for _ in 0..5 {
+		prediction.predict(&mut position, &mut rotation, forward());
+	}
+	let predicted = position;
+
+	// The server handled the first 3 inputs the same way we did
+	prediction.reconcile(&mut position, 3, simulate([0.0, 0.0, 0.0], &[forward(), forward(), forward()]));
+	assert_eq!(position, predicted);
+	assert_eq!(prediction.pending_count(), 2);
+}
+
+#[test]
+fn correction_replays_unacknowledged_inputs() {
+	let mut prediction = Prediction::new();
+	let mut position = [0.0, 0.0, 0.0];
+	let mut rotation = [0.0, 0.0, 0.0];
+	let inputs = [forward(), forward(), right(), right()];
+	for input in &inputs {
+		prediction.predict(&mut position, &mut rotation, *input);
+	}
+
+	// The server had us somewhere else, for example because we bumped into something
+	let server_position = [10.0, 0.0, 10.0];
+	prediction.reconcile(&mut position, 2, server_position);
+	assert_eq!(position, simulate(server_position, &[right(), right()]));
+	assert_eq!(prediction.pending_count(), 2);
+
+	// Once everything is acknowledged we end up exactly where the server says
+	prediction.reconcile(&mut position, 4, [1.0, 2.0, 3.0]);
+	assert_eq!(position, [1.0, 2.0, 3.0]);
+	assert_eq!(prediction.pending_count(), 0);
+}
+
+#[test]
+fn old_acknowledgements_are_harmless() {
+	let mut prediction = Prediction::new();
+	let mut position = [0.0, 0.0, 0.0];
+	let mut rotation = [0.0, 0.0, 0.0];
+	prediction.predict(&mut position, &mut rotation, forward());
+	prediction.predict(&mut position, &mut rotation, forward());
+	prediction.reconcile(&mut position, 2, position);
+
+	let current = position;
+	prediction.reconcile(&mut position, 1, current);
+	assert_eq!(position, current);
+}
@@ -0,0 +1,77 @@
+use settings::Settings;
+use input::{Action, Chord, Input};
+use glium::glutin::{VirtualKeyCode, MouseButton};
+
+#[test]
+fn settings_roundtrip() {
+	let mut settings = Settings::new();
+	settings.server_host = "example.com".to_string();
+	settings.server_port = 1234;
+	settings.mouse_sensitivity = 0.01;
+	settings.invert_y = true;
+	settings.fov = 90.0;
+	settings.resolution = (1920, 1080);
+	settings.vsync = false;
+	settings.input_map.bind(Action::MoveForward, vec![
+		Chord::single(Input::Key(VirtualKeyCode::Up)),
+		Chord::single(Input::Mouse(MouseButton::Middle)),
+	]);
+	settings.input_map.bind(Action::Quit, vec![Chord::new(vec![Input::Key(VirtualKeyCode::LControl), Input::Key(VirtualKeyCode::Q)])]);
+	settings.input_map.bind(Action::OpenChat, Vec::new());
+
+	assert_eq!(Settings::from_toml(&settings.to_toml()), Ok(settings));
+}
+
+#[test]
+fn missing_settings_keep_their_default() {
+	let settings = Settings::from_toml("[mouse]\ninvert_y = true").unwrap();
+	let mut expected = Settings::new();
+	expected.invert_y = true;
+	assert_eq!(settings, expected);
+	assert_eq!(Settings::from_toml(""), Ok(Settings::new()));
+}
+
+#[test]
+fn unknown_settings_are_ignored() {
+	assert_eq!(Settings::from_toml("[mouse]\nacceleration = 2"), Ok(Settings::new()));
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+	assert!(Settings::from_toml("[network]\nport = 0").is_err());
+	assert!(Settings::from_toml("[network]\nport = \"8080\"").is_err());
+	assert!(Settings::from_toml("[mouse]\ninvert_y = 1").is_err());
+	assert!(Settings::from_toml("[keys]\nquit = \"Hyperspace\"").is_err());
+	assert!(Settings::from_toml("[keys]\nquit = [\"LControl+Hyperspace\"]").is_err());
+	assert!(Settings::from_toml("[keys]\nquit = 1").is_err());
+	assert!(Settings::from_toml("[display\nfov = 60").is_err());
+}
+
+#[test]
+fn key_bindings_from_toml() {
+	let settings = Settings::from_toml("[keys]\nmove_forward = [\"w\", \"Up\"]\nquit = \"LControl+Q\"\nlook = \"MouseLeft\"").unwrap();
+	let map = &settings.input_map;
+	assert_eq!(map.chords(Action::MoveForward), vec![
+		&Chord::single(Input::Key(VirtualKeyCode::W)),
+		&Chord::single(Input::Key(VirtualKeyCode::Up)),
+	]);
+	assert_eq!(map.chords(Action::Quit), vec![&Chord::new(vec![Input::Key(VirtualKeyCode::LControl), Input::Key(VirtualKeyCode::Q)])]);
+	assert_eq!(map.chords(Action::Look), vec![&Chord::single(Input::Mouse(MouseButton::Left))]);
+	// Actions that are not in the file keep their default
+	assert_eq!(map.chords(Action::Jump), vec![&Chord::single(Input::Key(VirtualKeyCode::Space))]);
+}
+
+#[test]
+fn interpolation_delay_from_toml() {
+	assert_eq!(Settings::from_toml("[network]\ninterpolation_delay = 0.25").unwrap().interpolation_delay, 0.25);
+	assert!(Settings::from_toml("[network]\ninterpolation_delay = -1").is_err());
+}
+
+#[test]
+fn fov_and_sensitivity_have_the_limits_of_the_settings_screen() {
+	assert_eq!(Settings::from_toml("[display]\nfov = 120").unwrap().fov, 120.0);
+	assert!(Settings::from_toml("[display]\nfov = 10").is_err());
+	assert!(Settings::from_toml("[display]\nfov = 170.5").is_err());
+	assert!(Settings::from_toml("[mouse]\nsensitivity = 0").is_err());
+	assert!(Settings::from_toml("[mouse]\nsensitivity = -0.01").is_err());
+}
@@ -0,0 +1,54 @@
+use ui::utils::{ Dimension, EventResult, UIAction };
+use ui::render_state::UIRender;
+use handler::texture::Texture;
+use ui::traits::UIElement;
+use glium::glutin::Event;
+
+pub struct Button {
+	pub text: String,
+	pub action: UIAction,
+}
+
+impl Button {
+	pub fn new<T: ToString>(text: T, action: UIAction) -> Button {
+		Button {
+			text: text.to_string(),
+			action: action,
+		}
+	}
+}
+
+impl UIElement for Button {
+	fn get_initial_position(&self, _: &Dimension) -> (u32, u32){
+		(50, 50)
+	}
+	fn get_desired_size(&self, _: &Dimension) -> (u32, u32){
+		(120, 30)
+	}
+
+	fn draw(&self, render: &mut UIRender) {
+		render.set_background(Texture::PanelBackground);
+		render.draw_text_at(&self.text, 10, 0);
+	}
+
+	fn update(&mut self, _: f32) {
+	}
+
+	fn handle_event(&mut self, _: &Event) -> EventResult {
+		EventResult::Unhandled
+	}
+
+	fn click(&mut self) -> EventResult {
+		EventResult::Action(self.action)
+	}
+	fn set_focus(&mut self) -> bool {
+		false
+	}
+
+	fn get_text(&self) -> Option<String> {
+		Some(self.text.clone())
+	}
+	fn set_text(&mut self, text: String) {
+		self.text = text;
+	}
+}
@@ -0,0 +1,50 @@
+use ui::utils::{ Dimension, EventResult };
+use ui::render_state::UIRender;
+use ui::traits::UIElement;
+use glium::glutin::Event;
+
+pub struct Label {
+	pub text: String,
+}
+
+impl Label {
+	pub fn new<T: ToString>(text: T) -> Label {
+		Label {
+			text: text.to_string(),
+		}
+	}
+}
+
+impl UIElement for Label {
+	fn get_initial_position(&self, _: &Dimension) -> (u32, u32){
+		(50, 50)
+	}
+	fn get_desired_size(&self, _: &Dimension) -> (u32, u32){
+		(400, 30)
+	}
+
+	fn draw(&self, render: &mut UIRender) {
+		render.draw_text_at(&self.text, 10, 0);
+	}
+
+	fn update(&mut self, _: f32) {
+	}
+
+	fn handle_event(&mut self, _: &Event) -> EventResult {
+		EventResult::Unhandled
+	}
+
+	fn click(&mut self) -> EventResult {
+		EventResult::Unhandled
+	}
+	fn set_focus(&mut self) -> bool {
+		false
+	}
+
+	fn get_text(&self) -> Option<String> {
+		Some(self.text.clone())
+	}
+	fn set_text(&mut self, text: String) {
+		self.text = text;
+	}
+}
@@ -1,5 +1,9 @@
 mod panel;
 mod textbox;
+mod label;
+mod button;
 
 pub use self::panel::*;
-pub use self::textbox::*;
\ No newline at end of file
+pub use self::textbox::*;
+pub use self::label::*;
+pub use self::button::*;
\ No newline at end of file
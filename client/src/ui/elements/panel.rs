@@ -40,5 +40,11 @@ impl UIElement for Panel {
 	fn set_focus(&mut self) -> bool {
 		false
 	}
+
+	fn get_text(&self) -> Option<String> {
+		None
+	}
+	fn set_text(&mut self, _: String) {
+	}
 }
 
@@ -1,5 +1,5 @@
 use glium::glutin::{ ElementState, Event, VirtualKeyCode };
-use ui::utils::{ Dimension, EventResult };
+use ui::utils::{ Dimension, EventResult, UIAction };
 use ui::render_state::UIRender;
 use handler::texture::Texture;
 use ui::traits::UIElement;
@@ -10,6 +10,8 @@ pub struct Textbox {
 	pub text: String,
 	pub is_password: bool,
 	pub has_focus: bool,
+	/// The action that is triggered when enter is pressed in this textbox
+	pub submit_action: Option<UIAction>,
 
 	show_cursor: bool,
 	cursor_time: f32,
@@ -21,6 +23,7 @@ impl Textbox {
 			text: String::new(),
 			is_password: false,
 			has_focus: false,
+			submit_action: None,
 
 			show_cursor: true,
 			cursor_time: 0f32
@@ -41,6 +44,13 @@ impl UIElement for Textbox {
 		true
 	}
 
+	fn get_text(&self) -> Option<String> {
+		Some(self.text.clone())
+	}
+	fn set_text(&mut self, text: String) {
+		self.text = text;
+	}
+
 	fn draw(&self, render: &mut UIRender) {
 		let mut text_to_draw = self.text.clone();
 		if self.has_focus && self.show_cursor { text_to_draw.push('|'); }
@@ -78,6 +88,12 @@ impl UIElement for Textbox {
 				self.has_focus = false;
 				return EventResult::Handled;
 			}
+			if let Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Return)) = *ev {
+				if let Some(action) = self.submit_action {
+					return EventResult::Action(action);
+				}
+				return EventResult::Handled;
+			}
 			if let Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Tab)) = *ev {
 				self.has_focus = false;
 				return EventResult::SelectNext;
@@ -12,11 +12,23 @@ pub use ui::wrapper::*;
 use glium::Frame;
 use glium::glutin::{ Event, ElementState };
 use render::DisplayData;
-use ui::elements::{ Panel, Textbox };
+use ui::elements::{ Panel, Textbox, Label, Button };
 use error;
 
+// Add an element to a panel at the given position, with a name so it can be found later
+fn add_child<T: UIElement + 'static>(display: &DisplayData, panel: &mut UIWrapper, element: T, name: &'static str, position: (u32, u32)) -> Result<(), error::GameError> {
+	let mut wrapper = try!(UIWrapper::new(display, element, &Dimension::from_uielement(panel)));
+	wrapper.name = name;
+	wrapper.position = position;
+	try!(wrapper.resize(display, &Dimension::from_uielement(panel)));
+	panel.children.push(wrapper);
+	Ok(())
+}
+
 pub struct UI {
 	pub elements: Vec<UIWrapper>,
+	pub view: UIView,
+	actions: Vec<UIAction>,
 
 	mouse_x: u32,
 	mouse_y: u32
@@ -26,12 +38,17 @@ impl UI {
 	pub fn new() -> UI {
 		UI {
 			elements: Vec::new(),
+			view: UIView::None,
+			actions: Vec::new(),
 			mouse_x: 0,
 			mouse_y: 0
 		}
 	}
 
 	pub fn load(&mut self, display: &DisplayData, view: UIView) -> Result<(), error::GameError> {
+		self.elements.clear();
+		self.view = view;
+
 		if let UIView::Login = view {
 			let size = try!(display.get_screen_dimensions());
 			let mut panel = try!(UIWrapper::new(display, Panel::new(), &Dimension { x: 0, y: 0, width: size.0, height: size.1 }));
@@ -40,21 +57,65 @@ impl UI {
 			let mut password_textbox = Textbox::new();
 
 			username_textbox.has_focus = true;
+			username_textbox.submit_action = Some(UIAction::Login);
 			password_textbox.is_password = true;
+			password_textbox.submit_action = Some(UIAction::Login);
 
 			let mut username_textbox = try!(UIWrapper::new(display, username_textbox, &Dimension::from_uielement(&panel)));
 			let mut password_textbox = try!(UIWrapper::new(display, password_textbox, &Dimension::from_uielement(&panel)));
+			let mut login_button = try!(UIWrapper::new(display, Button::new("Login", UIAction::Login), &Dimension::from_uielement(&panel)));
+			let mut register_button = try!(UIWrapper::new(display, Button::new("Register", UIAction::Register), &Dimension::from_uielement(&panel)));
+			let mut status_label = try!(UIWrapper::new(display, Label::new(""), &Dimension::from_uielement(&panel)));
 
+			username_textbox.name = "username";
 			username_textbox.position = (0, 0);
 			username_textbox.size = (100, 100);
+			password_textbox.name = "password";
 			password_textbox.position = (0, 100);
 			password_textbox.size = (100, 100);
+			login_button.position = (220, 0);
+			register_button.position = (220, 100);
+			status_label.name = "status";
+			status_label.position = (0, 150);
 
 			try!(username_textbox.resize(display, &Dimension::from_uielement(&panel)));
 			try!(password_textbox.resize(display, &Dimension::from_uielement(&panel)));
+			try!(login_button.resize(display, &Dimension::from_uielement(&panel)));
+			try!(register_button.resize(display, &Dimension::from_uielement(&panel)));
+			try!(status_label.resize(display, &Dimension::from_uielement(&panel)));
 
 			panel.children.push(username_textbox);
 			panel.children.push(password_textbox);
+			panel.children.push(login_button);
+			panel.children.push(register_button);
+			panel.children.push(status_label);
+
+			self.elements.push(panel);
+		}
+
+		if let UIView::Settings = view {
+			// The values are filled in by whoever opens the settings, the UI doesn't know about them
+			let size = try!(display.get_screen_dimensions());
+			let mut panel = try!(UIWrapper::new(display, Panel::new(), &Dimension { x: 0, y: 0, width: size.0, height: size.1 }));
+
+			let mut server_textbox = Textbox::new();
+			server_textbox.has_focus = true;
+			server_textbox.submit_action = Some(UIAction::SaveSettings);
+			let mut sensitivity_textbox = Textbox::new();
+			sensitivity_textbox.submit_action = Some(UIAction::SaveSettings);
+			let mut fov_textbox = Textbox::new();
+			fov_textbox.submit_action = Some(UIAction::SaveSettings);
+
+			try!(add_child(display, &mut panel, Label::new("Server"), "", (0, 0)));
+			try!(add_child(display, &mut panel, server_textbox, "server", (200, 0)));
+			try!(add_child(display, &mut panel, Label::new("Mouse sensitivity"), "", (0, 50)));
+			try!(add_child(display, &mut panel, sensitivity_textbox, "sensitivity", (200, 50)));
+			try!(add_child(display, &mut panel, Label::new("Field of view"), "", (0, 100)));
+			try!(add_child(display, &mut panel, fov_textbox, "fov", (200, 100)));
+			try!(add_child(display, &mut panel, Button::new("", UIAction::ToggleInvertY), "invert_y", (0, 150)));
+			try!(add_child(display, &mut panel, Button::new("Save", UIAction::SaveSettings), "", (0, 200)));
+			try!(add_child(display, &mut panel, Button::new("Close", UIAction::CloseSettings), "", (200, 200)));
+			try!(add_child(display, &mut panel, Label::new(""), "settings_status", (0, 250)));
 
 			self.elements.push(panel);
 		}
@@ -82,18 +143,20 @@ impl UI {
 		}
 
 		for element in &mut self.elements {
-			let result = element.handle_event(event);
-			if let EventResult::Unhandled = result {
-				continue;
+			match element.handle_event(event) {
+				EventResult::Unhandled => continue,
+				EventResult::Action(action) => self.actions.push(action),
+				_ => {}
 			}
 			return true;
 		}
 
 		if let Event::MouseInput(ElementState::Pressed, _) = *event {
 			for element in &mut self.elements {
-				let result = element.click(self.mouse_x,  self.mouse_y);
-				if let EventResult::Unhandled = result {
-					continue;
+				match element.click(self.mouse_x,  self.mouse_y) {
+					EventResult::Unhandled => continue,
+					EventResult::Action(action) => self.actions.push(action),
+					_ => {}
 				}
 				return true;
 			}
@@ -101,6 +164,31 @@ impl UI {
 
 		false
 	}
+	/// Take the actions the user triggered since the last call
+	pub fn take_actions(&mut self) -> Vec<UIAction> {
+		::std::mem::replace(&mut self.actions, Vec::new())
+	}
+
+	/// Get the text of the element with the given name
+	pub fn get_text(&self, name: &str) -> Option<String> {
+		for element in &self.elements {
+			if let Some(found) = element.find(name) {
+				return found.element.get_text();
+			}
+		}
+		None
+	}
+
+	/// Set the text of the element with the given name
+	pub fn set_text<T: ToString>(&mut self, name: &str, text: T) {
+		for element in &mut self.elements {
+			if let Some(found) = element.find_mut(name) {
+				found.element.set_text(text.to_string());
+				return;
+			}
+		}
+	}
+
 	pub fn update(&mut self, delta_time: f32) {
 		for element in &mut self.elements {
 			element.update(delta_time);
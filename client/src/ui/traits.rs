@@ -10,5 +10,6 @@ pub trait UIElement {
 	fn handle_event(&mut self, ev: &Event) -> EventResult;
 	fn click(&mut self) -> EventResult;
 	fn set_focus(&mut self) -> bool;
-
+	fn get_text(&self) -> Option<String>;
+	fn set_text(&mut self, text: String);
 }
@@ -28,7 +28,18 @@ pub enum RenderCommand {
 pub enum EventResult {
 	Unhandled,
 	Handled,
-	SelectNext
+	SelectNext,
+	Action(UIAction),
+}
+
+/// Something the user asked for through the UI, that the game has to act on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UIAction {
+	Login,
+	Register,
+	ToggleInvertY,
+	SaveSettings,
+	CloseSettings,
 }
 
 // TODO: Move this to general render data
@@ -39,7 +50,9 @@ pub struct Vertex2D {
 }
 implement_vertex!(Vertex2D, position, tex_coords);
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum UIView {
 	Login,
+	Settings,
 	None
 }
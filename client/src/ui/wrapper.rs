@@ -12,6 +12,8 @@ use error;
 
 pub struct UIWrapper {
 	pub element: Box<UIElement>,
+	/// Used to find this element with find and find_mut, empty if nobody needs to find it
+	pub name: &'static str,
 	pub children: Vec<UIWrapper>,
 	pub position: (u32, u32),
 	pub size: (u32, u32),
@@ -52,6 +54,7 @@ impl UIWrapper {
 
 		let mut wrapper = UIWrapper {
 			element: Box::new(inner),
+			name: "",
 			children: Vec::new(),
 			position: position,
 			size: (0, 0),
@@ -151,6 +154,30 @@ impl UIWrapper {
 		Ok(())
 	}
 
+	pub fn find(&self, name: &str) -> Option<&UIWrapper> {
+		if self.name == name {
+			return Some(self);
+		}
+		for child in &self.children {
+			if let Some(found) = child.find(name) {
+				return Some(found);
+			}
+		}
+		None
+	}
+
+	pub fn find_mut(&mut self, name: &str) -> Option<&mut UIWrapper> {
+		if self.name == name {
+			return Some(self);
+		}
+		for child in &mut self.children {
+			if let Some(found) = child.find_mut(name) {
+				return Some(found);
+			}
+		}
+		None
+	}
+
 	pub fn update(&mut self, delta_time: f32) {
 		self.element.update(delta_time);
 		for child in &mut self.children {
@@ -191,7 +218,8 @@ impl UIWrapper {
 								self.children[i+1].element.set_focus();
 							}
 							return EventResult::Handled;
-						}
+						},
+						EventResult::Action(action) => return EventResult::Action(action),
 					}
 				}
 				EventResult::Unhandled
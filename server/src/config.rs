@@ -0,0 +1,332 @@
+use storage::StorageBackend;
+use shared::{DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_OUTGOING_SIZE, MESSAGE_NAMES};
+use shared::toml_value::{string, integer, float, positive};
+use network::{DEFAULT_MAX_CONNECTIONS, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_MISSED_PINGS};
+use world::DEFAULT_MAX_PLAYERS;
+use interest::DEFAULT_INTEREST_RADIUS;
+use movement::{DEFAULT_MOVEMENT_TOLERANCE, DEFAULT_VIOLATION_BUDGET};
+use session::DEFAULT_SESSION_GRACE_PERIOD;
+use rate_limit::RateLimits;
+use log::LevelFilter;
+use toml::{Parser, Table, Value};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+/// The file that is read when no --config is given, it is fine if this one doesn't exist
+pub const DEFAULT_CONFIG_FILE: &'static str = "server.toml";
+
+pub const USAGE: &'static str = "Usage: server [options]
+
+Options:
+    --config <file>          The configuration file to read (default: server.toml)
+    --bind <address>         The address to listen on
+    --port <port>            The port to listen on
+    --tick-rate <ups>        How often the network is handled per second
+    --ping-interval <secs>   How often the clients are pinged
+    --max-players <amount>   The amount of players that can be logged in at the same time
+    --max-connections <n>    The amount of connections, at least the max players
+    --interest-radius <dist> How far away players can see each other
+    --save-path <path>       The directory the world and users are saved in
+    --storage <backend>      file, sqlite or memory
+    --log-level <level>      off, error, warn, info, debug or trace
+    --help                   Show this message";
+
+/// Everything about the server that can be changed without recompiling
+/// Values come from the defaults, then the configuration file and then the command line, each overriding the previous
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+	pub bind_address: String,
+	pub port: u16,
+	/// How often per second the server handles the network
+	/// The world itself always moves at shared::SIMULATION_RATE
+	pub tick_rate: u32,
+	/// In seconds
+	pub ping_interval: f64,
+	pub max_players: usize,
+	pub max_frame_size: usize,
+	pub max_outgoing_size: usize,
+	/// The amount of connections, including those that didn't log in yet
+	pub max_connections: usize,
+	/// Seconds a new connection gets to finish the handshake
+	pub handshake_timeout: f64,
+	/// Clients that don't answer this many pings in a row are disconnected
+	pub max_missed_pings: u32,
+	/// The directory for the file storage, or the directory of the database for the SQLite storage
+	pub save_path: PathBuf,
+	/// "file", "sqlite" or "memory"
+	pub storage: String,
+	/// In seconds
+	pub autosave_interval: f64,
+	/// Players only hear about the players within this distance
+	pub interest_radius: f32,
+	/// Seconds a client's inputs may run ahead of the server before they count as speeding
+	pub movement_tolerance: f64,
+	/// The amount of illegal moves within a minute that gets a client kicked
+	pub violation_budget: usize,
+	/// Seconds a disconnected player waits in the world for its client to reconnect, 0 logs it out right away
+	pub session_grace_period: f64,
+	pub rate_limits: RateLimits,
+	pub log_level: LevelFilter,
+}
+
+impl Config {
+	pub fn new() -> Config {
+		Config {
+			bind_address: "localhost".to_string(),
+			port: 8080,
+			tick_rate: 50,
+			ping_interval: 1.0,
+			max_players: DEFAULT_MAX_PLAYERS,
+			max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+			max_outgoing_size: DEFAULT_MAX_OUTGOING_SIZE,
+			max_connections: DEFAULT_MAX_CONNECTIONS,
+			handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+			max_missed_pings: DEFAULT_MAX_MISSED_PINGS,
+			save_path: PathBuf::from("."),
+			storage: "file".to_string(),
+			autosave_interval: 60.0,
+			interest_radius: DEFAULT_INTEREST_RADIUS,
+			movement_tolerance: DEFAULT_MOVEMENT_TOLERANCE,
+			violation_budget: DEFAULT_VIOLATION_BUDGET,
+			session_grace_period: DEFAULT_SESSION_GRACE_PERIOD,
+			rate_limits: RateLimits::new(),
+			log_level: LevelFilter::Info,
+		}
+	}
+
+	/// Build the configuration from the command line arguments, without the program name
+	/// Returns Ok(None) if the user only asked for the usage
+	pub fn from_args(args: &[String]) -> Result<Option<Config>, String> {
+		if args.iter().any(|a| a == "--help" || a == "-h") {
+			return Ok(None);
+		}
+		if args.len() % 2 != 0 {
+			return Err(format!("Missing a value for {}", args[args.len() - 1]));
+		}
+		let options: Vec<(&str, &str)> = args.chunks(2).map(|pair| (pair[0].as_str(), pair[1].as_str())).collect();
+
+		let mut config = Config::new();
+		match options.iter().find(|o| o.0 == "--config") {
+			Some(&(_, path)) => try!(config.load_file(path, true)),
+			None => try!(config.load_file(DEFAULT_CONFIG_FILE, false))
+		}
+		for &(option, value) in &options {
+			try!(config.apply_arg(option, value));
+		}
+		try!(config.validate());
+		Ok(Some(config))
+	}
+
+	/// Read the configuration file at path over the current values
+	fn load_file(&mut self, path: &str, required: bool) -> Result<(), String> {
+		let mut text = String::new();
+		match File::open(path) {
+			Ok(mut file) => if let Err(e) = file.read_to_string(&mut text) {
+				return Err(format!("Could not read {}: {}", path, e));
+			},
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(()),
+			Err(e) => return Err(format!("Could not open {}: {}", path, e))
+		}
+		self.apply_toml(&text).map_err(|e| format!("{}: {}", path, e))
+	}
+
+	/// Apply the contents of a configuration file over the current values
+	pub fn apply_toml(&mut self, text: &str) -> Result<(), String> {
+		let mut parser = Parser::new(text);
+		let table = match parser.parse() {
+			Some(t) => t,
+			None => {
+				let error = &parser.errors[0];
+				let (line, column) = parser.to_linecol(error.lo);
+				return Err(format!("line {}, column {}: {}", line + 1, column + 1, error.desc));
+			}
+		};
+		for (section, value) in &table {
+			let values = match *value {
+				Value::Table(ref t) => t,
+				_ => return Err(format!("Expected a [{}] section", section))
+			};
+			match section.as_str() {
+				"network" => try!(self.apply_network(values)),
+				"world" => try!(self.apply_world(values)),
+				"storage" => try!(self.apply_storage(values)),
+				"rate_limit" => try!(self.apply_rate_limit(values)),
+				"log" => try!(self.apply_log(values)),
+				_ => return Err(format!("Unknown section [{}]", section))
+			}
+		}
+		Ok(())
+	}
+
+	fn apply_network(&mut self, table: &Table) -> Result<(), String> {
+		for (key, value) in table {
+			let name = format!("network.{}", key);
+			match key.as_str() {
+				"bind_address" => self.bind_address = try!(string(&name, value)),
+				"port" => self.port = try!(integer(&name, value, 0, u16::max_value() as i64)) as u16,
+				"ping_interval" => self.ping_interval = try!(float(&name, value)),
+				"max_players" => self.max_players = try!(integer(&name, value, 1, i64::max_value())) as usize,
+				"max_frame_size" => self.max_frame_size = try!(integer(&name, value, 1, i64::max_value())) as usize,
+				"max_outgoing_size" => self.max_outgoing_size = try!(integer(&name, value, 1, i64::max_value())) as usize,
+				"max_connections" => self.max_connections = try!(integer(&name, value, 1, i64::max_value())) as usize,
+				"handshake_timeout" => self.handshake_timeout = try!(positive(&name, value)),
+				"max_missed_pings" => self.max_missed_pings = try!(integer(&name, value, 1, u32::max_value() as i64)) as u32,
+				_ => return Err(format!("Unknown setting {}", name))
+			}
+		}
+		Ok(())
+	}
+
+	fn apply_world(&mut self, table: &Table) -> Result<(), String> {
+		for (key, value) in table {
+			let name = format!("world.{}", key);
+			match key.as_str() {
+				"tick_rate" => self.tick_rate = try!(integer(&name, value, 1, 1000)) as u32,
+				"autosave_interval" => self.autosave_interval = try!(float(&name, value)),
+				"interest_radius" => self.interest_radius = try!(float(&name, value)) as f32,
+				"movement_tolerance" => self.movement_tolerance = try!(float(&name, value)),
+				"violation_budget" => self.violation_budget = try!(integer(&name, value, 0, i64::max_value())) as usize,
+				"session_grace_period" => self.session_grace_period = try!(float(&name, value)),
+				_ => return Err(format!("Unknown setting {}", name))
+			}
+		}
+		Ok(())
+	}
+
+	fn apply_storage(&mut self, table: &Table) -> Result<(), String> {
+		for (key, value) in table {
+			let name = format!("storage.{}", key);
+			match key.as_str() {
+				"backend" => self.storage = try!(string(&name, value)),
+				"path" => self.save_path = PathBuf::from(try!(string(&name, value))),
+				_ => return Err(format!("Unknown setting {}", name))
+			}
+		}
+		Ok(())
+	}
+
+	// The limits of the kinds of messages are tables in this section, like [rate_limit.Login]
+	// The keys come sorted, so Input would come before messages_per_second, the totals go first so new kinds start from them
+	fn apply_rate_limit(&mut self, table: &Table) -> Result<(), String> {
+		let (kinds, totals): (Vec<_>, Vec<_>) = table.iter().partition(|&(_, value)| value.as_table().is_some());
+		for (key, value) in totals.into_iter().chain(kinds) {
+			let name = format!("rate_limit.{}", key);
+			match (key.as_str(), value) {
+				("messages_per_second", _) => self.rate_limits.total.messages_per_second = try!(positive(&name, value)),
+				("bytes_per_second", _) => self.rate_limits.total.bytes_per_second = try!(positive(&name, value)),
+				("strikes", _) => self.rate_limits.strikes = try!(integer(&name, value, 0, i64::max_value())) as usize,
+				(kind, &Value::Table(ref kind_table)) => {
+					// The kinds are case sensitive, so a typo would otherwise quietly do nothing
+					if !MESSAGE_NAMES.contains(&kind) {
+						return Err(format!("Unknown kind of message {}, expected one of {}", name, MESSAGE_NAMES.join(", ")));
+					}
+					let mut limit = match self.rate_limits.kinds.iter().find(|k| k.0 == kind) {
+						Some(k) => k.1.clone(),
+						None => self.rate_limits.total.clone()
+					};
+					for (key, value) in kind_table {
+						let name = format!("{}.{}", name, key);
+						match key.as_str() {
+							"messages_per_second" => limit.messages_per_second = try!(positive(&name, value)),
+							"bytes_per_second" => limit.bytes_per_second = try!(positive(&name, value)),
+							_ => return Err(format!("Unknown setting {}", name))
+						}
+					}
+					self.rate_limits.set_kind(kind, limit);
+				},
+				_ => return Err(format!("Unknown setting {}", name))
+			}
+		}
+		Ok(())
+	}
+
+	fn apply_log(&mut self, table: &Table) -> Result<(), String> {
+		for (key, value) in table {
+			let name = format!("log.{}", key);
+			match key.as_str() {
+				"level" => self.log_level = try!(parse_level(&try!(string(&name, value)))),
+				_ => return Err(format!("Unknown setting {}", name))
+			}
+		}
+		Ok(())
+	}
+
+	/// Apply a single command line option over the current values
+	pub fn apply_arg(&mut self, option: &str, value: &str) -> Result<(), String> {
+		match option {
+			"--config" => {},
+			"--bind" => self.bind_address = value.to_string(),
+			"--port" => self.port = try!(parse(option, value)),
+			"--tick-rate" => self.tick_rate = try!(parse(option, value)),
+			"--ping-interval" => self.ping_interval = try!(parse(option, value)),
+			"--max-players" => self.max_players = try!(parse(option, value)),
+			"--max-connections" => self.max_connections = try!(parse(option, value)),
+			"--interest-radius" => self.interest_radius = try!(parse(option, value)),
+			"--save-path" => self.save_path = PathBuf::from(value),
+			"--storage" => self.storage = value.to_string(),
+			"--log-level" => self.log_level = try!(parse_level(value)),
+			_ => return Err(format!("Unknown option {}", option))
+		}
+		Ok(())
+	}
+
+	/// Check the values that can't be checked while reading them, because they can come from either place
+	pub fn validate(&self) -> Result<(), String> {
+		if self.bind_address.is_empty() {
+			return Err("The bind address can't be empty".to_string());
+		}
+		if self.tick_rate == 0 || self.tick_rate > 1000 {
+			return Err(format!("The tick rate has to be between 1 and 1000, not {}", self.tick_rate));
+		}
+		if !(self.ping_interval > 0.0) {
+			return Err(format!("The ping interval has to be more than 0 seconds, not {}", self.ping_interval));
+		}
+		if !(self.autosave_interval > 0.0) {
+			return Err(format!("The autosave interval has to be more than 0 seconds, not {}", self.autosave_interval));
+		}
+		if !(self.interest_radius > 0.0) {
+			return Err(format!("The interest radius has to be more than 0, not {}", self.interest_radius));
+		}
+		if !(self.session_grace_period >= 0.0) {
+			return Err(format!("The session grace period can't be negative, it is {}", self.session_grace_period));
+		}
+		if !(self.movement_tolerance >= 0.0) {
+			return Err(format!("The movement tolerance can't be negative, not {}", self.movement_tolerance));
+		}
+		if self.max_players == 0 {
+			return Err("There has to be room for at least 1 player".to_string());
+		}
+		if self.max_connections < self.max_players {
+			return Err(format!(
+				"The max connections ({}) has to be at least the max players ({})",
+				self.max_connections, self.max_players
+			));
+		}
+		if self.max_outgoing_size < self.max_frame_size {
+			return Err(format!(
+				"The max outgoing size ({}) has to be at least the max frame size ({})",
+				self.max_outgoing_size, self.max_frame_size
+			));
+		}
+		try!(self.storage_backend());
+		Ok(())
+	}
+
+	pub fn storage_backend(&self) -> Result<StorageBackend, String> {
+		match self.storage.as_str() {
+			"file" => Ok(StorageBackend::File(self.save_path.clone())),
+			"sqlite" => Ok(StorageBackend::Sqlite(self.save_path.join("server.db"))),
+			"memory" => Ok(StorageBackend::Memory),
+			s => Err(format!("Unknown storage backend {}, expected file, sqlite or memory", s))
+		}
+	}
+}
+
+fn parse<T: ::std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+	value.parse().map_err(|_| format!("Invalid value for {}: {}", option, value))
+}
+
+fn parse_level(value: &str) -> Result<LevelFilter, String> {
+	value.parse().map_err(|_| format!("Unknown log level {}, expected off, error, warn, info, debug or trace", value))
+}
@@ -0,0 +1,72 @@
+// The lower bits of an id are the slot, the upper bits are the generation of that slot
+const INDEX_BITS: u32 = 20;
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+/// After this many reuses, a slot starts over at generation 1
+pub const MAX_GENERATION: u32 = (1 << (32 - INDEX_BITS)) - 1;
+
+/// Hands out the ids of connections and entities
+/// Slots are reused after an id is freed, but with a new generation,
+/// so an id that refers to something that is gone is never confused with the new owner of the slot
+/// Ids are never 0, so 0 can be used for "not assigned yet"
+pub struct IdAllocator {
+	slots: Vec<Slot>,
+	free_indexes: Vec<u32>,
+}
+
+struct Slot {
+	generation: u32,
+	in_use: bool,
+}
+
+impl IdAllocator {
+	pub fn new() -> IdAllocator {
+		IdAllocator {
+			slots: Vec::new(),
+			free_indexes: Vec::new(),
+		}
+	}
+
+	pub fn allocate(&mut self) -> u32 {
+		if let Some(index) = self.free_indexes.pop() {
+			let slot = &mut self.slots[index as usize];
+			slot.in_use = true;
+			return make_id(index, slot.generation);
+		}
+		let index = self.slots.len() as u32;
+		assert!(index <= INDEX_MASK, "Ran out of ids");
+		self.slots.push(Slot { generation: 1, in_use: true });
+		make_id(index, 1)
+	}
+
+	pub fn free(&mut self, id: u32) {
+		if !self.is_alive(id) {
+			warn!("Tried to free id {} which is not alive", id);
+			return;
+		}
+		let index = index_of(id);
+		let slot = &mut self.slots[index as usize];
+		// Generation 0 is skipped so an id can never be 0
+		slot.generation = if slot.generation == MAX_GENERATION { 1 } else { slot.generation + 1 };
+		slot.in_use = false;
+		self.free_indexes.push(index);
+	}
+
+	pub fn is_alive(&self, id: u32) -> bool {
+		match self.slots.get(index_of(id) as usize) {
+			Some(slot) => slot.in_use && slot.generation == generation_of(id),
+			None => false
+		}
+	}
+}
+
+fn make_id(index: u32, generation: u32) -> u32 {
+	(generation << INDEX_BITS) | index
+}
+
+fn index_of(id: u32) -> u32 {
+	id & INDEX_MASK
+}
+
+fn generation_of(id: u32) -> u32 {
+	id >> INDEX_BITS
+}
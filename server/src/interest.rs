@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use vecmath::Vector3;
+
+/// The default distance in units within which players see each other
+pub const DEFAULT_INTEREST_RADIUS: f32 = 64.0;
+
+/// Entities that are already visible stay visible until they are this much further than the radius
+/// Otherwise an entity walking along the edge would be spawned and despawned every tick
+pub const LEAVE_MARGIN: f32 = 1.1;
+
+/// Buckets entities by their position on the ground, so finding the entities near a point
+/// only has to look at a few cells instead of every entity in the world
+pub struct SpatialGrid {
+	cell_size: f32,
+	cells: HashMap<(i32, i32), Vec<(u32, Vector3<f32>)>>,
+}
+
+impl SpatialGrid {
+	/// Queries with a radius up to cell_size only have to look at the 9 cells around the point
+	pub fn new(cell_size: f32) -> SpatialGrid {
+		SpatialGrid {
+			cell_size: cell_size,
+			cells: HashMap::new(),
+		}
+	}
+
+	fn cell(&self, position: Vector3<f32>) -> (i32, i32) {
+		((position[0] / self.cell_size).floor() as i32, (position[2] / self.cell_size).floor() as i32)
+	}
+
+	pub fn insert(&mut self, uid: u32, position: Vector3<f32>) {
+		let cell = self.cell(position);
+		self.cells.entry(cell).or_insert_with(Vec::new).push((uid, position));
+	}
+
+	/// The uids of all entities within radius of position, the height is ignored
+	pub fn query(&self, position: Vector3<f32>, radius: f32) -> Vec<u32> {
+		let min = self.cell([position[0] - radius, 0.0, position[2] - radius]);
+		let max = self.cell([position[0] + radius, 0.0, position[2] + radius]);
+		let mut found = Vec::new();
+		for x in min.0..max.0 + 1 {
+			for z in min.1..max.1 + 1 {
+				if let Some(entities) = self.cells.get(&(x, z)) {
+					for &(uid, other) in entities {
+						let dx = other[0] - position[0];
+						let dz = other[2] - position[2];
+						if dx * dx + dz * dz <= radius * radius {
+							found.push(uid);
+						}
+					}
+				}
+			}
+		}
+		found
+	}
+}
+
+/// Which entities a client should know about
+/// Returns (visible, entered, left), visible contains the entities in range, including those that just entered
+pub fn update_visible(grid: &SpatialGrid, position: Vector3<f32>, radius: f32, own_uid: u32, previous: &[u32]) -> (Vec<u32>, Vec<u32>, Vec<u32>) {
+	let near = grid.query(position, radius);
+	let still_near = grid.query(position, radius * LEAVE_MARGIN);
+
+	let mut visible: Vec<u32> = previous.iter().cloned().filter(|uid| still_near.contains(uid)).collect();
+	let left = previous.iter().cloned().filter(|uid| !visible.contains(uid)).collect();
+	let mut entered = Vec::new();
+	for uid in near {
+		if uid != own_uid && !visible.contains(&uid) {
+			visible.push(uid);
+			entered.push(uid);
+		}
+	}
+	(visible, entered, left)
+}
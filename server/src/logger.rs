@@ -0,0 +1,30 @@
+use log::{self, Log, Metadata, Record, LevelFilter, SetLoggerError};
+use time;
+
+/// Writes every log message to stdout, prefixed with the time and the level
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+	fn enabled(&self, metadata: &Metadata) -> bool {
+		metadata.level() <= log::max_level()
+	}
+
+	fn log(&self, record: &Record) {
+		if self.enabled(record.metadata()) {
+			let now = time::now();
+			let timestamp = time::strftime("%H:%M:%S", &now).unwrap_or_default();
+			println!("{} {:<5} {}", timestamp, record.level(), record.args());
+		}
+	}
+
+	fn flush(&self) {}
+}
+
+/// Only messages at or above the given level are written
+pub fn init(level: LevelFilter) -> Result<(), SetLoggerError> {
+	try!(log::set_logger(&LOGGER));
+	log::set_max_level(level);
+	Ok(())
+}
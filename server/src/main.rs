@@ -5,91 +5,198 @@ extern crate shared;
 extern crate time;
 extern crate bincode;
 extern crate rustc_serialize;
+extern crate vecmath;
+extern crate crypto;
+extern crate rand;
+extern crate byteorder;
+extern crate ctrlc;
+extern crate toml;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate rusqlite;
 
+mod config;
+mod logger;
 mod network;
+mod id_allocator;
+mod storage;
+mod password;
+mod world;
+mod interest;
+mod movement;
+mod rate_limit;
+mod session;
+#[cfg(test)]
+mod test;
 
 use shared::*;
 use network::ServerSocket;
+use config::{Config, USAGE};
+use world::{World, WorldState};
+use std::env;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// How long the clients get to receive the ServerShutdown message, in seconds
+const SHUTDOWN_TIMEOUT: f64 = 2.0;
+
+// How often the rate limiting counters are logged, in seconds
+const STATS_INTERVAL: f64 = 60.0;
 
 fn main(){
-	// TODO: Load the world state from database
-	// TODO: Load all the players from database
-	let mut listener = ServerSocket::create("localhost", 8080);
+	let args: Vec<String> = env::args().skip(1).collect();
+	let config = match Config::from_args(&args) {
+		Ok(Some(c)) => c,
+		Ok(None) => {
+			println!("{}", USAGE);
+			return;
+		},
+		Err(e) => {
+			println!("Invalid configuration: {}", e);
+			println!("{}", USAGE);
+			process::exit(1);
+		}
+	};
+	if let Err(e) = logger::init(config.log_level) {
+		println!("Could not set up logging: {:?}", e);
+	}
+	debug!("{:?}", config);
+
+	// validate already checked the backend name
+	let backend = config.storage_backend().unwrap();
+	let mut storage = match backend.open() {
+		Ok(s) => s,
+		Err(e) => {
+			error!("Could not open {:?}: {:?}", backend, e);
+			process::exit(1);
+		}
+	};
+	let accounts = match storage::load_users(&mut *storage) {
+		Ok(a) => a,
+		Err(e) => {
+			// Don't start, because the next save would overwrite whatever is left of the users
+			error!("Could not load the users: {:?}", e);
+			error!("When using the file storage, restore users.dat from one of the backups (users.dat.1 is the newest) and restart the server");
+			process::exit(1);
+		}
+	};
+	let state = match storage.load_world() {
+		Ok(Some(s)) => s,
+		Ok(None) => WorldState::new(),
+		Err(e) => {
+			error!("Could not load the world: {:?}", e);
+			error!("When using the file storage, restore world.dat from one of the backups (world.dat.1 is the newest) and restart the server");
+			process::exit(1);
+		}
+	};
+
+	let mut listener = match ServerSocket::create(&config.bind_address, config.port) {
+		Ok(l) => l,
+		Err(e) => {
+			error!("Could not listen on {}:{}: {}", config.bind_address, config.port, e);
+			process::exit(1);
+		}
+	};
+	if let Ok(address) = listener.local_addr() {
+		info!("Listening on {}", address);
+	}
+	listener.max_frame_size = config.max_frame_size;
+	listener.max_outgoing_size = config.max_outgoing_size;
+	listener.rate_limits = config.rate_limits.clone();
+	listener.max_connections = config.max_connections;
+	listener.handshake_timeout = config.handshake_timeout;
+	listener.max_missed_pings = config.max_missed_pings;
+	let mut world = World::new(storage, state, accounts);
+	world.max_players = config.max_players;
+	world.interest_radius = config.interest_radius;
+	world.movement_tolerance = config.movement_tolerance;
+	world.violation_budget = config.violation_budget;
+	world.session_grace_period = config.session_grace_period;
+
+	// Cleared on SIGINT and SIGTERM, so we can save before exiting
+	let running = Arc::new(AtomicBool::new(true));
+	{
+		let running = running.clone();
+		if let Err(e) = ctrlc::set_handler(move || running.store(false, Ordering::SeqCst)) {
+			warn!("Could not set the shutdown handler, the world will only be saved periodically: {:?}", e);
+		}
+	}
 
 	let mut last_time = time::precise_time_s();
 	let mut last_print_time = 0.0;
-	loop {
+	let mut last_save_time = time::precise_time_s();
+	let mut last_stats_time = time::precise_time_s();
+	let mut timestep = FixedTimestep::new();
+	let mut last_step_time = time::precise_time_s();
+	while running.load(Ordering::SeqCst) {
 		let update_time = time::precise_time_ns();
 
+		// Only accepting new connections failed, the connected clients can go on
+		if let Err(e) = listener.listen(&mut world) {
+			error!("Could not listen: {:?}", e);
+		}
 
-		// Nasty solution to break out of the 3 callback functions that listener.listen has
-		// The 3 functions get their own sender, that all loop into the receiver
-		// This is because we can't call listener.broadcast in the callback functions because listener is already being used as a mutable
-		let (send, receive) = std::sync::mpsc::channel();
-		let s1 = send.clone();
-		let s2 = send.clone();
-		let s3 = send;
-
-		// TODO: move all the data into a world state and pass the world state to the listener
-		// These 3 functions then go to the world state instead of in here
-		let listen_result = listener.listen(move |new_client| {
-			let id = new_client.id;
-			try!(new_client.send(NetworkMessage::Identify(id)));
-			try!(s1.send(NetworkMessage::SetPosition {
-				uid: id,
-				position: [-10.0, 0.0, 0.0],
-				rotation: [0.0, 0.0, 0.0]
-			}));
-			Ok(())
-		}, move |client, message| {
-			// TODO: Find something smart to handle all the different messages
-			if message == NetworkMessage::Ping {
-				let ping = ((time::precise_time_s() - client.last_ping_time) * 1000f64) as u32;
-				try!(client.send(NetworkMessage::PingResult(ping)));
-			}
-			if let NetworkMessage::SetPosition { position, rotation, .. } = message {
-				try!(s2.send(NetworkMessage::SetPosition {
-					uid: client.id,
-					position: position,
-					rotation: rotation,
-				}));
-			}
-			Ok(())
-		}, move |client| {
-			try!(s3.send(NetworkMessage::RemoveEntity { uid: client.id }));
-			Ok(())
-		});
-
-		if let Err(e) = listen_result {
-			panic!("Could not listen: {:?}", e);
+		// The world moves in the same fixed steps as the clients, no matter how fast this loop runs
+		let now = time::precise_time_s();
+		let steps = timestep.advance(now - last_step_time);
+		for _ in 0..steps {
+			world.tick();
 		}
+		last_step_time = now;
 
-		// Get the broadcast messages from the channels and handle them
-		while let Ok(message) = receive.try_recv() {
-			listener.broadcast(message);
+		// Nothing moves between steps, so there is only something new to tell after a step
+		if steps > 0 {
+			for (id, message) in world.take_updates() {
+				listener.send_to(id, message);
+			}
 		}
 
-		// Send all players a ping every second
-		// TODO: Will this spam too much? Maybe make it every 5, 10, 60 seconds?
-		if time::precise_time_s() - last_time > 1f64 {
-			println!("Ping!");
+		if time::precise_time_s() - last_time > config.ping_interval {
+			debug!("Ping!");
 			last_time = time::precise_time_s();
-			for client in &mut listener.clients {
-				client.last_ping_time = last_time;
-				client.send(NetworkMessage::Ping).unwrap();// TODO: Deal with unwrap
+			listener.ping(last_time);
+		}
+
+		listener.flush();
+
+		if time::precise_time_s() - last_save_time > config.autosave_interval {
+			if let Err(e) = world.save() {
+				error!("Could not save the world: {:?}", e);
 			}
+			last_save_time = time::precise_time_s();
+		}
+
+		if time::precise_time_s() - last_stats_time > STATS_INTERVAL {
+			let stats = listener.rate_stats();
+			info!(
+				"Received {} messages ({} bytes), dropped {}, throttled {} times, disconnected {} clients for sending too much",
+				stats.messages, stats.bytes, stats.dropped, stats.throttled, stats.disconnected
+			);
+			last_stats_time = time::precise_time_s();
 		}
 
-		// Sleep so that the server reaches 50 UPS
+		// Sleep so that the server reaches the tick rate
 		let delta_time = time::precise_time_ns() - update_time;
-		let target_time = 1_000_000_000 / 50;
+		let target_time = 1_000_000_000 / config.tick_rate as u64;
 		if target_time > delta_time {
 			std::thread::sleep(std::time::Duration::new(0, (target_time - delta_time) as u32));
 		} else if time::precise_time_s() > last_print_time + 5.0 {
 			// Server too slow, can't keep up
-			println!("{}: Server couldn't keep up with 50 ups", time::now().strftime("%H:%M:%S").unwrap());// TODO: Deal with unwrap
+			warn!("Server couldn't keep up with {} ups", config.tick_rate);
 			last_print_time = time::precise_time_s();
 		}
 	}
+
+	info!("Shutting down");
+	shutdown(&mut world, &mut listener);
 }
 
+/// Save the world before telling the clients, so the players are saved where their clients last saw them
+fn shutdown(world: &mut World, listener: &mut ServerSocket) {
+	if let Err(e) = world.save() {
+		error!("Could not save the world: {:?}", e);
+	}
+	listener.close_all(NetworkMessage::ServerShutdown, "The server is shutting down", SHUTDOWN_TIMEOUT);
+}
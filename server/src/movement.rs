@@ -0,0 +1,60 @@
+use shared::TIMESTEP;
+
+/// The default amount of seconds a client's inputs may run ahead of the server's clock
+/// Inputs that were held up by the network arrive in bursts, this keeps those from being seen as speeding
+pub const DEFAULT_MOVEMENT_TOLERANCE: f64 = 0.5;
+
+/// The default amount of violations within VIOLATION_WINDOW that gets a client kicked
+pub const DEFAULT_VIOLATION_BUDGET: usize = 20;
+
+/// Violations older than this many seconds are forgiven, so a few lag spikes over a long session don't add up to a kick
+pub const VIOLATION_WINDOW: f64 = 60.0;
+
+/// Why a move was rejected
+#[derive(Debug, PartialEq, Clone)]
+pub enum Violation {
+	/// The client sent more inputs than the time that passed allows, by this many seconds
+	TooFast { ahead: f64 },
+}
+
+/// Keeps track of how fast a player is moving, so a modified client can't speed by sending more inputs
+/// The server applies every input itself, so a single step can't go further than MOVE_SPEED allows
+pub struct MovementValidator {
+	/// How far the accepted moves have brought the player in time, every move is worth one TIMESTEP
+	/// and an input that arrives at a certain time is the step that ends at that time
+	pub last_time: f64,
+	// The times of the recent violations
+	violations: Vec<f64>,
+}
+
+impl MovementValidator {
+	pub fn new(time: f64) -> MovementValidator {
+		MovementValidator {
+			last_time: time,
+			violations: Vec::new(),
+		}
+	}
+
+	/// Check a single simulation step that arrived at the given time
+	/// The move is only remembered when it is accepted, a rejected move should be undone by the caller
+	pub fn check(&mut self, now: f64, tolerance: f64) -> Result<(), Violation> {
+		// A player that stood still or lagged doesn't get to save up more than the tolerance to run faster later
+		let move_time = (self.last_time + TIMESTEP).max(now - tolerance);
+		if move_time > now + tolerance {
+			return Err(self.violation(now, Violation::TooFast { ahead: move_time - now }));
+		}
+		self.last_time = move_time;
+		Ok(())
+	}
+
+	fn violation(&mut self, now: f64, violation: Violation) -> Violation {
+		self.violations.retain(|t| now - *t < VIOLATION_WINDOW);
+		self.violations.push(now);
+		violation
+	}
+
+	/// The amount of violations within VIOLATION_WINDOW before now
+	pub fn violation_count(&self, now: f64) -> usize {
+		self.violations.iter().filter(|t| now - **t < VIOLATION_WINDOW).count()
+	}
+}
@@ -1,19 +1,99 @@
-use std::string;
-use std::net::TcpListener;
-use std::sync::mpsc::SendError;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::Duration;
+use time;
 
-use shared::{ClientSocket, NetworkMessage, ClientError, NO_CONTENT_CODE};
+use rate_limit::{ClientLimiter, RateLimits, RateStats, Verdict};
+use shared::{ClientSocket, NetworkMessage, ClientError, DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_OUTGOING_SIZE, PROTOCOL_VERSION, negotiate};
 
 pub struct ServerSocket {
 	listener: TcpListener,
 	pub clients: Vec<ClientSocket>,
+	// Clients that are connected but did not send a valid Hello yet, with the time they connected
+	pending: Vec<(ClientSocket, f64)>,
+	// Clients that are dropped, with the time they were dropped, see CLOSE_DELAY
+	closing: Vec<(ClientSocket, f64)>,
+	/// The maximum frame size that is applied to every new client
+	pub max_frame_size: usize,
+	/// The amount of bytes that can be queued for a client before it is disconnected
+	pub max_outgoing_size: usize,
+	/// What every client is allowed to send
+	pub rate_limits: RateLimits,
+	/// The amount of connections, including those still in the handshake, after which new connections are turned away
+	pub max_connections: usize,
+	/// Seconds a new connection gets to send its Hello
+	pub handshake_timeout: f64,
+	/// The amount of pings in a row a client can leave unanswered before it is disconnected
+	pub max_missed_pings: u32,
+	// One for every client in clients
+	limiters: Vec<ClientLimiter>,
+	// The counters of the clients that are gone
+	removed_stats: RateStats,
 }
 
 #[derive(Debug)]
 pub enum ServerError {
 	CouldNotAcceptSocket,
 	ClientError(ClientError),
-	ThreadError,
+	/// A client sent a message that only the server is supposed to send
+	UnexpectedMessage(&'static str),
+	/// A client sent a message that is only allowed after logging in
+	NotLoggedIn,
+	/// The client broke the rules too often, with the reason
+	Kicked(String),
+}
+
+impl ServerError {
+	/// The reason that is sent to a client that is dropped because of this error
+	pub fn reason(&self) -> String {
+		match *self {
+			ServerError::CouldNotAcceptSocket => "The server could not accept the connection".to_string(),
+			ServerError::ClientError(ClientError::Malformed) => "The server could not read a message".to_string(),
+			ServerError::ClientError(ref e) => format!("Connection error: {:?}", e),
+			ServerError::UnexpectedMessage(name) => format!("The server did not expect a {} message", name),
+			ServerError::NotLoggedIn => "You have to log in first".to_string(),
+			ServerError::Kicked(ref reason) => format!("Kicked: {}", reason),
+		}
+	}
+}
+
+/// The default of ServerSocket::max_connections
+pub const DEFAULT_MAX_CONNECTIONS: usize = 128;
+
+/// The default of ServerSocket::handshake_timeout
+pub const DEFAULT_HANDSHAKE_TIMEOUT: f64 = 5.0;
+
+/// The default of ServerSocket::max_missed_pings
+pub const DEFAULT_MAX_MISSED_PINGS: u32 = 10;
+
+// Seconds a dropped client stays connected, closing a socket with unread data resets the connection,
+// which would throw away the Disconnect that tells the client why before it can read it
+const CLOSE_DELAY: f64 = 1.0;
+
+// Tell a client why it is dropped, it is going away either way so we don't care if this arrives
+fn send_disconnect(client: &mut ClientSocket, reason: String) {
+	if let Err(e) = client.send(NetworkMessage::Disconnect { reason: reason }) {
+		debug!("Could not send Disconnect to client {}: {:?}", client.id, e);
+	}
+}
+
+/// Why a client is removed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Removal {
+	/// The connection broke or the client stopped answering, it may come back
+	ConnectionLost,
+	/// The client was dropped for what it sent, or left on purpose
+	Dropped,
+}
+
+/// Receives everything that happens to the clients of a ServerSocket
+pub trait ClientHandler {
+	/// Called when a client finished the handshake, before it is added to the list of clients
+	fn client_created(&mut self, client: &mut ClientSocket) -> Result<(), ServerError>;
+	fn client_message(&mut self, client: &mut ClientSocket, message: NetworkMessage) -> Result<(), ServerError>;
+	/// Called right before a disconnected client is removed
+	fn client_removed(&mut self, client: &mut ClientSocket, removal: Removal) -> Result<(), ServerError>;
 }
 
 impl From<ClientError> for ServerError {
@@ -21,85 +101,303 @@ impl From<ClientError> for ServerError {
 		ServerError::ClientError(err)
 	}
 }
-impl From<SendError<NetworkMessage>> for ServerError {
-	fn from(_: SendError<NetworkMessage>) -> ServerError {
-		ServerError::ThreadError
-	}
-}
 
-// See main.rs for TODO's
 impl ServerSocket {
-	pub fn create<T: string::ToString>(host: T, port: i32) -> ServerSocket {
-		let address = format!("{}:{}", host.to_string(), port);
-		println!("Setting up socket on: {}", &address);
-		let listener = TcpListener::bind(address.as_str()).unwrap();// TODO: Deal with unwrap
-		listener.set_nonblocking(true).unwrap();// TODO: Deal with unwrap
-		ServerSocket {
+	pub fn create(host: &str, port: u16) -> io::Result<ServerSocket> {
+		info!("Setting up socket on: {}:{}", host, port);
+		let listener = try!(TcpListener::bind((host, port)));
+		try!(listener.set_nonblocking(true));
+		Ok(ServerSocket {
 			listener: listener,
 			clients: Vec::new(),
+			pending: Vec::new(),
+			closing: Vec::new(),
+			max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+			max_outgoing_size: DEFAULT_MAX_OUTGOING_SIZE,
+			rate_limits: RateLimits::new(),
+			max_connections: DEFAULT_MAX_CONNECTIONS,
+			handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+			max_missed_pings: DEFAULT_MAX_MISSED_PINGS,
+			limiters: Vec::new(),
+			removed_stats: RateStats::default(),
+		})
+	}
+
+	/// The address we are actually listening on, which tells the port when the configured port was 0
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.listener.local_addr()
+	}
+
+	/// The rate limiting counters of every client since the server started
+	pub fn rate_stats(&self) -> RateStats {
+		let mut stats = self.removed_stats.clone();
+		for limiter in &self.limiters {
+			stats.add(&limiter.stats);
 		}
+		stats
 	}
 
-	pub fn broadcast(&mut self, message: NetworkMessage) {
+	/// Ping every client, and disconnect the ones that didn't answer the last max_missed_pings pings
+	/// The clients answer with a Pong, which the handler gives to the PingTracker of the client
+	pub fn ping(&mut self, now: f64) {
 		for client in &mut self.clients {
-			if client.send(message.clone()).is_err() {
+			if !client.is_connected() {
+				continue;
+			}
+			if client.pings.missed >= self.max_missed_pings {
+				info!("Client {} timed out after {} unanswered pings", client.id, client.pings.missed);
+				send_disconnect(client, "Timed out".to_string());
+				client.disconnect();
+				continue;
+			}
+			let ping = client.pings.ping(now);
+			if let Err(e) = client.send(ping) {
+				warn!("Could not ping client {}: {:?}", client.id, e);
+				client.disconnect();
+			}
+		}
+	}
+
+	/// Send a message to the client with the given id, if it is still connected
+	pub fn send_to(&mut self, id: u32, message: NetworkMessage) {
+		if let Some(client) = self.clients.iter_mut().find(|c| c.id == id && c.is_connected()) {
+			if let Err(e) = client.send(message) {
+				warn!("Could not send to client {}: {:?}", client.id, e);
+				client.disconnect();
+			}
+		}
+	}
+
+	/// Write the queued messages of every client
+	/// Clients that can't be written to are disconnected, and will be removed in the next listen
+	pub fn flush(&mut self) {
+		for client in self.clients.iter_mut().chain(self.pending.iter_mut().map(|p| &mut p.0)) {
+			if !client.is_connected() {
+				continue;
+			}
+			if let Err(e) = client.flush() {
+				warn!("Could not flush client {}: {:?}", client.id, e);
 				client.disconnect();
 			}
 		}
 	}
 
-	pub fn listen<F1, F2, F3>(&mut self,
-							  client_created_callback: F1,
-							  client_message_callback: F2,
-							  client_removed_callback: F3) -> Result<(), ServerError>
-							  where F1: Fn(&mut ClientSocket) -> Result<(), ServerError>,
-									F2: Fn(&mut ClientSocket, NetworkMessage) -> Result<(), ServerError>,
-									F3: Fn(&mut ClientSocket) -> Result<(), ServerError> {
+	/// Send a last message to every client and close all connections
+	/// Clients that didn't finish the handshake may not speak our protocol, they get a Disconnect with reason instead
+	/// Waits at most timeout seconds for the queued messages to be written
+	pub fn close_all(&mut self, message: NetworkMessage, reason: &str, timeout: f64) {
+		for client in &mut self.clients {
+			if let Err(e) = client.send(message.clone()) {
+				warn!("Could not send to client {}: {:?}", client.id, e);
+				client.disconnect();
+			}
+		}
+		for &mut (ref mut client, _) in &mut self.pending {
+			send_disconnect(client, reason.to_string());
+		}
+
+		let deadline = time::precise_time_s() + timeout;
+		while time::precise_time_s() < deadline {
+			self.flush();
+			let has_pending_bytes = self.clients.iter().chain(self.pending.iter().map(|p| &p.0))
+				.any(|c| c.is_connected() && c.pending_bytes() > 0);
+			if !has_pending_bytes {
+				break;
+			}
+			thread::sleep(Duration::from_millis(10));
+		}
+
+		for client in self.clients.iter_mut().chain(self.pending.iter_mut().map(|p| &mut p.0)) {
+			client.disconnect();
+		}
+		self.clients.clear();
+		self.pending.clear();
+		self.closing.clear();
+		for limiter in self.limiters.drain(..) {
+			self.removed_stats.add(&limiter.stats);
+		}
+	}
+
+	pub fn listen<H: ClientHandler>(&mut self, handler: &mut H) -> Result<(), ServerError> {
 		match self.listener.accept() {
+			// Nobody is waiting to connect
+			Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
 			Err(e) => {
-				let mut no_clients_error = false;
-				if let Some(os_error) = e.raw_os_error() {
-					if os_error == NO_CONTENT_CODE {
-						no_clients_error = true;
-					}
-				}
-				if !no_clients_error {
-					println!("{:?}", e);
-					return Err(ServerError::CouldNotAcceptSocket);
-				}
+				error!("Could not accept a client: {:?}", e);
+				return Err(ServerError::CouldNotAcceptSocket);
 			},
 			Ok(s) => {
-				println!("Client connected: {:?}", s.1);
 				let mut client = ClientSocket::from_stream(s.0);
-				try!(client_created_callback(&mut client));
-				self.clients.push(client);
+				if self.clients.len() + self.pending.len() >= self.max_connections {
+					info!("Turning away {:?}, there are already {} connections", s.1, self.max_connections);
+					send_disconnect(&mut client, "The server has too many connections, please try again later".to_string());
+					self.closing.push((client, time::precise_time_s()));
+				} else {
+					info!("Client connected: {:?}", s.1);
+					client.set_max_frame_size(self.max_frame_size);
+					client.set_max_outgoing_size(self.max_outgoing_size);
+					self.pending.push((client, time::precise_time_s()));
+				}
 			}
 		};
 
-		let mut remove_indexes: Vec<usize> = Vec::new();
+		self.handle_handshakes(handler);
+		self.handle_closing();
+
+		let mut remove_indexes: Vec<(usize, Removal)> = Vec::new();
 
+		let now = time::precise_time_s();
 		for i in 0..self.clients.len() {
 			let client = &mut self.clients[i];
-			match client.get_message() {
-				Ok(Some(message)) => {
-					try!(client_message_callback(client, message));
+			let limiter_index = match self.limiters.iter().position(|l| l.client_id == client.id) {
+				Some(index) => index,
+				None => {
+					self.limiters.push(ClientLimiter::new(client.id, &self.rate_limits, now));
+					self.limiters.len() - 1
+				}
+			};
+			let limiter = &mut self.limiters[limiter_index];
+			// Not reading makes the client wait, without costing us anything
+			if limiter.is_throttled(now) {
+				continue;
+			}
+			match client.poll_sized_messages() {
+				Ok(messages) => {
+					for (message, size) in messages {
+						match limiter.check(message.name(), size, now, &self.rate_limits) {
+							Verdict::Allow => {},
+							Verdict::Throttle => {
+								debug!("Throttling client {}, dropped a {}", client.id, message.name());
+								// The client waits for an answer to these, without one it would wait forever
+								match message {
+									NetworkMessage::Login { .. } | NetworkMessage::Register { .. } => {
+										let result = NetworkMessage::LoginResult { error: Some("Too many attempts, please try again in a moment".to_string()) };
+										if let Err(e) = client.send(result) {
+											debug!("Could not tell client {} it is throttled: {:?}", client.id, e);
+										}
+									},
+									_ => {}
+								}
+								continue;
+							},
+							Verdict::Disconnect => {
+								warn!("Dropping client {}: sending too much, the last message was a {}", client.id, message.name());
+								send_disconnect(client, "You are sending too much".to_string());
+								remove_indexes.push((i, Removal::Dropped));
+								break;
+							}
+						}
+						// A message that can't be handled only costs the offending client its connection
+						if let Err(e) = handler.client_message(client, message) {
+							warn!("Dropping client {}: {:?}", client.id, e);
+							send_disconnect(client, e.reason());
+							// Only an answer that couldn't be sent is the fault of the connection
+							let removal = match e {
+								ServerError::ClientError(_) => Removal::ConnectionLost,
+								_ => Removal::Dropped
+							};
+							remove_indexes.push((i, removal));
+							break;
+						}
+						// The handler can drop the client as well
+						if !client.is_connected() {
+							remove_indexes.push((i, Removal::Dropped));
+							break;
+						}
+					}
 				},
-				Ok(None) => {},
 				Err(ClientError::Disconnected) => {
-					remove_indexes.push(i);
+					remove_indexes.push((i, Removal::ConnectionLost));
 				},
 				Err(e) => {
-					println!("Unknown error: {:?}", e);
-					remove_indexes.push(i);
+					// Malformed or oversized frames only cost the offending client its connection
+					warn!("Dropping client {}: {:?}", client.id, e);
+					send_disconnect(client, ServerError::ClientError(e).reason());
+					remove_indexes.push((i, Removal::Dropped));
 				}
 			};
 		}
 		remove_indexes.reverse();
-		for remove_index in remove_indexes {
-			try!(client_removed_callback(&mut self.clients[remove_index]));
-			println!("Removing at {}", remove_index);
-			self.clients.remove(remove_index);
+		for (remove_index, removal) in remove_indexes {
+			// The client is gone either way, the handler failing shouldn't take the other clients with it
+			if let Err(e) = handler.client_removed(&mut self.clients[remove_index], removal) {
+				error!("Could not remove client {}: {:?}", self.clients[remove_index].id, e);
+			}
+			debug!("Removing at {}", remove_index);
+			let client = self.clients.remove(remove_index);
+			if let Some(index) = self.limiters.iter().position(|l| l.client_id == client.id) {
+				let limiter = self.limiters.remove(index);
+				self.removed_stats.add(&limiter.stats);
+			}
+			self.closing.push((client, now));
 		}
 		Ok(())
 	}
+
+	// Close the connections of dropped clients once they closed them, or after CLOSE_DELAY
+	// Until then, what they send is read and thrown away
+	fn handle_closing(&mut self) {
+		let now = time::precise_time_s();
+		self.closing.retain(|&(ref client, close_time)| client.is_connected() && now - close_time < CLOSE_DELAY);
+		for &mut (ref mut client, _) in &mut self.closing {
+			if client.flush().is_err() || client.poll_messages().is_err() {
+				client.disconnect();
+			}
+		}
+	}
+
+	fn handle_handshakes<H: ClientHandler>(&mut self, handler: &mut H) {
+		// (index, accepted)
+		let mut finished: Vec<(usize, bool)> = Vec::new();
+
+		let now = time::precise_time_s();
+		for i in 0..self.pending.len() {
+			let (ref mut client, connect_time) = self.pending[i];
+			match client.get_message() {
+				Ok(Some(message)) => match negotiate(&message) {
+					Ok(capabilities) => {
+						let welcome = NetworkMessage::Welcome {
+							protocol_version: PROTOCOL_VERSION,
+							capabilities: capabilities,
+						};
+						finished.push((i, client.send(welcome).is_ok()));
+					},
+					Err(reason) => {
+						info!("Rejecting client: {}", reason);
+						// The client is dropped either way, so we don't care if this arrives
+						let _ = client.send(NetworkMessage::Reject { reason: reason });
+						finished.push((i, false));
+					}
+				},
+				// Otherwise anyone can keep a connection slot by connecting and never saying anything
+				Ok(None) => if now - connect_time > self.handshake_timeout {
+					info!("Client did not finish the handshake within {} seconds", self.handshake_timeout);
+					send_disconnect(client, "The handshake took too long".to_string());
+					finished.push((i, false));
+				},
+				Err(e) => {
+					info!("Client disconnected during handshake: {:?}", e);
+					finished.push((i, false));
+				}
+			};
+		}
+
+		finished.reverse();
+		for (index, accepted) in finished {
+			let (mut client, _) = self.pending.remove(index);
+			if accepted {
+				match handler.client_created(&mut client) {
+					Ok(()) => {
+						self.clients.push(client);
+						continue;
+					},
+					Err(e) => {
+						error!("Could not create a client: {:?}", e);
+						send_disconnect(&mut client, e.reason());
+					}
+				}
+			}
+			self.closing.push((client, now));
+		}
+	}
 }
\ No newline at end of file
@@ -0,0 +1,89 @@
+use shared::User;
+use crypto::pbkdf2::{pbkdf2_simple, pbkdf2_check};
+use std::io;
+
+// Every hash stores its own iteration count, so this can be increased without breaking existing passwords
+const PBKDF2_ITERATIONS: u32 = 10_000;
+
+/// How the password of a UserPassword is stored
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug, Clone, Copy)]
+pub enum PasswordFormat {
+	/// Saved by servers from before passwords were hashed, see migrate_plaintext_passwords
+	PlainText = 0,
+	/// The hash, salt and parameters in the format of crypto::pbkdf2::pbkdf2_simple
+	Pbkdf2 = 1,
+}
+
+/// The password of a user, stored as a salted PBKDF2 hash
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+pub struct UserPassword {
+	pub user_id: u32,
+	pub format: PasswordFormat,
+	pub password: String,
+}
+
+impl UserPassword {
+	pub fn new(user_id: u32, password: &str) -> io::Result<UserPassword> {
+		Ok(UserPassword {
+			user_id: user_id,
+			format: PasswordFormat::Pbkdf2,
+			password: try!(pbkdf2_simple(password, PBKDF2_ITERATIONS)),
+		})
+	}
+
+	/// Check the password in constant time
+	/// Plain text passwords never match, they are hashed when the users are loaded
+	pub fn verify(&self, password: &str) -> bool {
+		if self.format != PasswordFormat::Pbkdf2 {
+			return false;
+		}
+		match pbkdf2_check(password, &self.password) {
+			Ok(valid) => valid,
+			Err(e) => {
+				warn!("Invalid password hash for user {}: {}", self.user_id, e);
+				false
+			}
+		}
+	}
+
+	pub fn is_hashed(&self) -> bool {
+		self.format == PasswordFormat::Pbkdf2
+	}
+}
+
+/// A UserPassword as it was saved before it had a format
+/// Both plain text passwords and hashes were saved like this
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+pub struct LegacyUserPassword {
+	pub user_id: u32,
+	pub password: String,
+}
+
+impl LegacyUserPassword {
+	/// Only a complete hash counts as one, a plain text password can look like the start of one
+	pub fn upgrade(self) -> UserPassword {
+		let format = match pbkdf2_check("", &self.password) {
+			Ok(_) => PasswordFormat::Pbkdf2,
+			Err(_) => PasswordFormat::PlainText
+		};
+		UserPassword {
+			user_id: self.user_id,
+			format: format,
+			password: self.password,
+		}
+	}
+}
+
+/// Hash every password that is still stored as plain text
+/// Returns true if any password was changed, in which case the users should be saved again
+pub fn migrate_plaintext_passwords(users: &mut Vec<(User, UserPassword)>) -> io::Result<bool> {
+	let mut changed = false;
+	for &mut (_, ref mut password) in users.iter_mut() {
+		if !password.is_hashed() {
+			info!("Hashing the plain text password of user {}", password.user_id);
+			*password = try!(UserPassword::new(password.user_id, &password.password));
+			changed = true;
+		}
+	}
+	Ok(changed)
+}
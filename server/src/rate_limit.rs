@@ -0,0 +1,207 @@
+/// Buckets hold this many seconds worth of their rate, so short bursts are fine as long as the average is
+pub const BURST_SECONDS: f64 = 2.0;
+
+/// How long a client that went over a limit isn't read from
+/// TCP makes the client wait while we don't read, so this slows it down without costing us anything
+pub const THROTTLE_TIME: f64 = 0.5;
+
+/// Strikes older than this many seconds are forgotten
+pub const STRIKE_WINDOW: f64 = 10.0;
+
+/// Refills at a fixed rate up to its capacity, and every message takes some of it
+pub struct TokenBucket {
+	/// Tokens per second
+	pub rate: f64,
+	pub capacity: f64,
+	tokens: f64,
+	last_time: f64,
+}
+
+impl TokenBucket {
+	/// Buckets start out full
+	pub fn new(rate: f64, capacity: f64, now: f64) -> TokenBucket {
+		TokenBucket {
+			rate: rate,
+			capacity: capacity,
+			tokens: capacity,
+			last_time: now,
+		}
+	}
+
+	/// True if there are enough tokens to take amount, without taking them
+	/// Something bigger than the whole bucket is allowed when the bucket is full, leaving it in debt
+	pub fn has(&mut self, amount: f64, now: f64) -> bool {
+		if now > self.last_time {
+			self.tokens = (self.tokens + (now - self.last_time) * self.rate).min(self.capacity);
+			self.last_time = now;
+		}
+		self.tokens >= amount.min(self.capacity)
+	}
+
+	/// Take amount tokens, returns false if there are not enough
+	pub fn take(&mut self, amount: f64, now: f64) -> bool {
+		if !self.has(amount, now) {
+			return false;
+		}
+		self.tokens -= amount;
+		true
+	}
+}
+
+/// How much a client may send
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+	pub messages_per_second: f64,
+	pub bytes_per_second: f64,
+}
+
+impl RateLimit {
+	pub fn new(messages_per_second: f64, bytes_per_second: f64) -> RateLimit {
+		RateLimit {
+			messages_per_second: messages_per_second,
+			bytes_per_second: bytes_per_second,
+		}
+	}
+
+	fn buckets(&self, now: f64) -> (TokenBucket, TokenBucket) {
+		(
+			TokenBucket::new(self.messages_per_second, (self.messages_per_second * BURST_SECONDS).max(1.0), now),
+			TokenBucket::new(self.bytes_per_second, self.bytes_per_second * BURST_SECONDS, now),
+		)
+	}
+}
+
+/// The limits of every client
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimits {
+	/// Everything a client sends counts towards this limit
+	pub total: RateLimit,
+	/// Messages of these kinds also count towards their own limit, the kind is the name of the NetworkMessage
+	pub kinds: Vec<(String, RateLimit)>,
+	/// The amount of times within STRIKE_WINDOW that a client can go over a limit before it is disconnected
+	pub strikes: usize,
+}
+
+impl RateLimits {
+	pub fn new() -> RateLimits {
+		RateLimits {
+			// Inputs alone are shared::SIMULATION_RATE messages per second
+			total: RateLimit::new(200.0, 64.0 * 1024.0),
+			kinds: vec![
+				// Hashing passwords is slow on purpose, so nobody gets to make us do it a lot
+				("Register".to_string(), RateLimit::new(1.0, 1024.0)),
+				("Login".to_string(), RateLimit::new(1.0, 1024.0)),
+			],
+			strikes: 10,
+		}
+	}
+
+	/// Set the limit of a kind of message, replacing the previous limit of that kind
+	pub fn set_kind(&mut self, kind: &str, limit: RateLimit) {
+		match self.kinds.iter_mut().find(|k| k.0 == kind) {
+			Some(existing) => existing.1 = limit,
+			None => self.kinds.push((kind.to_string(), limit))
+		}
+	}
+}
+
+/// What to do with a message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+	Allow,
+	/// The message is dropped and the client isn't read for a while
+	Throttle,
+	Disconnect,
+}
+
+/// Counters for monitoring, these only ever go up
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateStats {
+	pub messages: u64,
+	pub bytes: u64,
+	/// Messages that were dropped because the client went over a limit
+	pub dropped: u64,
+	pub throttled: u64,
+	pub disconnected: u64,
+}
+
+impl RateStats {
+	pub fn add(&mut self, other: &RateStats) {
+		self.messages += other.messages;
+		self.bytes += other.bytes;
+		self.dropped += other.dropped;
+		self.throttled += other.throttled;
+		self.disconnected += other.disconnected;
+	}
+}
+
+/// The buckets of a single client
+pub struct ClientLimiter {
+	pub client_id: u32,
+	// (messages, bytes)
+	total: (TokenBucket, TokenBucket),
+	// (kind, messages, bytes)
+	kinds: Vec<(String, TokenBucket, TokenBucket)>,
+	// The times the client went over a limit
+	strikes: Vec<f64>,
+	throttled_until: f64,
+	pub stats: RateStats,
+}
+
+impl ClientLimiter {
+	pub fn new(client_id: u32, limits: &RateLimits, now: f64) -> ClientLimiter {
+		ClientLimiter {
+			client_id: client_id,
+			total: limits.total.buckets(now),
+			kinds: limits.kinds.iter().map(|k| {
+				let (messages, bytes) = k.1.buckets(now);
+				(k.0.clone(), messages, bytes)
+			}).collect(),
+			strikes: Vec::new(),
+			throttled_until: 0.0,
+			stats: RateStats::default(),
+		}
+	}
+
+	/// True while the client shouldn't be read from
+	pub fn is_throttled(&self, now: f64) -> bool {
+		now < self.throttled_until
+	}
+
+	/// Count a message of the given kind and size
+	pub fn check(&mut self, kind: &str, size: usize, now: f64, limits: &RateLimits) -> Verdict {
+		self.stats.messages += 1;
+		self.stats.bytes += size as u64;
+
+		// Nothing is taken unless every bucket allows it, a message that is dropped doesn't count against the others
+		let size = size as f64;
+		let allowed = !self.is_throttled(now)
+			&& self.total.0.has(1.0, now)
+			&& self.total.1.has(size, now)
+			&& self.kinds.iter_mut().find(|k| k.0 == kind).map_or(true, |k| k.1.has(1.0, now) && k.2.has(size, now));
+		if allowed {
+			self.total.0.take(1.0, now);
+			self.total.1.take(size, now);
+			if let Some(bucket) = self.kinds.iter_mut().find(|k| k.0 == kind) {
+				bucket.1.take(1.0, now);
+				bucket.2.take(size, now);
+			}
+			return Verdict::Allow;
+		}
+
+		self.stats.dropped += 1;
+		if self.is_throttled(now) {
+			// Whatever was read together with the message that went over the limit
+			return Verdict::Throttle;
+		}
+		self.strikes.retain(|t| now - *t < STRIKE_WINDOW);
+		self.strikes.push(now);
+		if self.strikes.len() > limits.strikes {
+			self.stats.disconnected += 1;
+			return Verdict::Disconnect;
+		}
+		self.stats.throttled += 1;
+		self.throttled_until = now + THROTTLE_TIME;
+		Verdict::Throttle
+	}
+}
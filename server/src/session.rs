@@ -0,0 +1,23 @@
+use rand::{OsRng, Rng};
+use crypto::util::fixed_time_eq;
+use std::io;
+
+/// The default amount of seconds a player stays in the world after its client lost the connection
+/// The client can resume the player with its session token within this time, after that the player logs out
+pub const DEFAULT_SESSION_GRACE_PERIOD: f64 = 30.0;
+
+// Enough that guessing the token of someone else is hopeless
+const TOKEN_BYTES: usize = 16;
+
+/// A new random session token, as hexadecimal
+pub fn new_token() -> io::Result<String> {
+	let mut rng = try!(OsRng::new());
+	let mut bytes = [0u8; TOKEN_BYTES];
+	rng.fill_bytes(&mut bytes);
+	Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Compare two tokens in constant time, so the response time doesn't tell how much of a guess was right
+pub fn token_matches(token: &str, guess: &str) -> bool {
+	token.len() == guess.len() && fixed_time_eq(token.as_bytes(), guess.as_bytes())
+}
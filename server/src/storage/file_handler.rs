@@ -0,0 +1,232 @@
+use shared::User;
+use password::{UserPassword, LegacyUserPassword};
+use world::WorldState;
+use storage::{Storage, PersistenceError, WorldEvent};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use bincode::rustc_serialize::{encode, decode_from};
+use bincode::SizeLimit;
+use byteorder::{BigEndian, ByteOrder};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use rustc_serialize::{Encodable, Decodable};
+
+const USERS_FILE: &'static str = "users.dat";
+const WORLD_FILE: &'static str = "world.dat";
+const EVENTS_FILE: &'static str = "events.log";
+const CHUNK_DIRECTORY: &'static str = "chunks";
+
+// Every file starts with MAGIC, the format version, the length of the data and a SHA-256 of the data
+// Files without the magic bytes are from before the header existed, and are treated as version 0
+// Version 2 added the format of the passwords to the users
+const MAGIC: &'static [u8; 4] = b"RGSV";
+const FORMAT_VERSION: u32 = 2;
+const HEADER_SIZE: usize = 4 + 4 + 8 + 32;
+
+// The amount of previous saves that are kept around, as users.dat.1 (newest) to users.dat.3 (oldest)
+const BACKUP_COUNT: u32 = 3;
+
+/// Stores the state of the server as bincode files in a directory
+/// Saves are atomic: the data is written to a temporary file, synced to disk and then renamed over the old file
+/// Events are appended to a single log file, each event prefixed with its length
+pub struct FileHandler {
+	directory: PathBuf,
+}
+
+impl FileHandler {
+	pub fn new<P: AsRef<Path>>(directory: P) -> FileHandler {
+		FileHandler {
+			directory: directory.as_ref().to_path_buf(),
+		}
+	}
+
+	// The format version and the data of a file, after checking the header
+	fn load_payload<P: AsRef<Path>>(&self, name: P) -> Result<Option<(u32, Vec<u8>)>, PersistenceError> {
+		let path = self.directory.join(name);
+		let mut file = match File::open(&path) {
+			Ok(f) => f,
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(PersistenceError::Io(e))
+		};
+		let mut data = Vec::new();
+		try!(file.read_to_end(&mut data));
+
+		let (version, payload) = try!(read_header(&data));
+		if version > FORMAT_VERSION {
+			return Err(PersistenceError::UnsupportedVersion(version));
+		}
+		Ok(Some((version, payload.to_vec())))
+	}
+
+	// Only the users changed between versions so far, everything else loads from any version
+	fn load<T: Decodable, P: AsRef<Path>>(&self, name: P) -> Result<Option<T>, PersistenceError> {
+		match try!(self.load_payload(name)) {
+			Some((_, payload)) => Ok(Some(try!(decode_all(&payload)))),
+			None => Ok(None)
+		}
+	}
+
+	// name can contain directories, which are created when they don't exist yet
+	fn save<T: Encodable, P: AsRef<Path>>(&self, name: P, value: &T) -> Result<(), PersistenceError> {
+		let payload = match encode(value, SizeLimit::Infinite) {
+			Ok(p) => p,
+			Err(_) => return Err(PersistenceError::CouldNotEncode)
+		};
+		let path = self.directory.join(name);
+		let directory = match path.parent() {
+			Some(d) => d.to_path_buf(),
+			None => self.directory.clone()
+		};
+		try!(fs::create_dir_all(&directory));
+		let temp_path = with_suffix(&path, "tmp");
+
+		{
+			let mut file = try!(OpenOptions::new().write(true).create(true).truncate(true).open(&temp_path));
+			try!(file.write_all(&write_header(&payload)));
+			try!(file.write_all(&payload));
+			try!(file.sync_all());
+		}
+
+		try!(rotate_backups(&path));
+		try!(fs::rename(&temp_path, &path));
+		try!(sync_directory(&directory));
+		Ok(())
+	}
+}
+
+impl Storage for FileHandler {
+	fn load_users(&mut self) -> Result<Vec<(User, UserPassword)>, PersistenceError> {
+		// Add a branch here that converts the old users, every time their layout changes
+		match try!(self.load_payload(USERS_FILE)) {
+			Some((version, ref payload)) if version < 2 => {
+				let users: Vec<(User, LegacyUserPassword)> = try!(decode_all(payload));
+				Ok(users.into_iter().map(|(user, password)| (user, password.upgrade())).collect())
+			},
+			Some((_, ref payload)) => decode_all(payload),
+			None => Ok(Vec::new())
+		}
+	}
+
+	fn save_users(&mut self, users: &Vec<(User, UserPassword)>) -> Result<(), PersistenceError> {
+		self.save(USERS_FILE, users)
+	}
+
+	fn load_world(&mut self) -> Result<Option<WorldState>, PersistenceError> {
+		self.load(WORLD_FILE)
+	}
+
+	fn save_world(&mut self, world: &WorldState) -> Result<(), PersistenceError> {
+		self.save(WORLD_FILE, world)
+	}
+
+	fn load_chunk(&mut self, x: i32, z: i32) -> Result<Option<Vec<u8>>, PersistenceError> {
+		self.load(chunk_path(x, z))
+	}
+
+	fn save_chunk(&mut self, x: i32, z: i32, data: &[u8]) -> Result<(), PersistenceError> {
+		self.save(chunk_path(x, z), &data.to_vec())
+	}
+
+	fn append_event(&mut self, event: &WorldEvent) -> Result<(), PersistenceError> {
+		let record = match encode(&(::time::get_time().sec, event), SizeLimit::Infinite) {
+			Ok(r) => r,
+			Err(_) => return Err(PersistenceError::CouldNotEncode)
+		};
+		let mut length = [0u8; 4];
+		BigEndian::write_u32(&mut length, record.len() as u32);
+
+		try!(fs::create_dir_all(&self.directory));
+		let mut file = try!(OpenOptions::new().append(true).create(true).open(self.directory.join(EVENTS_FILE)));
+		// Write it in one go, so a crash can at most cut off the last event
+		let mut data = length.to_vec();
+		data.extend_from_slice(&record);
+		try!(file.write_all(&data));
+		Ok(())
+	}
+}
+
+fn chunk_path(x: i32, z: i32) -> PathBuf {
+	Path::new(CHUNK_DIRECTORY).join(format!("{}_{}.dat", x, z))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+	let mut name = path.as_os_str().to_os_string();
+	name.push(".");
+	name.push(suffix);
+	PathBuf::from(name)
+}
+
+// Shift every backup one place and copy the current file to the newest backup
+// The current file is copied instead of renamed, so there is always a complete file at the real path
+fn rotate_backups(path: &Path) -> Result<(), PersistenceError> {
+	if !path.exists() {
+		return Ok(());
+	}
+	for i in (1..BACKUP_COUNT).rev() {
+		let from = with_suffix(path, &i.to_string());
+		if from.exists() {
+			try!(fs::rename(&from, with_suffix(path, &(i + 1).to_string())));
+		}
+	}
+	try!(fs::copy(path, with_suffix(path, "1")));
+	Ok(())
+}
+
+fn write_header(payload: &[u8]) -> Vec<u8> {
+	let mut header = vec![0u8; HEADER_SIZE];
+	header[0..4].copy_from_slice(MAGIC);
+	BigEndian::write_u32(&mut header[4..8], FORMAT_VERSION);
+	BigEndian::write_u64(&mut header[8..16], payload.len() as u64);
+	checksum(payload, &mut header[16..48]);
+	header
+}
+
+fn read_header(data: &[u8]) -> Result<(u32, &[u8]), PersistenceError> {
+	if data.len() < MAGIC.len() || &data[0..4] != MAGIC {
+		return Ok((0, data));
+	}
+	if data.len() < HEADER_SIZE {
+		return Err(PersistenceError::Corrupt("The header is incomplete".to_string()));
+	}
+	let version = BigEndian::read_u32(&data[4..8]);
+	let length = BigEndian::read_u64(&data[8..16]);
+	let payload = &data[HEADER_SIZE..];
+	if payload.len() as u64 != length {
+		return Err(PersistenceError::Corrupt(format!("Expected {} bytes of data, found {}", length, payload.len())));
+	}
+	let mut expected = [0u8; 32];
+	checksum(payload, &mut expected);
+	if &expected[..] != &data[16..48] {
+		return Err(PersistenceError::Corrupt("The checksum does not match".to_string()));
+	}
+	Ok((version, payload))
+}
+
+// Version 0 files have no checksum, so anything without the magic bytes ends up here, damaged or not
+// The decoder can't read past the data, and the data has to be used up, otherwise it wasn't a save
+fn decode_all<T: Decodable>(payload: &[u8]) -> Result<T, PersistenceError> {
+	let mut reader = payload;
+	let value = try!(decode_from(&mut reader, SizeLimit::Bounded(payload.len() as u64)));
+	if !reader.is_empty() {
+		return Err(PersistenceError::Corrupt(format!("{} bytes left after the data", reader.len())));
+	}
+	Ok(value)
+}
+
+fn checksum(payload: &[u8], output: &mut [u8]) {
+	let mut hasher = Sha256::new();
+	hasher.input(payload);
+	hasher.result(output);
+}
+
+// The rename is only durable once the directory itself is synced
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> io::Result<()> {
+	try!(File::open(directory)).sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_: &Path) -> io::Result<()> {
+	Ok(())
+}
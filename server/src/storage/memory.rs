@@ -0,0 +1,86 @@
+use shared::User;
+use password::UserPassword;
+use world::WorldState;
+use storage::{Storage, PersistenceError, WorldEvent};
+use bincode::rustc_serialize::{encode, decode};
+use bincode::SizeLimit;
+use rustc_serialize::{Encodable, Decodable};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct MemoryData {
+	users: Option<Vec<u8>>,
+	world: Option<Vec<u8>>,
+	chunks: HashMap<(i32, i32), Vec<u8>>,
+	events: Vec<WorldEvent>,
+}
+
+/// Keeps everything in memory, for tests and throwaway servers
+/// Clones share the same data, so a test can keep a clone around to look at what was stored
+/// Values are stored encoded, so loading gives a fresh copy just like the other backends
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+	data: Rc<RefCell<MemoryData>>,
+}
+
+impl MemoryStorage {
+	pub fn new() -> MemoryStorage {
+		MemoryStorage::default()
+	}
+
+	/// All the events that were appended so far, oldest first
+	pub fn events(&self) -> Vec<WorldEvent> {
+		self.data.borrow().events.clone()
+	}
+
+	pub fn chunk_count(&self) -> usize {
+		self.data.borrow().chunks.len()
+	}
+}
+
+fn to_bytes<T: Encodable>(value: &T) -> Result<Vec<u8>, PersistenceError> {
+	encode(value, SizeLimit::Infinite).map_err(|_| PersistenceError::CouldNotEncode)
+}
+
+fn from_bytes<T: Decodable>(data: &Option<Vec<u8>>) -> Result<Option<T>, PersistenceError> {
+	match *data {
+		Some(ref d) => Ok(Some(try!(decode(d)))),
+		None => Ok(None)
+	}
+}
+
+impl Storage for MemoryStorage {
+	fn load_users(&mut self) -> Result<Vec<(User, UserPassword)>, PersistenceError> {
+		Ok(try!(from_bytes(&self.data.borrow().users)).unwrap_or_else(Vec::new))
+	}
+
+	fn save_users(&mut self, users: &Vec<(User, UserPassword)>) -> Result<(), PersistenceError> {
+		self.data.borrow_mut().users = Some(try!(to_bytes(users)));
+		Ok(())
+	}
+
+	fn load_world(&mut self) -> Result<Option<WorldState>, PersistenceError> {
+		from_bytes(&self.data.borrow().world)
+	}
+
+	fn save_world(&mut self, world: &WorldState) -> Result<(), PersistenceError> {
+		self.data.borrow_mut().world = Some(try!(to_bytes(world)));
+		Ok(())
+	}
+
+	fn load_chunk(&mut self, x: i32, z: i32) -> Result<Option<Vec<u8>>, PersistenceError> {
+		Ok(self.data.borrow().chunks.get(&(x, z)).cloned())
+	}
+
+	fn save_chunk(&mut self, x: i32, z: i32, data: &[u8]) -> Result<(), PersistenceError> {
+		self.data.borrow_mut().chunks.insert((x, z), data.to_vec());
+		Ok(())
+	}
+
+	fn append_event(&mut self, event: &WorldEvent) -> Result<(), PersistenceError> {
+		self.data.borrow_mut().events.push(event.clone());
+		Ok(())
+	}
+}
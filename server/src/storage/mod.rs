@@ -0,0 +1,97 @@
+mod file_handler;
+mod memory;
+mod sqlite;
+
+pub use self::file_handler::FileHandler;
+pub use self::memory::MemoryStorage;
+pub use self::sqlite::SqliteStorage;
+
+use shared::User;
+use password::{UserPassword, migrate_plaintext_passwords};
+use world::WorldState;
+use bincode::rustc_serialize::DecodingError;
+use rusqlite;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum PersistenceError {
+	Io(io::Error),
+	/// The stored data exists but is damaged
+	Corrupt(String),
+	/// The data was written by a newer version of the server
+	UnsupportedVersion(u32),
+	CouldNotEncode,
+	Database(rusqlite::Error),
+}
+
+impl From<io::Error> for PersistenceError {
+	fn from(err: io::Error) -> PersistenceError {
+		PersistenceError::Io(err)
+	}
+}
+
+impl From<DecodingError> for PersistenceError {
+	fn from(err: DecodingError) -> PersistenceError {
+		PersistenceError::Corrupt(format!("{:?}", err))
+	}
+}
+
+impl From<rusqlite::Error> for PersistenceError {
+	fn from(err: rusqlite::Error) -> PersistenceError {
+		PersistenceError::Database(err)
+	}
+}
+
+/// Something that happened in the world that is worth keeping a record of
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug, Clone)]
+pub enum WorldEvent {
+	Registered { account_id: u32, name: String },
+	LoggedIn { account_id: u32 },
+	LoggedOut { account_id: u32 },
+}
+
+/// Where the server keeps everything that has to survive a restart
+pub trait Storage {
+	/// Returns an empty list if no users were saved yet
+	fn load_users(&mut self) -> Result<Vec<(User, UserPassword)>, PersistenceError>;
+	fn save_users(&mut self, users: &Vec<(User, UserPassword)>) -> Result<(), PersistenceError>;
+	/// Returns None if the world was never saved
+	fn load_world(&mut self) -> Result<Option<WorldState>, PersistenceError>;
+	fn save_world(&mut self, world: &WorldState) -> Result<(), PersistenceError>;
+	/// Chunks are stored as they are, the storage doesn't need to know what is in them
+	fn load_chunk(&mut self, x: i32, z: i32) -> Result<Option<Vec<u8>>, PersistenceError>;
+	fn save_chunk(&mut self, x: i32, z: i32, data: &[u8]) -> Result<(), PersistenceError>;
+	/// Events are only ever added, never changed
+	fn append_event(&mut self, event: &WorldEvent) -> Result<(), PersistenceError>;
+}
+
+/// Which Storage the server uses
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageBackend {
+	/// Bincode files in the given directory
+	File(PathBuf),
+	/// An SQLite database at the given path
+	Sqlite(PathBuf),
+	/// Nothing is written to disk, everything is lost when the server stops
+	Memory,
+}
+
+impl StorageBackend {
+	pub fn open(&self) -> Result<Box<Storage>, PersistenceError> {
+		Ok(match *self {
+			StorageBackend::File(ref directory) => Box::new(FileHandler::new(directory)),
+			StorageBackend::Sqlite(ref path) => Box::new(try!(SqliteStorage::open(path))),
+			StorageBackend::Memory => Box::new(MemoryStorage::new()),
+		})
+	}
+}
+
+/// Load the users and hash every password that is still stored as plain text
+pub fn load_users(storage: &mut Storage) -> Result<Vec<(User, UserPassword)>, PersistenceError> {
+	let mut users = try!(storage.load_users());
+	if try!(migrate_plaintext_passwords(&mut users)) {
+		try!(storage.save_users(&users));
+	}
+	Ok(users)
+}
@@ -0,0 +1,162 @@
+use shared::User;
+use password::{UserPassword, PasswordFormat};
+use world::WorldState;
+use storage::{Storage, PersistenceError, WorldEvent};
+use bincode::rustc_serialize::{encode, decode};
+use bincode::SizeLimit;
+use rusqlite::{Connection, OptionalExtension};
+use std::path::Path;
+use time;
+
+// Every statement uses IF NOT EXISTS, so this can run on every start
+const SCHEMA: &'static str = "
+	CREATE TABLE IF NOT EXISTS users (
+		id INTEGER PRIMARY KEY,
+		name TEXT NOT NULL,
+		password TEXT NOT NULL,
+		position_x REAL NOT NULL,
+		position_y REAL NOT NULL,
+		position_z REAL NOT NULL,
+		rotation_x REAL NOT NULL,
+		rotation_y REAL NOT NULL,
+		rotation_z REAL NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS world (
+		id INTEGER PRIMARY KEY CHECK (id = 0),
+		data BLOB NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS chunks (
+		x INTEGER NOT NULL,
+		z INTEGER NOT NULL,
+		data BLOB NOT NULL,
+		PRIMARY KEY (x, z)
+	);
+	CREATE TABLE IF NOT EXISTS events (
+		id INTEGER PRIMARY KEY AUTOINCREMENT,
+		time INTEGER NOT NULL,
+		data BLOB NOT NULL
+	);
+";
+
+// Changes to the schema after the first version, MIGRATIONS[i] brings a database from user_version i to i + 1
+// Databases from before password_format only ever had hashes in them, plain text passwords were hashed when they were read from files
+const MIGRATIONS: &'static [&'static str] = &[
+	"ALTER TABLE users ADD COLUMN password_format INTEGER NOT NULL DEFAULT 1;",
+];
+
+/// Stores the state of the server in an SQLite database
+/// Users get a row each, so the database can be inspected and edited with any SQLite tool
+pub struct SqliteStorage {
+	connection: Connection,
+}
+
+impl SqliteStorage {
+	/// Open or create the database, use ":memory:" for a database that only lives as long as this storage
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStorage, PersistenceError> {
+		let connection = try!(Connection::open(path));
+		try!(connection.execute_batch(SCHEMA));
+		let version: i64 = try!(connection.query_row("PRAGMA user_version", params![], |row| row.get(0)));
+		for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+			try!(connection.execute_batch(&format!("BEGIN; {} PRAGMA user_version = {}; COMMIT;", migration, i + 1)));
+		}
+		Ok(SqliteStorage {
+			connection: connection,
+		})
+	}
+}
+
+impl Storage for SqliteStorage {
+	fn load_users(&mut self) -> Result<Vec<(User, UserPassword)>, PersistenceError> {
+		let mut statement = try!(self.connection.prepare(
+			"SELECT id, name, password, position_x, position_y, position_z, rotation_x, rotation_y, rotation_z, password_format FROM users ORDER BY id"
+		));
+		let rows = try!(statement.query_map(params![], |row| {
+			let id: i64 = try!(row.get(0));
+			let position: (f64, f64, f64) = (try!(row.get(3)), try!(row.get(4)), try!(row.get(5)));
+			let rotation: (f64, f64, f64) = (try!(row.get(6)), try!(row.get(7)), try!(row.get(8)));
+			let password: String = try!(row.get(2));
+			let format: i64 = try!(row.get(9));
+			Ok((User {
+				id: id as u32,
+				name: try!(row.get(1)),
+				position: [position.0 as f32, position.1 as f32, position.2 as f32],
+				rotation: [rotation.0 as f32, rotation.1 as f32, rotation.2 as f32],
+			}, password, format))
+		}));
+		let mut users = Vec::new();
+		for row in rows {
+			let (user, password, format) = try!(row);
+			let format = match format {
+				0 => PasswordFormat::PlainText,
+				1 => PasswordFormat::Pbkdf2,
+				f => return Err(PersistenceError::Corrupt(format!("Unknown password format {} for user {}", f, user.id)))
+			};
+			let password = UserPassword {
+				user_id: user.id,
+				format: format,
+				password: password,
+			};
+			users.push((user, password));
+		}
+		Ok(users)
+	}
+
+	fn save_users(&mut self, users: &Vec<(User, UserPassword)>) -> Result<(), PersistenceError> {
+		// Replace everything in one transaction, so a crash leaves either the old or the new users
+		let transaction = try!(self.connection.transaction());
+		try!(transaction.execute("DELETE FROM users", params![]));
+		for &(ref user, ref password) in users {
+			try!(transaction.execute(
+				"INSERT INTO users (id, name, password, position_x, position_y, position_z, rotation_x, rotation_y, rotation_z, password_format)
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+				params![
+					user.id as i64, user.name, password.password,
+					user.position[0] as f64, user.position[1] as f64, user.position[2] as f64,
+					user.rotation[0] as f64, user.rotation[1] as f64, user.rotation[2] as f64,
+					password.format as i64
+				]
+			));
+		}
+		try!(transaction.commit());
+		Ok(())
+	}
+
+	fn load_world(&mut self) -> Result<Option<WorldState>, PersistenceError> {
+		let data: Option<Vec<u8>> = try!(self.connection.query_row(
+			"SELECT data FROM world WHERE id = 0", params![], |row| row.get(0)
+		).optional());
+		match data {
+			Some(d) => Ok(Some(try!(decode(&d)))),
+			None => Ok(None)
+		}
+	}
+
+	fn save_world(&mut self, world: &WorldState) -> Result<(), PersistenceError> {
+		let data = match encode(world, SizeLimit::Infinite) {
+			Ok(d) => d,
+			Err(_) => return Err(PersistenceError::CouldNotEncode)
+		};
+		try!(self.connection.execute("INSERT OR REPLACE INTO world (id, data) VALUES (0, ?1)", params![data]));
+		Ok(())
+	}
+
+	fn load_chunk(&mut self, x: i32, z: i32) -> Result<Option<Vec<u8>>, PersistenceError> {
+		Ok(try!(self.connection.query_row(
+			"SELECT data FROM chunks WHERE x = ?1 AND z = ?2", params![x, z], |row| row.get(0)
+		).optional()))
+	}
+
+	fn save_chunk(&mut self, x: i32, z: i32, data: &[u8]) -> Result<(), PersistenceError> {
+		try!(self.connection.execute("INSERT OR REPLACE INTO chunks (x, z, data) VALUES (?1, ?2, ?3)", params![x, z, data]));
+		Ok(())
+	}
+
+	fn append_event(&mut self, event: &WorldEvent) -> Result<(), PersistenceError> {
+		let data = match encode(event, SizeLimit::Infinite) {
+			Ok(d) => d,
+			Err(_) => return Err(PersistenceError::CouldNotEncode)
+		};
+		try!(self.connection.execute("INSERT INTO events (time, data) VALUES (?1, ?2)", params![time::get_time().sec, data]));
+		Ok(())
+	}
+}
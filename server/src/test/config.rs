@@ -0,0 +1,125 @@
+use config::Config;
+use storage::StorageBackend;
+use log::LevelFilter;
+use rate_limit::RateLimit;
+use std::path::PathBuf;
+
+fn args(args: &[&str]) -> Vec<String> {
+	args.iter().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn example_file_is_valid() {
+	let mut config = Config::new();
+	config.apply_toml(include_str!("../../server.example.toml")).unwrap();
+	config.validate().unwrap();
+	// The example documents the defaults, so it shouldn't change anything
+	assert_eq!(config, Config::new());
+}
+
+#[test]
+fn file_overrides_defaults() {
+	let mut config = Config::new();
+	config.apply_toml("
+		[network]
+		port = 9000
+		[world]
+		tick_rate = 20
+		[storage]
+		backend = \"sqlite\"
+		path = \"saves\"
+		[log]
+		level = \"debug\"
+	").unwrap();
+	assert_eq!(config.port, 9000);
+	assert_eq!(config.tick_rate, 20);
+	assert_eq!(config.log_level, LevelFilter::Debug);
+	assert_eq!(config.storage_backend(), Ok(StorageBackend::Sqlite(PathBuf::from("saves").join("server.db"))));
+}
+
+#[test]
+fn arguments_override_file() {
+	let mut config = Config::new();
+	config.apply_toml("[network]\nport = 9000").unwrap();
+	config.apply_arg("--port", "9001").unwrap();
+	assert_eq!(config.port, 9001);
+}
+
+#[test]
+fn missing_default_file_is_fine() {
+	let config = Config::from_args(&args(&["--tick-rate", "30"])).unwrap().unwrap();
+	assert_eq!(config.tick_rate, 30);
+}
+
+#[test]
+fn missing_given_file_is_an_error() {
+	assert!(Config::from_args(&args(&["--config", "does-not-exist.toml"])).is_err());
+}
+
+#[test]
+fn help_returns_none() {
+	assert_eq!(Config::from_args(&args(&["--port", "1", "--help"])), Ok(None));
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+	let mut config = Config::new();
+	assert!(config.apply_toml("[network]\nport = 70000").is_err());
+	assert!(config.apply_toml("[network]\nport = \"80\"").is_err());
+	assert!(config.apply_toml("[network]\nprot = 80").is_err());
+	assert!(config.apply_toml("[netwerk]\nport = 80").is_err());
+	assert!(config.apply_toml("port = 80").is_err());
+	assert!(config.apply_toml("[network\nport = 80").is_err());
+	assert!(config.apply_arg("--port", "eighty").is_err());
+	assert!(config.apply_arg("--log-level", "loud").is_err());
+	assert!(config.apply_arg("--unknown", "1").is_err());
+	assert!(Config::from_args(&args(&["--port"])).is_err());
+
+	config.tick_rate = 0;
+	assert!(config.validate().is_err());
+	let mut config = Config::new();
+	config.storage = "floppy".to_string();
+	assert!(config.validate().is_err());
+	let mut config = Config::new();
+	config.max_outgoing_size = config.max_frame_size - 1;
+	assert!(config.validate().is_err());
+	let mut config = Config::new();
+	config.apply_arg("--interest-radius", "0").unwrap();
+	assert!(config.validate().is_err());
+	let mut config = Config::new();
+	config.apply_arg("--max-connections", "1").unwrap();
+	assert!(config.validate().is_err());
+	let mut config = Config::new();
+	assert!(config.apply_toml("[network]\nhandshake_timeout = -1").is_err());
+	let mut config = Config::new();
+	config.apply_toml("[world]\nsession_grace_period = -1").unwrap();
+	assert!(config.validate().is_err());
+}
+
+#[test]
+fn rate_limits_from_file() {
+	let mut config = Config::new();
+	config.apply_toml("
+		[rate_limit]
+		messages_per_second = 100
+		strikes = 3
+		[rate_limit.Login]
+		messages_per_second = 0.5
+		[rate_limit.Input]
+		bytes_per_second = 2048
+	").unwrap();
+	let limits = &config.rate_limits;
+	assert_eq!(limits.total.messages_per_second, 100.0);
+	assert_eq!(limits.strikes, 3);
+
+	let kind = |name: &str| limits.kinds.iter().find(|k| k.0 == name).map(|k| k.1.clone()).unwrap();
+	// A kind that already had a limit keeps what isn't set
+	assert_eq!(kind("Login"), RateLimit::new(0.5, 1024.0));
+	// A new kind starts from the total
+	assert_eq!(kind("Input"), RateLimit::new(100.0, 2048.0));
+
+	assert!(config.apply_toml("[rate_limit]\nmessages_per_second = 0").is_err());
+	assert!(config.apply_toml("[rate_limit.Login]\nmessages = 1").is_err());
+	assert!(config.apply_toml("[rate_limit.login]\nmessages_per_second = 1").is_err());
+	assert!(config.apply_toml("[rate_limit.Logon]\nmessages_per_second = 1").is_err());
+}
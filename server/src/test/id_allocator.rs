@@ -0,0 +1,61 @@
+use id_allocator::{IdAllocator, MAX_GENERATION};
+
+#[test]
+fn ids_are_unique_and_never_0() {
+	let mut ids = IdAllocator::new();
+	let allocated: Vec<u32> = (0..100).map(|_| ids.allocate()).collect();
+	for (i, id) in allocated.iter().enumerate() {
+		assert!(*id != 0);
+		assert!(ids.is_alive(*id));
+		assert!(!allocated[i + 1..].contains(id));
+	}
+}
+
+#[test]
+fn freed_slots_are_reused_with_a_new_generation() {
+	let mut ids = IdAllocator::new();
+	let first = ids.allocate();
+	let second = ids.allocate();
+	ids.free(first);
+	assert!(!ids.is_alive(first));
+
+	let reused = ids.allocate();
+	assert!(reused != first);
+	assert!(reused != second);
+	// Same slot, so the lower bits are the same
+	assert_eq!(reused & 0xFFFFF, first & 0xFFFFF);
+	assert_eq!(reused >> 20, (first >> 20) + 1);
+}
+
+#[test]
+fn stale_ids_are_rejected() {
+	let mut ids = IdAllocator::new();
+	let stale = ids.allocate();
+	ids.free(stale);
+	let owner = ids.allocate();
+	assert!(!ids.is_alive(stale));
+
+	// Freeing the old id again doesn't take the slot away from its new owner
+	ids.free(stale);
+	assert!(ids.is_alive(owner));
+	assert!(ids.allocate() != owner);
+}
+
+#[test]
+fn generations_wrap_around() {
+	let mut ids = IdAllocator::new();
+	let first = ids.allocate();
+	let mut id = first;
+	for _ in 1..MAX_GENERATION {
+		ids.free(id);
+		id = ids.allocate();
+		assert!(id != first);
+	}
+	assert_eq!(id >> 20, MAX_GENERATION);
+
+	// The generation after the last one is 1 again, 0 is skipped so ids are never 0
+	ids.free(id);
+	let wrapped = ids.allocate();
+	assert!(wrapped != 0);
+	assert_eq!(wrapped, first);
+}
@@ -0,0 +1,58 @@
+use interest::{SpatialGrid, LEAVE_MARGIN, update_visible};
+
+fn sorted(mut uids: Vec<u32>) -> Vec<u32> {
+	uids.sort();
+	uids
+}
+
+#[test]
+fn query_finds_entities_in_range() {
+	let mut grid = SpatialGrid::new(10.0);
+	grid.insert(1, [0.0, 0.0, 0.0]);
+	grid.insert(2, [9.0, 0.0, 0.0]);
+	grid.insert(3, [-7.0, 0.0, -7.0]);
+	grid.insert(4, [25.0, 0.0, 0.0]);
+	grid.insert(5, [0.0, 500.0, 3.0]);
+
+	assert_eq!(sorted(grid.query([0.0, 0.0, 0.0], 10.0)), vec![1, 2, 3, 5]);
+	assert_eq!(sorted(grid.query([20.0, 0.0, 0.0], 10.0)), vec![4]);
+	assert_eq!(sorted(grid.query([1000.0, 0.0, 1000.0], 10.0)), Vec::<u32>::new());
+}
+
+#[test]
+fn query_with_a_radius_bigger_than_the_cells() {
+	let mut grid = SpatialGrid::new(1.0);
+	for i in 0..20 {
+		grid.insert(i, [i as f32, 0.0, -(i as f32)]);
+	}
+	let distance = (2.0f32).sqrt();
+	assert_eq!(grid.query([0.0, 0.0, 0.0], distance * 5.5).len(), 6);
+}
+
+#[test]
+fn entering_and_leaving() {
+	let mut grid = SpatialGrid::new(10.0);
+	grid.insert(1, [0.0, 0.0, 0.0]);
+	grid.insert(2, [5.0, 0.0, 0.0]);
+
+	let (visible, entered, left) = update_visible(&grid, [0.0, 0.0, 0.0], 10.0, 1, &[3]);
+	assert_eq!(visible, vec![2]);
+	assert_eq!(entered, vec![2]);
+	assert_eq!(left, vec![3]);
+}
+
+#[test]
+fn visible_entities_get_a_margin_before_they_leave() {
+	let mut grid = SpatialGrid::new(10.0);
+	grid.insert(2, [10.0 * (1.0 + LEAVE_MARGIN) / 2.0, 0.0, 0.0]);
+
+	// Just outside the radius, so it doesn't enter
+	let (visible, entered, _) = update_visible(&grid, [0.0, 0.0, 0.0], 10.0, 1, &[]);
+	assert!(visible.is_empty());
+	assert!(entered.is_empty());
+
+	// But if it was already visible it stays
+	let (visible, _, left) = update_visible(&grid, [0.0, 0.0, 0.0], 10.0, 1, &[2]);
+	assert_eq!(visible, vec![2]);
+	assert!(left.is_empty());
+}
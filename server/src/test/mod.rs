@@ -0,0 +1,10 @@
+mod config;
+mod id_allocator;
+mod interest;
+mod movement;
+mod network;
+mod password;
+mod rate_limit;
+mod session;
+mod storage;
+pub mod world;
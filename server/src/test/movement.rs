@@ -0,0 +1,53 @@
+use movement::{MovementValidator, Violation, VIOLATION_WINDOW};
+use shared::TIMESTEP;
+
+#[test]
+fn walking_at_the_simulation_rate_is_fine() {
+	let mut validator = MovementValidator::new(0.0);
+	for i in 1..500 {
+		assert_eq!(validator.check(i as f64 * TIMESTEP, 0.001), Ok(()));
+	}
+	assert_eq!(validator.violation_count(500.0 * TIMESTEP), 0);
+}
+
+#[test]
+fn bursts_within_the_tolerance_are_fine() {
+	let mut validator = MovementValidator::new(0.0);
+	// A second of inputs that were stuck in the network all arrive at once
+	for _ in 0..(1.0 / TIMESTEP) as u32 {
+		assert_eq!(validator.check(1.0, 0.505), Ok(()));
+	}
+}
+
+#[test]
+fn sending_inputs_too_fast_is_rejected() {
+	let mut validator = MovementValidator::new(0.0);
+	let mut rejected = 0;
+	for _ in 0..100 {
+		match validator.check(0.0, 0.505) {
+			Ok(()) => {},
+			Err(Violation::TooFast { .. }) => rejected += 1
+		}
+	}
+	// Half a second of steps is accepted, the rest is too much
+	assert_eq!(100 - rejected, 25);
+	assert_eq!(validator.violation_count(0.0), rejected);
+}
+
+#[test]
+fn standing_still_doesnt_save_up_time() {
+	let mut validator = MovementValidator::new(0.0);
+	let accepted = (0..100).filter(|_| validator.check(60.0, 0.505).is_ok()).count();
+	// The player starts at most the tolerance behind and can get the tolerance ahead, which is 1.01 seconds of steps
+	assert_eq!(accepted, 51);
+}
+
+#[test]
+fn old_violations_are_forgiven() {
+	let mut validator = MovementValidator::new(0.0);
+	while validator.check(1.0, 0.5).is_ok() {}
+	while validator.check(2.0, 0.5).is_ok() {}
+	assert_eq!(validator.violation_count(2.0), 2);
+	assert_eq!(validator.violation_count(1.5 + VIOLATION_WINDOW), 1);
+	assert_eq!(validator.violation_count(3.0 + VIOLATION_WINDOW), 0);
+}
@@ -0,0 +1,308 @@
+use std::thread;
+use std::time::Duration;
+
+use shared::{ClientSocket, NetworkMessage, PlayerInput, hello};
+use network::{ServerSocket, ServerError, ClientHandler, Removal};
+use storage::{Storage, MemoryStorage};
+use test::world::world;
+use world::{World, WorldState};
+use shutdown;
+
+fn server() -> ServerSocket {
+	ServerSocket::create("127.0.0.1", 0).unwrap()
+}
+
+fn port(server: &ServerSocket) -> u16 {
+	server.local_addr().unwrap().port()
+}
+
+// A client that connected and said Hello
+fn connect(server: &ServerSocket) -> ClientSocket {
+	let mut client = ClientSocket::create("127.0.0.1", port(server));
+	client.connect().unwrap();
+	client.send(hello("test")).unwrap();
+	client
+}
+
+// Let the server listen a few times, so it gets to see everything that was sent
+fn listen(server: &mut ServerSocket, world: &mut World) {
+	for _ in 0..5 {
+		server.listen(world).unwrap();
+		server.flush();
+		thread::sleep(Duration::from_millis(10));
+	}
+}
+
+// Everything the client received until the server closed the connection
+fn receive_until_closed(client: &mut ClientSocket) -> Vec<NetworkMessage> {
+	let mut received = Vec::new();
+	for _ in 0..100 {
+		match client.poll_messages() {
+			Ok(messages) => received.extend(messages),
+			Err(_) => break
+		}
+		thread::sleep(Duration::from_millis(10));
+	}
+	received
+}
+
+fn disconnect_reason(messages: &[NetworkMessage]) -> Option<String> {
+	messages.iter().filter_map(|m| match *m {
+		NetworkMessage::Disconnect { ref reason } => Some(reason.clone()),
+		_ => None
+	}).next()
+}
+
+#[test]
+fn clients_that_say_nothing_are_disconnected() {
+	let mut server = server();
+	let mut world = world();
+	server.handshake_timeout = 0.05;
+	let mut client = ClientSocket::create("127.0.0.1", port(&server));
+	client.connect().unwrap();
+	listen(&mut server, &mut world);
+	thread::sleep(Duration::from_millis(100));
+
+	listen(&mut server, &mut world);
+	assert!(server.clients.is_empty());
+	assert!(disconnect_reason(&receive_until_closed(&mut client)).is_some());
+}
+
+#[test]
+fn connections_are_limited() {
+	let mut server = server();
+	let mut world = world();
+	server.max_connections = 1;
+	let mut first = connect(&server);
+	listen(&mut server, &mut world);
+	let mut second = connect(&server);
+	listen(&mut server, &mut world);
+
+	assert_eq!(server.clients.len(), 1);
+	assert!(disconnect_reason(&receive_until_closed(&mut second)).is_some());
+	// The first one is still fine
+	assert!(first.poll_messages().is_ok());
+}
+
+#[test]
+fn clients_that_dont_answer_pings_time_out() {
+	let mut server = server();
+	let mut world = world();
+	server.max_missed_pings = 2;
+	let mut client = connect(&server);
+	listen(&mut server, &mut world);
+	assert_eq!(server.clients.len(), 1);
+
+	for i in 0..3 {
+		server.ping(i as f64);
+		listen(&mut server, &mut world);
+	}
+	assert!(server.clients.is_empty());
+	assert_eq!(disconnect_reason(&receive_until_closed(&mut client)), Some("Timed out".to_string()));
+}
+
+#[test]
+fn answering_pings_keeps_the_connection() {
+	let mut server = server();
+	let mut world = world();
+	server.max_missed_pings = 2;
+	let mut client = connect(&server);
+	listen(&mut server, &mut world);
+
+	for i in 0..5 {
+		server.ping(i as f64);
+		listen(&mut server, &mut world);
+		for message in client.poll_messages().unwrap() {
+			if let NetworkMessage::Ping { sequence, .. } = message {
+				client.send(NetworkMessage::Pong { sequence: sequence, time: 0.0 }).unwrap();
+			}
+		}
+		listen(&mut server, &mut world);
+	}
+	assert_eq!(server.clients.len(), 1);
+	assert_eq!(server.clients[0].pings.missed, 0);
+	assert!(server.clients[0].pings.rtt.is_some());
+}
+
+#[test]
+fn dropped_clients_hear_why() {
+	let mut server = server();
+	let mut world = world();
+	let mut client = connect(&server);
+	listen(&mut server, &mut world);
+	client.send(NetworkMessage::Welcome { protocol_version: 0, capabilities: Vec::new() }).unwrap();
+	listen(&mut server, &mut world);
+
+	assert!(server.clients.is_empty());
+	assert_eq!(disconnect_reason(&receive_until_closed(&mut client)), Some("The server did not expect a Welcome message".to_string()));
+}
+
+#[test]
+fn clients_can_leave() {
+	let mut server = server();
+	let mut world = world();
+	let mut client = connect(&server);
+	listen(&mut server, &mut world);
+	client.send(NetworkMessage::Disconnect { reason: "Bye".to_string() }).unwrap();
+	listen(&mut server, &mut world);
+	assert!(server.clients.is_empty());
+}
+
+#[test]
+fn only_lost_connections_keep_their_player() {
+	let mut server = server();
+	let mut world = world();
+	let mut lost = connect(&server);
+	let mut dropped = connect(&server);
+	listen(&mut server, &mut world);
+	lost.send(NetworkMessage::Register { username: "alice".to_string(), password: "password".to_string() }).unwrap();
+	dropped.send(NetworkMessage::Register { username: "bob".to_string(), password: "password".to_string() }).unwrap();
+	listen(&mut server, &mut world);
+	assert_eq!(world.players.len(), 2);
+
+	lost.disconnect();
+	dropped.send(NetworkMessage::SetPosition { uid: 0, position: [0.0, 0.0, 0.0], rotation: [0.0, 0.0, 0.0] }).unwrap();
+	listen(&mut server, &mut world);
+
+	assert!(server.clients.is_empty());
+	assert_eq!(world.players.len(), 1);
+	assert_eq!(world.players[0].user.name, "alice");
+	assert_eq!(world.players[0].client_id, None);
+}
+
+#[test]
+fn clients_get_answers_to_their_pings() {
+	let mut server = server();
+	let mut world = world();
+	let mut client = connect(&server);
+	listen(&mut server, &mut world);
+	client.send(NetworkMessage::Ping { sequence: 7, time: 0.0 }).unwrap();
+	listen(&mut server, &mut world);
+
+	thread::sleep(Duration::from_millis(50));
+	let pongs: Vec<u32> = client.poll_messages().unwrap().into_iter().filter_map(|m| match m {
+		NetworkMessage::Pong { sequence, .. } => Some(sequence),
+		_ => None
+	}).collect();
+	assert_eq!(pongs, vec![7]);
+}
+
+#[test]
+fn throttled_logins_are_answered() {
+	let mut server = server();
+	let mut world = world();
+	let mut client = connect(&server);
+	listen(&mut server, &mut world);
+	// The default limits allow a burst of two logins
+	for _ in 0..3 {
+		client.send(NetworkMessage::Login { username: "alice".to_string(), password: "wrong".to_string() }).unwrap();
+	}
+	listen(&mut server, &mut world);
+
+	thread::sleep(Duration::from_millis(50));
+	let results: Vec<NetworkMessage> = client.poll_messages().unwrap().into_iter()
+		.filter(|m| m.is_same_type_as(&NetworkMessage::LoginResult { error: None }))
+		.collect();
+	assert_eq!(results.len(), 3);
+	assert_eq!(results[2], NetworkMessage::LoginResult { error: Some("Too many attempts, please try again in a moment".to_string()) });
+}
+
+#[test]
+fn only_accepted_clients_are_told_about_the_shutdown() {
+	let mut server = server();
+	let mut world = world();
+	let mut accepted = connect(&server);
+	listen(&mut server, &mut world);
+	let mut pending = ClientSocket::create("127.0.0.1", port(&server));
+	pending.connect().unwrap();
+	listen(&mut server, &mut world);
+	assert_eq!(server.clients.len(), 1);
+
+	server.close_all(NetworkMessage::ServerShutdown, "Bye", 1.0);
+	assert!(receive_until_closed(&mut accepted).contains(&NetworkMessage::ServerShutdown));
+	let messages = receive_until_closed(&mut pending);
+	assert!(!messages.contains(&NetworkMessage::ServerShutdown));
+	assert_eq!(disconnect_reason(&messages), Some("Bye".to_string()));
+}
+
+#[test]
+fn shutting_down_saves_the_world_and_the_accounts() {
+	let mut server = server();
+	let storage = MemoryStorage::new();
+	let mut world = World::new(Box::new(storage.clone()), WorldState::new(), Vec::new());
+	let mut client = connect(&server);
+	// Still in the handshake when the server stops
+	let mut pending = ClientSocket::create("127.0.0.1", port(&server));
+	pending.connect().unwrap();
+	listen(&mut server, &mut world);
+	client.send(NetworkMessage::Register { username: "alice".to_string(), password: "password".to_string() }).unwrap();
+	client.send(NetworkMessage::Input { sequence: 1, input: PlayerInput { forward: 1, strafe: 0, rotation: [0.0, 0.0, 0.0] } }).unwrap();
+	listen(&mut server, &mut world);
+	let position = world.players[0].user.position;
+
+	shutdown(&mut world, &mut server);
+
+	let mut saved = storage.clone();
+	let users = saved.load_users().unwrap();
+	assert_eq!(users.len(), 1);
+	assert_eq!(users[0].0.position, position);
+	assert!(saved.load_world().unwrap().is_some());
+	assert!(receive_until_closed(&mut client).contains(&NetworkMessage::ServerShutdown));
+	assert_eq!(disconnect_reason(&receive_until_closed(&mut pending)), Some("The server is shutting down".to_string()));
+}
+
+// A handler that can't take any more clients
+struct FullHandler;
+
+impl ClientHandler for FullHandler {
+	fn client_created(&mut self, _: &mut ClientSocket) -> Result<(), ServerError> {
+		Err(ServerError::CouldNotAcceptSocket)
+	}
+
+	fn client_message(&mut self, _: &mut ClientSocket, _: NetworkMessage) -> Result<(), ServerError> {
+		Ok(())
+	}
+
+	fn client_removed(&mut self, _: &mut ClientSocket, _: Removal) -> Result<(), ServerError> {
+		Ok(())
+	}
+}
+
+#[test]
+fn handler_errors_only_drop_that_client() {
+	let mut server = server();
+	let mut client = connect(&server);
+	for _ in 0..5 {
+		assert!(server.listen(&mut FullHandler).is_ok());
+		thread::sleep(Duration::from_millis(10));
+	}
+	assert!(server.clients.is_empty());
+	assert_eq!(disconnect_reason(&receive_until_closed(&mut client)), Some(ServerError::CouldNotAcceptSocket.reason()));
+}
+
+#[test]
+fn other_clients_stay_when_one_is_dropped() {
+	let mut server = server();
+	let mut world = world();
+	let mut failing = connect(&server);
+	let mut other = connect(&server);
+	listen(&mut server, &mut world);
+	assert_eq!(server.clients.len(), 2);
+
+	failing.send(NetworkMessage::Welcome { protocol_version: 0, capabilities: Vec::new() }).unwrap();
+	other.send(NetworkMessage::Ping { sequence: 3, time: 0.0 }).unwrap();
+	listen(&mut server, &mut world);
+
+	assert_eq!(server.clients.len(), 1);
+	assert!(disconnect_reason(&receive_until_closed(&mut failing)).is_some());
+	// The other client is still served
+	other.send(NetworkMessage::Ping { sequence: 4, time: 0.0 }).unwrap();
+	listen(&mut server, &mut world);
+	thread::sleep(Duration::from_millis(50));
+	let pongs: Vec<u32> = other.poll_messages().unwrap().into_iter().filter_map(|m| match m {
+		NetworkMessage::Pong { sequence, .. } => Some(sequence),
+		_ => None
+	}).collect();
+	assert_eq!(pongs, vec![3, 4]);
+	assert!(other.is_connected());
+}
@@ -0,0 +1,34 @@
+use password::{UserPassword, PasswordFormat};
+
+#[test]
+fn passwords_are_hashed() {
+	let password = UserPassword::new(1, "hunter2").unwrap();
+	assert_eq!(password.format, PasswordFormat::Pbkdf2);
+	assert!(!password.password.contains("hunter2"));
+	// Every hash has its own salt
+	assert!(UserPassword::new(1, "hunter2").unwrap().password != password.password);
+}
+
+#[test]
+fn only_the_right_password_is_accepted() {
+	let password = UserPassword::new(1, "hunter2").unwrap();
+	assert!(password.verify("hunter2"));
+	assert!(!password.verify("hunter3"));
+	assert!(!password.verify(""));
+}
+
+#[test]
+fn malformed_hashes_dont_match_anything() {
+	for hash in vec!["", "$rpbkdf2$", "$rpbkdf2$0$AAAnEA==$salt$", "$rpbkdf2$0$!!!$salt$hash$", "hunter2"] {
+		let password = UserPassword { user_id: 1, format: PasswordFormat::Pbkdf2, password: hash.to_string() };
+		assert!(!password.verify(hash));
+		assert!(!password.verify("hunter2"));
+	}
+}
+
+#[test]
+fn plain_text_passwords_dont_match_until_they_are_hashed() {
+	let password = UserPassword { user_id: 1, format: PasswordFormat::PlainText, password: "hunter2".to_string() };
+	assert!(!password.is_hashed());
+	assert!(!password.verify("hunter2"));
+}
@@ -0,0 +1,130 @@
+use rate_limit::{TokenBucket, RateLimit, RateLimits, ClientLimiter, Verdict, THROTTLE_TIME, STRIKE_WINDOW};
+
+fn limits() -> RateLimits {
+	RateLimits {
+		total: RateLimit::new(10.0, 1000.0),
+		kinds: vec![("Login".to_string(), RateLimit::new(1.0, 1000.0))],
+		strikes: 2,
+	}
+}
+
+#[test]
+fn bucket_refills_over_time() {
+	let mut bucket = TokenBucket::new(10.0, 20.0, 0.0);
+	for _ in 0..20 {
+		assert!(bucket.take(1.0, 0.0));
+	}
+	assert!(!bucket.take(1.0, 0.0));
+	assert!(bucket.take(1.0, 0.1));
+	assert!(!bucket.take(1.0, 0.1));
+	// It never holds more than its capacity
+	for _ in 0..20 {
+		assert!(bucket.take(1.0, 100.0));
+	}
+	assert!(!bucket.take(1.0, 100.0));
+}
+
+#[test]
+fn bucket_allows_big_amounts_when_full() {
+	let mut bucket = TokenBucket::new(10.0, 20.0, 0.0);
+	assert!(bucket.take(50.0, 0.0));
+	// Now it has to pay off its debt first
+	assert!(!bucket.take(1.0, 3.0));
+	assert!(bucket.take(1.0, 3.1));
+}
+
+#[test]
+fn messages_within_the_limits_are_allowed() {
+	let limits = limits();
+	let mut limiter = ClientLimiter::new(1, &limits, 0.0);
+	for i in 0..100 {
+		assert_eq!(limiter.check("Input", 10, i as f64 * 0.1, &limits), Verdict::Allow);
+	}
+	assert_eq!(limiter.stats.messages, 100);
+	assert_eq!(limiter.stats.bytes, 1000);
+	assert_eq!(limiter.stats.dropped, 0);
+}
+
+#[test]
+fn going_over_a_limit_throttles() {
+	let limits = limits();
+	let mut limiter = ClientLimiter::new(1, &limits, 0.0);
+	for _ in 0..20 {
+		assert_eq!(limiter.check("Input", 10, 0.0, &limits), Verdict::Allow);
+	}
+	assert_eq!(limiter.check("Input", 10, 0.0, &limits), Verdict::Throttle);
+	assert!(limiter.is_throttled(0.0));
+	// The rest of what was read at the same time is dropped as well, without another strike
+	assert_eq!(limiter.check("Input", 10, 0.0, &limits), Verdict::Throttle);
+	assert_eq!(limiter.stats.dropped, 2);
+	assert_eq!(limiter.stats.throttled, 1);
+
+	assert!(!limiter.is_throttled(THROTTLE_TIME));
+	assert_eq!(limiter.check("Input", 10, THROTTLE_TIME, &limits), Verdict::Allow);
+}
+
+#[test]
+fn bytes_are_limited() {
+	let limits = limits();
+	let mut limiter = ClientLimiter::new(1, &limits, 0.0);
+	assert_eq!(limiter.check("Input", 1500, 0.0, &limits), Verdict::Allow);
+	assert_eq!(limiter.check("Input", 1500, 0.0, &limits), Verdict::Throttle);
+}
+
+#[test]
+fn kinds_have_their_own_limit() {
+	let limits = limits();
+	let mut limiter = ClientLimiter::new(1, &limits, 0.0);
+	assert_eq!(limiter.check("Login", 10, 0.0, &limits), Verdict::Allow);
+	assert_eq!(limiter.check("Login", 10, 0.0, &limits), Verdict::Allow);
+	assert_eq!(limiter.check("Login", 10, 0.0, &limits), Verdict::Throttle);
+	// Other messages still count towards the total only
+	assert_eq!(limiter.check("Input", 10, THROTTLE_TIME, &limits), Verdict::Allow);
+	assert_eq!(limiter.check("Login", 10, THROTTLE_TIME, &limits), Verdict::Throttle);
+}
+
+#[test]
+fn dropped_messages_dont_use_up_the_total() {
+	// Room for 3 messages in total, and a single Login
+	let limits = RateLimits {
+		total: RateLimit::new(1.5, 1000.0),
+		kinds: vec![("Login".to_string(), RateLimit::new(0.5, 1000.0))],
+		strikes: 2,
+	};
+	let mut limiter = ClientLimiter::new(1, &limits, 0.0);
+	assert_eq!(limiter.check("Login", 10, 0.0, &limits), Verdict::Allow);
+	assert_eq!(limiter.check("Login", 10, 0.0, &limits), Verdict::Throttle);
+	// The dropped Login didn't take anything from the total
+	assert_eq!(limiter.check("Input", 10, THROTTLE_TIME, &limits), Verdict::Allow);
+	assert_eq!(limiter.check("Input", 10, THROTTLE_TIME, &limits), Verdict::Allow);
+}
+
+#[test]
+fn too_many_strikes_disconnect() {
+	let limits = limits();
+	let mut limiter = ClientLimiter::new(1, &limits, 0.0);
+	let mut time = 0.0;
+	// Keep logging in as soon as the client is read from again
+	loop {
+		match limiter.check("Login", 10, time, &limits) {
+			Verdict::Allow => {},
+			Verdict::Throttle => time += THROTTLE_TIME,
+			Verdict::Disconnect => break
+		}
+	}
+	assert_eq!(limiter.stats.throttled, 2);
+	assert_eq!(limiter.stats.disconnected, 1);
+	assert!(time < STRIKE_WINDOW);
+}
+
+#[test]
+fn old_strikes_are_forgotten() {
+	let limits = limits();
+	let mut limiter = ClientLimiter::new(1, &limits, 0.0);
+	let mut time = 0.0;
+	for _ in 0..10 {
+		while limiter.check("Login", 10, time, &limits) == Verdict::Allow {}
+		time += STRIKE_WINDOW;
+	}
+	assert_eq!(limiter.stats.disconnected, 0);
+}
@@ -0,0 +1,19 @@
+use session::{new_token, token_matches};
+
+#[test]
+fn tokens_are_random() {
+	let token = new_token().unwrap();
+	assert_eq!(token.len(), 32);
+	assert!(token.chars().all(|c| c.is_digit(16)));
+	assert!(token != new_token().unwrap());
+}
+
+#[test]
+fn tokens_have_to_match_exactly() {
+	let token = new_token().unwrap();
+	assert!(token_matches(&token, &token.clone()));
+	assert!(!token_matches(&token, &token[1..]));
+	let last = if token.ends_with('0') { "1" } else { "0" };
+	assert!(!token_matches(&token, &format!("{}{}", &token[..31], last)));
+	assert!(!token_matches(&token, ""));
+}
@@ -0,0 +1,239 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::encode;
+use rusqlite::Connection;
+
+use shared::User;
+use password::{UserPassword, LegacyUserPassword, PasswordFormat};
+use world::WorldState;
+use storage::{self, Storage, StorageBackend, PersistenceError, WorldEvent, FileHandler, MemoryStorage, SqliteStorage};
+use time;
+
+// A fresh directory in the temp dir, every test gets its own so they can run in parallel
+fn temp_directory(name: &str) -> PathBuf {
+	let directory = env::temp_dir().join(format!("server-test-{}-{}", name, time::precise_time_ns()));
+	fs::create_dir_all(&directory).unwrap();
+	directory
+}
+
+fn users() -> Vec<(User, UserPassword)> {
+	vec![(User {
+		id: 1,
+		name: "alice".to_string(),
+		position: [1.0, 2.0, 3.0],
+		rotation: [0.0, 0.5, 0.0],
+	}, UserPassword {
+		user_id: 1,
+		format: PasswordFormat::Pbkdf2,
+		password: "$rpbkdf2$0$AAAnEA==$salt$hash$".to_string(),
+	})]
+}
+
+// The users like they were saved before passwords had a format
+fn legacy_users(password: &str) -> Vec<(User, LegacyUserPassword)> {
+	users().into_iter().map(|(user, p)| (user, LegacyUserPassword { user_id: p.user_id, password: password.to_string() })).collect()
+}
+
+// Every backend has to behave the same, so they all go through this
+fn roundtrip(storage: &mut Storage) {
+	assert_eq!(storage.load_users().unwrap(), Vec::new());
+	assert_eq!(storage.load_world().unwrap(), None);
+	assert_eq!(storage.load_chunk(0, -1).unwrap(), None);
+
+	storage.save_users(&users()).unwrap();
+	assert_eq!(storage.load_users().unwrap(), users());
+
+	let world = WorldState { spawn_position: [4.0, 5.0, 6.0], tick: 1234 };
+	storage.save_world(&world).unwrap();
+	storage.save_world(&world).unwrap();
+	assert_eq!(storage.load_world().unwrap(), Some(world));
+
+	storage.save_chunk(0, -1, &[1, 2, 3]).unwrap();
+	storage.save_chunk(0, -1, &[4, 5]).unwrap();
+	assert_eq!(storage.load_chunk(0, -1).unwrap(), Some(vec![4, 5]));
+	assert_eq!(storage.load_chunk(-1, 0).unwrap(), None);
+
+	storage.append_event(&WorldEvent::Registered { account_id: 1, name: "alice".to_string() }).unwrap();
+	storage.append_event(&WorldEvent::LoggedIn { account_id: 1 }).unwrap();
+}
+
+#[test]
+fn memory_roundtrip() {
+	let memory = MemoryStorage::new();
+	roundtrip(&mut memory.clone());
+	assert_eq!(memory.chunk_count(), 1);
+	assert_eq!(memory.events(), vec![
+		WorldEvent::Registered { account_id: 1, name: "alice".to_string() },
+		WorldEvent::LoggedIn { account_id: 1 },
+	]);
+}
+
+#[test]
+fn file_roundtrip() {
+	let directory = temp_directory("file");
+	roundtrip(&mut FileHandler::new(&directory));
+	assert!(directory.join("chunks").join("0_-1.dat").exists());
+	assert!(directory.join("events.log").exists());
+	fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn sqlite_roundtrip() {
+	roundtrip(&mut SqliteStorage::open(":memory:").unwrap());
+}
+
+#[test]
+fn sqlite_keeps_data_after_reopening() {
+	let directory = temp_directory("sqlite");
+	let path = directory.join("server.db");
+	{
+		let mut storage = StorageBackend::Sqlite(path.clone()).open().unwrap();
+		storage.save_users(&users()).unwrap();
+	}
+	let mut storage = StorageBackend::Sqlite(path).open().unwrap();
+	assert_eq!(storage.load_users().unwrap(), users());
+	fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn corrupted_file_is_reported() {
+	let directory = temp_directory("corrupt");
+	let mut storage = FileHandler::new(&directory);
+	storage.save_users(&users()).unwrap();
+
+	// Flip the last byte of the data, the checksum should catch it
+	let path = directory.join("users.dat");
+	let mut data = fs::read(&path).unwrap();
+	let last = data.len() - 1;
+	data[last] ^= 0xFF;
+	File::create(&path).unwrap().write_all(&data).unwrap();
+
+	match storage.load_users() {
+		Err(PersistenceError::Corrupt(_)) => {},
+		x => panic!("Expected Corrupt, got {:?}", x)
+	}
+	fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn saves_keep_three_backups() {
+	let directory = temp_directory("backups");
+	let mut storage = FileHandler::new(&directory);
+	for tick in 1..6 {
+		storage.save_world(&WorldState { spawn_position: [0.0, 0.0, 0.0], tick: tick }).unwrap();
+	}
+	assert!(!directory.join("world.dat.4").exists());
+
+	// Newest first, every backup is a complete save that loads on its own
+	for (backup, tick) in vec![(1, 4), (2, 3), (3, 2)] {
+		let restored = temp_directory("restored");
+		fs::copy(directory.join(format!("world.dat.{}", backup)), restored.join("world.dat")).unwrap();
+		assert_eq!(FileHandler::new(&restored).load_world().unwrap().map(|w| w.tick), Some(tick));
+		fs::remove_dir_all(&restored).unwrap();
+	}
+	fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn files_without_a_header_are_migrated() {
+	let directory = temp_directory("legacy");
+	File::create(directory.join("users.dat")).unwrap().write_all(&encode(&legacy_users(&users()[0].1.password), SizeLimit::Infinite).unwrap()).unwrap();
+
+	let mut storage = FileHandler::new(&directory);
+	let loaded = storage.load_users().unwrap();
+	assert_eq!(loaded, users());
+	// The next save writes the current format
+	storage.save_users(&loaded).unwrap();
+	assert_eq!(&fs::read(directory.join("users.dat")).unwrap()[0..4], b"RGSV");
+	assert_eq!(storage.load_users().unwrap(), users());
+	fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn damaged_files_without_a_header_are_reported() {
+	let directory = temp_directory("garbage");
+	let legacy = encode(&legacy_users("hunter2"), SizeLimit::Infinite).unwrap();
+	let damaged = vec![
+		// A length that claims far more data than the file has
+		vec![0xFF; 16],
+		legacy[0..legacy.len() - 3].to_vec(),
+		legacy.iter().cloned().chain(vec![1, 2, 3]).collect(),
+	];
+	for data in damaged {
+		File::create(directory.join("users.dat")).unwrap().write_all(&data).unwrap();
+		match FileHandler::new(&directory).load_users() {
+			Err(PersistenceError::Corrupt(_)) => {},
+			x => panic!("Expected Corrupt, got {:?}", x)
+		}
+	}
+	fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn newer_formats_are_rejected() {
+	let directory = temp_directory("newer");
+	FileHandler::new(&directory).save_users(&users()).unwrap();
+
+	// The version comes right after the magic bytes
+	let path = directory.join("users.dat");
+	let mut data = fs::read(&path).unwrap();
+	data[7] += 1;
+	File::create(&path).unwrap().write_all(&data).unwrap();
+
+	match FileHandler::new(&directory).load_users() {
+		Err(PersistenceError::UnsupportedVersion(_)) => {},
+		x => panic!("Expected UnsupportedVersion, got {:?}", x)
+	}
+	fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn plain_text_passwords_are_migrated() {
+	let mut memory = MemoryStorage::new();
+	let mut plain = users();
+	plain[0].1.format = PasswordFormat::PlainText;
+	plain[0].1.password = "hunter2".to_string();
+	memory.save_users(&plain).unwrap();
+
+	let users = storage::load_users(&mut memory).unwrap();
+	assert!(users[0].1.is_hashed());
+	assert!(users[0].1.verify("hunter2"));
+	// The hashed passwords are saved straight away
+	assert!(memory.load_users().unwrap()[0].1.is_hashed());
+}
+
+#[test]
+fn plain_text_passwords_that_look_like_hashes_are_migrated() {
+	let directory = temp_directory("lookalike");
+	let password = "$rpbkdf2$hunter2";
+	File::create(directory.join("users.dat")).unwrap().write_all(&encode(&legacy_users(password), SizeLimit::Infinite).unwrap()).unwrap();
+
+	let mut storage = FileHandler::new(&directory);
+	let users = storage::load_users(&mut storage).unwrap();
+	assert!(users[0].1.is_hashed());
+	assert!(users[0].1.verify(password));
+	fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn sqlite_databases_from_before_password_formats_are_migrated() {
+	let directory = temp_directory("sqlite-migration");
+	let path = directory.join("server.db");
+	{
+		let connection = Connection::open(&path).unwrap();
+		connection.execute_batch("
+			CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL, password TEXT NOT NULL,
+				position_x REAL NOT NULL, position_y REAL NOT NULL, position_z REAL NOT NULL,
+				rotation_x REAL NOT NULL, rotation_y REAL NOT NULL, rotation_z REAL NOT NULL);
+			INSERT INTO users VALUES (1, 'alice', '$rpbkdf2$0$AAAnEA==$salt$hash$', 1.0, 2.0, 3.0, 0.0, 0.5, 0.0);
+		").unwrap();
+	}
+	assert_eq!(SqliteStorage::open(&path).unwrap().load_users().unwrap(), users());
+	// Opening it again doesn't migrate it twice
+	assert_eq!(SqliteStorage::open(&path).unwrap().load_users().unwrap(), users());
+	fs::remove_dir_all(&directory).unwrap();
+}
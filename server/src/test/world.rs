@@ -0,0 +1,433 @@
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use shared::{ClientSocket, NetworkMessage, PlayerInput, EntityDelta, QuantizedState, MOVE_SPEED, apply_input};
+use network::{ClientHandler, ServerError, Removal};
+use storage::{Storage, MemoryStorage};
+use world::{World, WorldState};
+use time;
+
+// (the socket of the server, the socket of the client)
+pub fn socket_pair() -> (ClientSocket, ClientSocket) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+	let (server, _) = listener.accept().unwrap();
+	(ClientSocket::from_stream(server), ClientSocket::from_stream(client))
+}
+
+// Everything the client received, waits a bit so the messages have time to arrive
+pub fn receive(socket: &mut ClientSocket) -> Vec<NetworkMessage> {
+	thread::sleep(Duration::from_millis(50));
+	socket.poll_messages().unwrap()
+}
+
+pub fn world() -> World {
+	World::new(Box::new(MemoryStorage::new()), WorldState::new(), Vec::new())
+}
+
+// Connect a client and register an account, returns both sides of the connection
+pub fn join(world: &mut World, name: &str) -> (ClientSocket, ClientSocket) {
+	let (mut server, mut client) = socket_pair();
+	world.client_created(&mut server).unwrap();
+	world.client_message(&mut server, NetworkMessage::Register { username: name.to_string(), password: "password".to_string() }).unwrap();
+	receive(&mut client);
+	(server, client)
+}
+
+fn forward() -> PlayerInput {
+	PlayerInput { forward: 1, strafe: 0, rotation: [0.0, 0.5, 0.0] }
+}
+
+#[test]
+fn joining_sends_the_start_position() {
+	let mut world = world();
+	let (mut server, mut client) = socket_pair();
+	world.client_created(&mut server).unwrap();
+	world.client_message(&mut server, NetworkMessage::Register { username: "alice".to_string(), password: "password".to_string() }).unwrap();
+
+	let spawn = world.state.spawn_position;
+	let messages = receive(&mut client);
+	assert!(messages.contains(&NetworkMessage::PlayerState { last_sequence: 0, position: spawn, rotation: [0.0, 0.0, 0.0] }));
+}
+
+#[test]
+fn inputs_move_the_player() {
+	let mut world = world();
+	let (mut server, mut client) = join(&mut world, "alice");
+
+	let mut expected = world.state.spawn_position;
+	let mut rotation = [0.0, 0.0, 0.0];
+	for sequence in 1..4 {
+		world.client_message(&mut server, NetworkMessage::Input { sequence: sequence, input: forward() }).unwrap();
+		apply_input(&mut expected, &mut rotation, &forward());
+	}
+	assert_eq!(world.players[0].user.position, expected);
+	assert_eq!(world.players[0].last_input_sequence, 3);
+
+	let messages = receive(&mut client);
+	assert_eq!(messages.last(), Some(&NetworkMessage::PlayerState { last_sequence: 3, position: expected, rotation: rotation }));
+}
+
+#[test]
+fn old_inputs_are_ignored() {
+	let mut world = world();
+	let (mut server, _client) = join(&mut world, "alice");
+
+	world.client_message(&mut server, NetworkMessage::Input { sequence: 5, input: forward() }).unwrap();
+	let position = world.players[0].user.position;
+	world.client_message(&mut server, NetworkMessage::Input { sequence: 5, input: forward() }).unwrap();
+	world.client_message(&mut server, NetworkMessage::Input { sequence: 2, input: forward() }).unwrap();
+	assert_eq!(world.players[0].user.position, position);
+}
+
+#[test]
+fn clients_cant_move_themselves() {
+	let mut world = world();
+	let (mut server, _client) = join(&mut world, "alice");
+	match world.client_message(&mut server, NetworkMessage::SetPosition { uid: 0, position: [100.0, 0.0, 0.0], rotation: [0.0, 0.0, 0.0] }) {
+		Err(ServerError::UnexpectedMessage(_)) => {},
+		x => panic!("Expected UnexpectedMessage, got {:?}", x)
+	}
+}
+
+#[test]
+fn inputs_need_a_login() {
+	let mut world = world();
+	let (mut server, _client) = socket_pair();
+	world.client_created(&mut server).unwrap();
+	match world.client_message(&mut server, NetworkMessage::Input { sequence: 1, input: forward() }) {
+		Err(ServerError::NotLoggedIn) => {},
+		x => panic!("Expected NotLoggedIn, got {:?}", x)
+	}
+}
+
+fn find_snapshot(updates: &[(u32, NetworkMessage)], id: u32) -> Option<(u32, u32, Vec<EntityDelta>)> {
+	for update in updates.iter().filter(|u| u.0 == id) {
+		if let NetworkMessage::WorldSnapshot { sequence, baseline, ref entities, .. } = update.1 {
+			return Some((sequence, baseline, entities.clone()));
+		}
+	}
+	None
+}
+
+fn snapshot_of(updates: &[(u32, NetworkMessage)], id: u32) -> (u32, u32, Vec<EntityDelta>) {
+	match find_snapshot(updates, id) {
+		Some(snapshot) => snapshot,
+		None => panic!("Expected a WorldSnapshot for {}, got {:?}", id, updates)
+	}
+}
+
+// The other messages for the client, without the snapshot
+fn notifications_of(updates: &[(u32, NetworkMessage)], id: u32) -> Vec<NetworkMessage> {
+	updates.iter()
+		.filter(|u| u.0 == id)
+		.map(|u| u.1.clone())
+		.filter(|m| !m.is_same_type_as(&NetworkMessage::WorldSnapshot { sequence: 0, time: 0.0, baseline: 0, entities: Vec::new() }))
+		.collect()
+}
+
+#[test]
+fn first_snapshot_contains_the_others() {
+	let mut world = world();
+	let (alice, _alice_client) = join(&mut world, "alice");
+	let (_bob, _bob_client) = join(&mut world, "bob");
+
+	let snapshots = world.take_updates();
+	let (sequence, baseline, entities) = snapshot_of(&snapshots, alice.id);
+	assert_eq!((sequence, baseline), (1, 0));
+	let state = QuantizedState::new(world.players[1].user.position, world.players[1].user.rotation);
+	assert_eq!(entities, vec![EntityDelta { uid: world.players[1].user.id, position: Some(state.position), rotation: Some(state.rotation) }]);
+}
+
+#[test]
+fn snapshots_only_contain_changes_since_the_acknowledgement() {
+	let mut world = world();
+	let (mut alice, _alice_client) = join(&mut world, "alice");
+	let (mut bob, _bob_client) = join(&mut world, "bob");
+
+	world.take_updates();
+	world.client_message(&mut alice, NetworkMessage::SnapshotAck { sequence: 1 }).unwrap();
+
+	// Nothing moved, once alice knows that there is nothing to send
+	let (sequence, baseline, entities) = snapshot_of(&world.take_updates(), alice.id);
+	assert_eq!(baseline, 1);
+	assert!(entities.is_empty());
+	world.client_message(&mut alice, NetworkMessage::SnapshotAck { sequence: sequence }).unwrap();
+	assert_eq!(find_snapshot(&world.take_updates(), alice.id), None);
+
+	// Bob moved forward without turning around, only the position is sent
+	world.client_message(&mut bob, NetworkMessage::Input { sequence: 1, input: PlayerInput { forward: 1, strafe: 0, rotation: [0.0, 0.0, 0.0] } }).unwrap();
+	let (_, baseline, entities) = snapshot_of(&world.take_updates(), alice.id);
+	assert_eq!(baseline, 2);
+	assert_eq!(entities.len(), 1);
+	assert!(entities[0].position.is_some());
+	assert_eq!(entities[0].rotation, None);
+
+	// Without an acknowledgement bob would still get the full state
+	let (_, baseline, _) = snapshot_of(&world.take_updates(), bob.id);
+	assert_eq!(baseline, 0);
+}
+
+#[test]
+fn players_that_leave_are_only_removed_by_remove_entity() {
+	let mut world = world();
+	let (mut alice, _alice_client) = join(&mut world, "alice");
+	let (mut bob, _bob_client) = join(&mut world, "bob");
+
+	let bob_uid = world.players[1].user.id;
+
+	world.take_updates();
+	world.client_message(&mut alice, NetworkMessage::SnapshotAck { sequence: 1 }).unwrap();
+	world.client_message(&mut bob, NetworkMessage::Disconnect { reason: "Bye".to_string() }).unwrap();
+	world.client_removed(&mut bob, Removal::Dropped).unwrap();
+
+	let updates = world.take_updates();
+	assert_eq!(notifications_of(&updates, alice.id), vec![NetworkMessage::RemoveEntity { uid: bob_uid }]);
+	assert!(snapshot_of(&updates, alice.id).2.is_empty());
+}
+
+#[test]
+fn nearby_players_are_spawned() {
+	let mut world = world();
+	let (alice, _alice_client) = join(&mut world, "alice");
+	let (_bob, _bob_client) = join(&mut world, "bob");
+
+	let updates = world.take_updates();
+	let bob_state = QuantizedState::new(world.players[1].user.position, world.players[1].user.rotation);
+	assert_eq!(notifications_of(&updates, alice.id), vec![NetworkMessage::SpawnEntity {
+		uid: world.players[1].user.id,
+		position: bob_state.position(),
+		rotation: bob_state.rotation(),
+	}]);
+	// Only once
+	assert!(notifications_of(&world.take_updates(), alice.id).is_empty());
+}
+
+#[test]
+fn players_out_of_range_are_not_sent() {
+	let mut world = world();
+	world.interest_radius = 10.0;
+	let (alice, _alice_client) = join(&mut world, "alice");
+	let (bob, _bob_client) = join(&mut world, "bob");
+	world.players[1].user.position = [100.0, 0.0, 0.0];
+
+	let updates = world.take_updates();
+	assert!(notifications_of(&updates, alice.id).is_empty());
+	assert_eq!(find_snapshot(&updates, alice.id), None);
+
+	// Bob walks up to alice
+	world.players[1].user.position = world.players[0].user.position;
+	let updates = world.take_updates();
+	assert_eq!(notifications_of(&updates, alice.id).len(), 1);
+	assert_eq!(snapshot_of(&updates, alice.id).2.len(), 1);
+
+	// And away again
+	world.players[1].user.position = [100.0, 0.0, 0.0];
+	let updates = world.take_updates();
+	assert_eq!(notifications_of(&updates, alice.id), vec![NetworkMessage::RemoveEntity { uid: world.players[1].user.id }]);
+	assert!(snapshot_of(&updates, bob.id).2.is_empty());
+}
+
+#[test]
+fn acknowledging_needs_a_login() {
+	let mut world = world();
+	let (mut server, _client) = socket_pair();
+	world.client_created(&mut server).unwrap();
+	match world.client_message(&mut server, NetworkMessage::SnapshotAck { sequence: 1 }) {
+		Err(ServerError::NotLoggedIn) => {},
+		x => panic!("Expected NotLoggedIn, got {:?}", x)
+	}
+}
+
+#[test]
+fn flooding_inputs_doesnt_move_faster() {
+	let mut world = world();
+	world.violation_budget = 1000;
+	let (mut server, mut client) = join(&mut world, "alice");
+	let start = world.players[0].user.position;
+
+	for sequence in 1..201 {
+		world.client_message(&mut server, NetworkMessage::Input { sequence: sequence, input: forward() }).unwrap();
+	}
+	// About half a second of tolerance, instead of the 4 seconds of steps that were sent
+	let player = &world.players[0];
+	let distance = ((player.user.position[0] - start[0]).powi(2) + (player.user.position[2] - start[2]).powi(2)).sqrt();
+	assert!(distance < MOVE_SPEED, "Moved {} units", distance);
+	assert_eq!(player.last_input_sequence, 200);
+
+	// The client is told where it really is
+	match receive(&mut client).last() {
+		Some(&NetworkMessage::PlayerState { last_sequence, position, .. }) => {
+			assert_eq!(last_sequence, 200);
+			assert_eq!(position, player.user.position);
+		},
+		x => panic!("Expected a PlayerState, got {:?}", x)
+	}
+}
+
+#[test]
+fn rejected_inputs_dont_turn_the_player() {
+	let mut world = world();
+	world.violation_budget = 1000;
+	let (mut server, _client) = join(&mut world, "alice");
+
+	let mut sequence = 1;
+	while world.players[0].movement.check(time::precise_time_s(), world.movement_tolerance).is_ok() {}
+	let (position, rotation) = (world.players[0].user.position, world.players[0].user.rotation);
+	for _ in 0..10 {
+		let input = PlayerInput { forward: 1, strafe: 0, rotation: [0.0, 2.0, 0.0] };
+		world.client_message(&mut server, NetworkMessage::Input { sequence: sequence, input: input }).unwrap();
+		sequence += 1;
+	}
+	assert_eq!(world.players[0].user.position, position);
+	assert_eq!(world.players[0].user.rotation, rotation);
+}
+
+#[test]
+fn speeding_too_often_gets_kicked() {
+	let mut world = world();
+	world.violation_budget = 5;
+	let (mut server, _client) = join(&mut world, "alice");
+
+	let mut result = Ok(());
+	for sequence in 1..100 {
+		result = world.client_message(&mut server, NetworkMessage::Input { sequence: sequence, input: forward() });
+		if result.is_err() {
+			break;
+		}
+	}
+	match result {
+		Err(ServerError::Kicked(_)) => {},
+		x => panic!("Expected Kicked, got {:?}", x)
+	}
+	// Kicked players can't resume their session
+	world.client_removed(&mut server, Removal::Dropped).unwrap();
+	assert!(world.players.is_empty());
+}
+
+// Lose the connection of the client and connect again, returns both sides of the new connection
+fn reconnect(world: &mut World, old: &mut ClientSocket) -> (ClientSocket, ClientSocket) {
+	world.client_removed(old, Removal::ConnectionLost).unwrap();
+	let (mut server, client) = socket_pair();
+	world.client_created(&mut server).unwrap();
+	(server, client)
+}
+
+#[test]
+fn lost_players_wait_for_their_client() {
+	let mut world = world();
+	let (alice, _alice_client) = join(&mut world, "alice");
+	let (mut bob, _bob_client) = join(&mut world, "bob");
+	world.take_updates();
+	world.client_removed(&mut bob, Removal::ConnectionLost).unwrap();
+
+	// The others still see bob, but nobody gets bob's updates
+	let updates = world.take_updates();
+	assert!(notifications_of(&updates, alice.id).is_empty());
+	assert!(updates.iter().all(|u| u.0 == alice.id));
+	assert_eq!(world.players[1].client_id, None);
+
+	world.expire_sessions(time::precise_time_s());
+	assert_eq!(world.players.len(), 2);
+	world.expire_sessions(time::precise_time_s() + world.session_grace_period);
+	assert_eq!(world.players.len(), 1);
+}
+
+#[test]
+fn dropped_players_are_removed_straight_away() {
+	let mut world = world();
+	let (alice, _alice_client) = join(&mut world, "alice");
+	let (mut bob, _bob_client) = join(&mut world, "bob");
+	let bob_uid = world.players[1].user.id;
+	world.take_updates();
+
+	// Like a client that sent a message it shouldn't have
+	world.client_removed(&mut bob, Removal::Dropped).unwrap();
+	assert_eq!(world.players.len(), 1);
+	assert_eq!(notifications_of(&world.take_updates(), alice.id), vec![NetworkMessage::RemoveEntity { uid: bob_uid }]);
+}
+
+#[test]
+fn resuming_continues_with_the_same_player() {
+	let mut world = world();
+	let (mut old, _old_client) = join(&mut world, "alice");
+	world.client_message(&mut old, NetworkMessage::Input { sequence: 1, input: forward() }).unwrap();
+	let (uid, token, position) = (world.players[0].user.id, world.players[0].session.clone(), world.players[0].user.position);
+
+	let (mut server, mut client) = reconnect(&mut world, &mut old);
+	world.client_message(&mut server, NetworkMessage::Resume { token: token.clone() }).unwrap();
+	assert_eq!(receive(&mut client), vec![
+		NetworkMessage::LoginResult { error: None },
+		NetworkMessage::Identify(uid),
+		NetworkMessage::Session { token: token },
+		NetworkMessage::PlayerState { last_sequence: 0, position: position, rotation: world.players[0].user.rotation },
+	]);
+	assert_eq!(world.players.len(), 1);
+	assert_eq!(world.players[0].client_id, Some(server.id));
+
+	// The client starts counting its inputs from the start again
+	world.client_message(&mut server, NetworkMessage::Input { sequence: 1, input: forward() }).unwrap();
+	assert!(world.players[0].user.position != position);
+}
+
+#[test]
+fn resuming_needs_the_right_token() {
+	let mut world = world();
+	let (mut old, _old_client) = join(&mut world, "alice");
+	let (mut server, mut client) = reconnect(&mut world, &mut old);
+	world.client_message(&mut server, NetworkMessage::Resume { token: "0123456789abcdef0123456789abcdef".to_string() }).unwrap();
+
+	match receive(&mut client).first() {
+		Some(&NetworkMessage::LoginResult { error: Some(_) }) => {},
+		x => panic!("Expected a failed LoginResult, got {:?}", x)
+	}
+	assert_eq!(world.players[0].client_id, None);
+}
+
+#[test]
+fn resuming_takes_over_the_old_connection() {
+	let mut world = world();
+	let (mut old, _old_client) = join(&mut world, "alice");
+	let token = world.players[0].session.clone();
+
+	// The server didn't notice yet that the old connection is gone
+	let (mut server, _client) = socket_pair();
+	world.client_created(&mut server).unwrap();
+	world.client_message(&mut server, NetworkMessage::Resume { token: token }).unwrap();
+	assert_eq!(world.players[0].client_id, Some(server.id));
+	match world.client_message(&mut old, NetworkMessage::Input { sequence: 1, input: forward() }) {
+		Err(ServerError::NotLoggedIn) => {},
+		x => panic!("Expected NotLoggedIn, got {:?}", x)
+	}
+	// Dropping the old connection doesn't affect the player anymore
+	world.client_removed(&mut old, Removal::ConnectionLost).unwrap();
+	assert_eq!(world.players[0].client_id, Some(server.id));
+}
+
+#[test]
+fn logging_in_takes_back_a_waiting_player() {
+	let mut world = world();
+	let (mut old, _old_client) = join(&mut world, "alice");
+	let uid = world.players[0].user.id;
+
+	let (mut server, _client) = reconnect(&mut world, &mut old);
+	world.client_message(&mut server, NetworkMessage::Login { username: "alice".to_string(), password: "password".to_string() }).unwrap();
+	assert_eq!(world.players.len(), 1);
+	assert_eq!(world.players[0].user.id, uid);
+	assert_eq!(world.players[0].client_id, Some(server.id));
+}
+
+#[test]
+fn accounts_are_written_when_the_world_is_saved() {
+	let mut storage = MemoryStorage::new();
+	let mut world = World::new(Box::new(storage.clone()), WorldState::new(), Vec::new());
+	let (mut server, _client) = join(&mut world, "alice");
+	world.client_removed(&mut server, Removal::ConnectionLost).unwrap();
+	assert_eq!(storage.load_users().unwrap(), Vec::new());
+
+	world.save().unwrap();
+	let users = storage.load_users().unwrap();
+	assert_eq!(users.len(), 1);
+	assert_eq!(users[0].0.name, "alice");
+}
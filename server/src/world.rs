@@ -0,0 +1,526 @@
+use shared::{ClientSocket, NetworkMessage, User, MessageHandler, PlayerInput, EntityDelta, QuantizedState, SnapshotEncoder, dispatch, apply_input};
+use network::{ClientHandler, ServerError, Removal};
+use storage::{Storage, PersistenceError, WorldEvent};
+use password::UserPassword;
+use id_allocator::IdAllocator;
+use interest::{SpatialGrid, DEFAULT_INTEREST_RADIUS, update_visible};
+use movement::{MovementValidator, DEFAULT_MOVEMENT_TOLERANCE, DEFAULT_VIOLATION_BUDGET, VIOLATION_WINDOW};
+use session::{DEFAULT_SESSION_GRACE_PERIOD, new_token, token_matches};
+use vecmath::Vector3;
+use time;
+
+const SPAWN_POSITION: Vector3<f32> = [-10.0, 0.0, 0.0];
+pub const DEFAULT_MAX_PLAYERS: usize = 64;
+
+/// Everything about the world that is saved, besides the players
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+pub struct WorldState {
+	pub spawn_position: Vector3<f32>,
+	/// The amount of ticks the world has been running, over all server runs
+	pub tick: u64,
+}
+
+impl WorldState {
+	pub fn new() -> WorldState {
+		WorldState {
+			spawn_position: SPAWN_POSITION,
+			tick: 0,
+		}
+	}
+}
+
+/// A logged in user that is walking around in the world
+pub struct Player {
+	/// The id of this user is the uid of the entity, not the id of the account or of the connection
+	pub user: User,
+	pub account_id: u32,
+	/// The connection that controls the player, None while its client is reconnecting
+	pub client_id: Option<u32>,
+	/// The token the client can Resume this player with
+	pub session: String,
+	/// When the client lost the connection, the player logs out session_grace_period seconds later
+	pub disconnect_time: f64,
+	/// The sequence of the last Input that was applied, older inputs are ignored
+	pub last_input_sequence: u32,
+	/// What this player's client already knows about the others
+	pub snapshots: SnapshotEncoder,
+	/// The uids of the other players this player's client knows about
+	pub visible: Vec<u32>,
+	pub movement: MovementValidator,
+}
+
+/// The state of the game on the server
+/// Everything the clients do goes through here, and after every step the main loop asks for the updates of each client
+pub struct World {
+	pub state: WorldState,
+	pub players: Vec<Player>,
+	/// Every registered user, the id of these users is the id of the account
+	pub accounts: Vec<(User, UserPassword)>,
+	/// The amount of players that can be logged in at the same time
+	pub max_players: usize,
+	/// Players only hear about the players within this distance
+	pub interest_radius: f32,
+	/// Seconds a client's inputs may run ahead of the server, see MovementValidator
+	pub movement_tolerance: f64,
+	/// The amount of illegal moves within movement::VIOLATION_WINDOW that gets a client kicked
+	pub violation_budget: usize,
+	/// Seconds a player waits in the world for its client to reconnect
+	pub session_grace_period: f64,
+	dummy_password: UserPassword,
+	// Set when the accounts changed since the last save
+	accounts_changed: bool,
+	storage: Box<Storage>,
+	ids: IdAllocator,
+}
+
+impl World {
+	pub fn new(storage: Box<Storage>, state: WorldState, accounts: Vec<(User, UserPassword)>) -> World {
+		World {
+			state: state,
+			players: Vec::new(),
+			accounts: accounts,
+			max_players: DEFAULT_MAX_PLAYERS,
+			interest_radius: DEFAULT_INTEREST_RADIUS,
+			movement_tolerance: DEFAULT_MOVEMENT_TOLERANCE,
+			violation_budget: DEFAULT_VIOLATION_BUDGET,
+			session_grace_period: DEFAULT_SESSION_GRACE_PERIOD,
+			dummy_password: UserPassword::new(0, "").expect("Could not hash the dummy password"),
+			accounts_changed: false,
+			storage: storage,
+			ids: IdAllocator::new(),
+		}
+	}
+
+	fn is_logged_in(&self, client_id: u32) -> bool {
+		self.players.iter().any(|p| p.client_id == Some(client_id))
+	}
+
+	/// Run a single simulation step of shared::TIMESTEP
+	pub fn tick(&mut self) {
+		self.state.tick += 1;
+		self.expire_sessions(time::precise_time_s());
+	}
+
+	/// Log out the players whose client didn't come back within session_grace_period
+	pub fn expire_sessions(&mut self, now: f64) {
+		let grace_period = self.session_grace_period;
+		while let Some(index) = self.players.iter().position(|p| p.client_id.is_none() && now - p.disconnect_time >= grace_period) {
+			info!("{} did not reconnect in time", self.players[index].user.name);
+			self.remove_player(index);
+		}
+	}
+
+	/// The messages every player should get after a step, with the client id they should be sent to
+	/// Players that come within interest_radius are spawned, those that move out of it are removed,
+	/// and the WorldSnapshot only contains what changed since the last snapshot the client acknowledged, if anything did
+	pub fn take_updates(&mut self) -> Vec<(u32, NetworkMessage)> {
+		let mut grid = SpatialGrid::new(self.interest_radius);
+		for player in &self.players {
+			grid.insert(player.user.id, player.user.position);
+		}
+		let states: Vec<(u32, QuantizedState)> = self.players.iter()
+			.map(|p| (p.user.id, QuantizedState::new(p.user.position, p.user.rotation)))
+			.collect();
+
+		let now = time::precise_time_s();
+		let mut updates = Vec::new();
+		for player in &mut self.players {
+			// Players whose client is reconnecting are still seen by the others, but there is nobody to send their updates to
+			let id = match player.client_id {
+				Some(id) => id,
+				None => continue
+			};
+			let (visible, entered, left) = update_visible(&grid, player.user.position, self.interest_radius, player.user.id, &player.visible);
+			for uid in left {
+				// Removed before the snapshot, so it isn't made against a baseline that still has the entity
+				player.snapshots.forget(uid);
+				updates.push((id, NetworkMessage::RemoveEntity { uid: uid }));
+			}
+			for uid in entered {
+				if let Some(&(_, ref state)) = states.iter().find(|s| s.0 == uid) {
+					updates.push((id, NetworkMessage::SpawnEntity { uid: uid, position: state.position(), rotation: state.rotation() }));
+				}
+			}
+
+			let state = states.iter().filter(|s| visible.contains(&s.0)).cloned().collect();
+			if let Some((sequence, baseline, entities)) = player.snapshots.encode(state) {
+				updates.push((id, NetworkMessage::WorldSnapshot {
+					sequence: sequence,
+					time: now,
+					baseline: baseline,
+					entities: entities,
+				}));
+			}
+			player.visible = visible;
+		}
+		updates
+	}
+
+	/// Save the world state and the accounts, including where the players currently are
+	/// The accounts are only written when something changed, writing them means an fsync and a backup
+	pub fn save(&mut self) -> Result<(), PersistenceError> {
+		for i in 0..self.players.len() {
+			self.store_player(i);
+		}
+		try!(self.storage.save_world(&self.state));
+		if self.accounts_changed {
+			try!(self.storage.save_users(&self.accounts));
+			self.accounts_changed = false;
+		}
+		Ok(())
+	}
+
+	// Copy the state of the player to their account
+	fn store_player(&mut self, player_index: usize) {
+		let player = &self.players[player_index];
+		if let Some(account) = self.accounts.iter_mut().find(|a| a.0.id == player.account_id) {
+			account.0.position = player.user.position;
+			account.0.rotation = player.user.rotation;
+			self.accounts_changed = true;
+		}
+	}
+
+	fn record(&mut self, event: WorldEvent) {
+		if let Err(e) = self.storage.append_event(&event) {
+			error!("Could not record {:?}: {:?}", event, e);
+		}
+	}
+
+	/// Put the player of the given account in the world
+	fn join(&mut self, client: &mut ClientSocket, account_index: usize) -> Result<(), ServerError> {
+		if self.players.len() >= self.max_players {
+			try!(client.send(NetworkMessage::LoginResult { error: Some("The server is full, please try again later".to_string()) }));
+			return Ok(());
+		}
+		let token = match new_token() {
+			Ok(t) => t,
+			Err(e) => {
+				error!("Could not create a session token: {:?}", e);
+				try!(client.send(NetworkMessage::LoginResult { error: Some("Could not log in, please try again later".to_string()) }));
+				return Ok(());
+			}
+		};
+
+		let player = {
+			let account = &self.accounts[account_index].0;
+			info!("{} logged in as client {}", account.name, client.id);
+			Player {
+				user: User {
+					// The entity keeps this id for as long as it is in the world, even when the client reconnects
+					id: self.ids.allocate(),
+					name: account.name.clone(),
+					position: account.position,
+					rotation: account.rotation,
+				},
+				account_id: account.id,
+				client_id: None,
+				session: token,
+				disconnect_time: 0.0,
+				last_input_sequence: 0,
+				snapshots: SnapshotEncoder::new(),
+				visible: Vec::new(),
+				movement: MovementValidator::new(time::precise_time_s()),
+			}
+		};
+		let account_id = player.account_id;
+		self.players.push(player);
+		self.record(WorldEvent::LoggedIn { account_id: account_id });
+		let index = self.players.len() - 1;
+		self.attach(client, index)
+	}
+
+	/// Let the client control the player, either because it just joined or because the client reconnected
+	/// A player that was controlled by another connection is taken away from it
+	fn attach(&mut self, client: &mut ClientSocket, player_index: usize) -> Result<(), ServerError> {
+		let player = &mut self.players[player_index];
+		player.client_id = Some(client.id);
+		// The client starts over, it lost whatever it knew with the old connection
+		player.last_input_sequence = 0;
+		player.snapshots = SnapshotEncoder::new();
+		player.visible = Vec::new();
+		player.movement = MovementValidator::new(time::precise_time_s());
+
+		try!(client.send(NetworkMessage::LoginResult { error: None }));
+		try!(client.send(NetworkMessage::Identify(player.user.id)));
+		try!(client.send(NetworkMessage::Session { token: player.session.clone() }));
+		// The client starts predicting from here
+		try!(client.send(NetworkMessage::PlayerState {
+			last_sequence: 0,
+			position: player.user.position,
+			rotation: player.user.rotation,
+		}));
+		Ok(())
+	}
+
+	/// Take the player out of the world, the others see it disappear in their next update
+	fn remove_player(&mut self, player_index: usize) {
+		// Remember where the player left, so they continue there next time
+		self.store_player(player_index);
+		let player = self.players.remove(player_index);
+		self.ids.free(player.user.id);
+		self.record(WorldEvent::LoggedOut { account_id: player.account_id });
+	}
+}
+
+impl ClientHandler for World {
+	fn client_created(&mut self, client: &mut ClientSocket) -> Result<(), ServerError> {
+		// The client doesn't get a player until it logs in
+		client.id = self.ids.allocate();
+		Ok(())
+	}
+
+	fn client_message(&mut self, client: &mut ClientSocket, message: NetworkMessage) -> Result<(), ServerError> {
+		dispatch(self, client, message)
+	}
+
+	fn client_removed(&mut self, client: &mut ClientSocket, removal: Removal) -> Result<(), ServerError> {
+		match (self.players.iter().position(|p| p.client_id == Some(client.id)), removal) {
+			(Some(index), Removal::ConnectionLost) => {
+				// The player waits for the client to come back, the next save keeps where it was if the server stops before that
+				self.store_player(index);
+				let player = &mut self.players[index];
+				info!("{} lost the connection, waiting {} seconds for it to come back", player.user.name, self.session_grace_period);
+				player.client_id = None;
+				player.disconnect_time = time::precise_time_s();
+			},
+			// A client that was dropped for what it sent doesn't get to come back for its player
+			(Some(index), Removal::Dropped) => {
+				info!("{} was dropped, logging out", self.players[index].user.name);
+				self.remove_player(index);
+			},
+			(None, _) => {}
+		}
+		self.ids.free(client.id);
+		Ok(())
+	}
+}
+
+impl MessageHandler for World {
+	type Context = ClientSocket;
+	type Error = ServerError;
+
+	fn handle_hello(&mut self, _: &mut ClientSocket, _: u32, _: String, _: Vec<String>) -> Result<(), ServerError> {
+		// The handshake is done by the ServerSocket before the client gets here
+		Err(ServerError::UnexpectedMessage("Hello"))
+	}
+
+	fn handle_welcome(&mut self, _: &mut ClientSocket, _: u32, _: Vec<String>) -> Result<(), ServerError> {
+		Err(ServerError::UnexpectedMessage("Welcome"))
+	}
+
+	fn handle_reject(&mut self, _: &mut ClientSocket, _: String) -> Result<(), ServerError> {
+		Err(ServerError::UnexpectedMessage("Reject"))
+	}
+
+	fn handle_disconnect(&mut self, client: &mut ClientSocket, reason: String) -> Result<(), ServerError> {
+		info!("Client {} is leaving: {}", client.id, reason);
+		// Leaving on purpose doesn't need a grace period
+		if let Some(index) = self.players.iter().position(|p| p.client_id == Some(client.id)) {
+			self.remove_player(index);
+		}
+		// The ServerSocket removes disconnected clients
+		client.disconnect();
+		Ok(())
+	}
+
+	fn handle_ping(&mut self, client: &mut ClientSocket, sequence: u32, _: f64) -> Result<(), ServerError> {
+		try!(client.send(NetworkMessage::Pong { sequence: sequence, time: time::precise_time_s() }));
+		Ok(())
+	}
+
+	fn handle_pong(&mut self, client: &mut ClientSocket, sequence: u32, peer_time: f64) -> Result<(), ServerError> {
+		// A Pong we didn't ask for is harmless, it could be the answer to a ping from before a reconnect
+		if client.pings.pong(sequence, peer_time, time::precise_time_s()) {
+			debug!("Client {}: round trip {:.1}ms, jitter {:.1}ms",
+				client.id, client.pings.rtt.unwrap_or(0.0) * 1000.0, client.pings.jitter * 1000.0);
+		}
+		Ok(())
+	}
+
+	fn handle_identify(&mut self, _: &mut ClientSocket, _: u32) -> Result<(), ServerError> {
+		Err(ServerError::UnexpectedMessage("Identify"))
+	}
+
+	fn handle_remove_entity(&mut self, _: &mut ClientSocket, _: u32) -> Result<(), ServerError> {
+		Err(ServerError::UnexpectedMessage("RemoveEntity"))
+	}
+
+	fn handle_set_position(&mut self, _: &mut ClientSocket, _: u32, _: Vector3<f32>, _: Vector3<f32>) -> Result<(), ServerError> {
+		// Clients send their Input, the server decides where they end up
+		Err(ServerError::UnexpectedMessage("SetPosition"))
+	}
+
+	fn handle_register(&mut self, client: &mut ClientSocket, username: String, password: String) -> Result<(), ServerError> {
+		if self.is_logged_in(client.id) {
+			return Err(ServerError::UnexpectedMessage("Register"));
+		}
+		let username = username.trim().to_string();
+		let error = if username.is_empty() || password.is_empty() {
+			Some("Please enter a username and a password")
+		} else if self.accounts.iter().any(|a| a.0.name.to_lowercase() == username.to_lowercase()) {
+			Some("This username is already taken")
+		} else {
+			None
+		};
+		if let Some(error) = error {
+			try!(client.send(NetworkMessage::LoginResult { error: Some(error.to_string()) }));
+			return Ok(());
+		}
+
+		let account_id = self.accounts.iter().map(|a| a.0.id).max().unwrap_or(0) + 1;
+		let password = match UserPassword::new(account_id, &password) {
+			Ok(p) => p,
+			Err(e) => {
+				error!("Could not hash password: {:?}", e);
+				try!(client.send(NetworkMessage::LoginResult { error: Some("Could not register, please try again later".to_string()) }));
+				return Ok(());
+			}
+		};
+		info!("Registered user {} with account id {}", username, account_id);
+		self.record(WorldEvent::Registered { account_id: account_id, name: username.clone() });
+		self.accounts.push((User {
+			id: account_id,
+			name: username,
+			position: self.state.spawn_position,
+			rotation: [0.0, 0.0, 0.0],
+		}, password));
+		// Written with the next autosave or at shutdown
+		self.accounts_changed = true;
+
+		let account_index = self.accounts.len() - 1;
+		self.join(client, account_index)
+	}
+
+	fn handle_login(&mut self, client: &mut ClientSocket, username: String, password: String) -> Result<(), ServerError> {
+		if self.is_logged_in(client.id) {
+			return Err(ServerError::UnexpectedMessage("Login"));
+		}
+		let username = username.trim();
+		let account_index = self.accounts.iter().position(|a| a.0.name.to_lowercase() == username.to_lowercase());
+		// Verify against a dummy hash for unknown users, so the response time doesn't tell which usernames exist
+		// This hashes inline, the rate limit on Login keeps a client from making the server do it too often
+		let is_valid = match account_index {
+			Some(index) => self.accounts[index].1.verify(&password),
+			None => self.dummy_password.verify(&password)
+		};
+		let account_index = match account_index {
+			Some(index) if is_valid => index,
+			_ => {
+				// Don't tell the client which of the two was wrong
+				try!(client.send(NetworkMessage::LoginResult { error: Some("Invalid username or password".to_string()) }));
+				return Ok(());
+			}
+		};
+
+		let account_id = self.accounts[account_index].0.id;
+		match self.players.iter().position(|p| p.account_id == account_id) {
+			// The password is as good as the session token
+			Some(index) if self.players[index].client_id.is_none() => self.attach(client, index),
+			Some(_) => {
+				try!(client.send(NetworkMessage::LoginResult { error: Some("This user is already logged in".to_string()) }));
+				Ok(())
+			},
+			None => self.join(client, account_index)
+		}
+	}
+
+	fn handle_login_result(&mut self, _: &mut ClientSocket, _: Option<String>) -> Result<(), ServerError> {
+		Err(ServerError::UnexpectedMessage("LoginResult"))
+	}
+
+	fn handle_server_shutdown(&mut self, _: &mut ClientSocket) -> Result<(), ServerError> {
+		Err(ServerError::UnexpectedMessage("ServerShutdown"))
+	}
+
+	fn handle_input(&mut self, client: &mut ClientSocket, sequence: u32, input: PlayerInput) -> Result<(), ServerError> {
+		let (tolerance, budget) = (self.movement_tolerance, self.violation_budget);
+		let index = match self.players.iter().position(|p| p.client_id == Some(client.id)) {
+			Some(index) => index,
+			None => return Err(ServerError::NotLoggedIn)
+		};
+		let player = &mut self.players[index];
+		// TCP doesn't reorder, so this only happens when the client is misbehaving
+		if sequence <= player.last_input_sequence {
+			return Ok(());
+		}
+		player.last_input_sequence = sequence;
+
+		// Applied to a copy, an illegal move doesn't change the player at all
+		let mut position = player.user.position;
+		let mut rotation = player.user.rotation;
+		apply_input(&mut position, &mut rotation, &input);
+		let now = time::precise_time_s();
+		match player.movement.check(now, tolerance) {
+			Ok(()) => {
+				player.user.position = position;
+				player.user.rotation = rotation;
+			},
+			Err(violation) => {
+				// The player stays where it was, the PlayerState below puts the client back
+				let count = player.movement.violation_count(now);
+				warn!(
+					"Client {} ({}) made an illegal move: {:?}, {} violations in the last {} seconds",
+					client.id, player.user.name, violation, count, VIOLATION_WINDOW
+				);
+				if count > budget {
+					// The ServerSocket drops the client for this, which logs the player out without a grace period
+					return Err(ServerError::Kicked(format!("Too many illegal moves: {:?}", violation)));
+				}
+			}
+		}
+
+		try!(client.send(NetworkMessage::PlayerState {
+			last_sequence: sequence,
+			position: player.user.position,
+			rotation: player.user.rotation,
+		}));
+		Ok(())
+	}
+
+	fn handle_player_state(&mut self, _: &mut ClientSocket, _: u32, _: Vector3<f32>, _: Vector3<f32>) -> Result<(), ServerError> {
+		Err(ServerError::UnexpectedMessage("PlayerState"))
+	}
+
+	fn handle_spawn_entity(&mut self, _: &mut ClientSocket, _: u32, _: Vector3<f32>, _: Vector3<f32>) -> Result<(), ServerError> {
+		Err(ServerError::UnexpectedMessage("SpawnEntity"))
+	}
+
+	fn handle_world_snapshot(&mut self, _: &mut ClientSocket, _: u32, _: f64, _: u32, _: Vec<EntityDelta>) -> Result<(), ServerError> {
+		Err(ServerError::UnexpectedMessage("WorldSnapshot"))
+	}
+
+	fn handle_snapshot_ack(&mut self, client: &mut ClientSocket, sequence: u32) -> Result<(), ServerError> {
+		match self.players.iter_mut().find(|p| p.client_id == Some(client.id)) {
+			Some(player) => {
+				player.snapshots.acknowledge(sequence);
+				Ok(())
+			},
+			None => Err(ServerError::NotLoggedIn)
+		}
+	}
+
+	fn handle_session(&mut self, _: &mut ClientSocket, _: String) -> Result<(), ServerError> {
+		Err(ServerError::UnexpectedMessage("Session"))
+	}
+
+	fn handle_resume(&mut self, client: &mut ClientSocket, token: String) -> Result<(), ServerError> {
+		if self.is_logged_in(client.id) {
+			return Err(ServerError::UnexpectedMessage("Resume"));
+		}
+		// The old connection may not have timed out yet when the client already reconnected, the new one wins
+		match self.players.iter().position(|p| token_matches(&p.session, &token)) {
+			Some(index) => {
+				if let Some(old_client) = self.players[index].client_id {
+					info!("Client {} takes over {} from client {}", client.id, self.players[index].user.name, old_client);
+				} else {
+					info!("{} is back as client {}", self.players[index].user.name, client.id);
+				}
+				self.attach(client, index)
+			},
+			None => {
+				try!(client.send(NetworkMessage::LoginResult { error: Some("The session expired, please log in again".to_string()) }));
+				Ok(())
+			}
+		}
+	}
+}
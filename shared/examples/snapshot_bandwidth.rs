@@ -0,0 +1,79 @@
+//! Compares the bandwidth of sending every player as a SetPosition every tick with delta compressed WorldSnapshots
+//! Run with: cargo run --release --example snapshot_bandwidth
+
+extern crate shared;
+extern crate bincode;
+
+use shared::{NetworkMessage, PlayerInput, QuantizedState, SnapshotEncoder, SIMULATION_RATE, apply_input};
+use bincode::SizeLimit;
+use bincode::rustc_serialize::encode;
+use std::f32::consts::PI;
+
+const PLAYERS: usize = 100;
+const SECONDS: u32 = 10;
+// How many ticks it takes for an acknowledgement to reach the server, 5 ticks is a round trip of 100ms
+const ACK_DELAY: u32 = 5;
+
+// The size of a message on the wire, including the length prefix
+fn frame_size(message: &NetworkMessage) -> usize {
+	encode(message, SizeLimit::Infinite).unwrap().len() + 4
+}
+
+// Three out of ten players walk around in circles, the rest stand still
+fn input(player: usize, tick: u32) -> PlayerInput {
+	if player % 10 < 3 {
+		PlayerInput {
+			forward: 1,
+			strafe: 0,
+			rotation: [0.0, (tick / 25) as f32 * PI / 8.0 + player as f32, 0.0],
+		}
+	} else {
+		PlayerInput::new()
+	}
+}
+
+fn main() {
+	let mut players: Vec<([f32; 3], [f32; 3])> = (0..PLAYERS).map(|i| ([i as f32, 0.0, 0.0], [0.0, 0.0, 0.0])).collect();
+	let mut encoders: Vec<SnapshotEncoder> = (0..PLAYERS).map(|_| SnapshotEncoder::new()).collect();
+	let mut set_position_bytes = 0;
+	let mut snapshot_bytes = 0;
+
+	let ticks = SECONDS * SIMULATION_RATE;
+	for tick in 0..ticks {
+		for (i, player) in players.iter_mut().enumerate() {
+			apply_input(&mut player.0, &mut player.1, &input(i, tick));
+		}
+
+		// Every client got a SetPosition for every player, because every client sends an Input every tick
+		for (i, player) in players.iter().enumerate() {
+			let message = NetworkMessage::SetPosition { uid: i as u32, position: player.0, rotation: player.1 };
+			set_position_bytes += frame_size(&message) * PLAYERS;
+		}
+
+		let states: Vec<(u32, QuantizedState)> = players.iter().enumerate()
+			.map(|(i, p)| (i as u32, QuantizedState::new(p.0, p.1)))
+			.collect();
+		for (i, encoder) in encoders.iter_mut().enumerate() {
+			let state = states.iter().filter(|s| s.0 != i as u32).cloned().collect();
+			let (sequence, baseline, entities) = match encoder.encode(state) {
+				Some(snapshot) => snapshot,
+				None => continue
+			};
+			snapshot_bytes += frame_size(&NetworkMessage::WorldSnapshot {
+				sequence: sequence,
+				time: tick as f64 / SIMULATION_RATE as f64,
+				baseline: baseline,
+				entities: entities,
+			});
+			if sequence > ACK_DELAY {
+				encoder.acknowledge(sequence - ACK_DELAY);
+			}
+		}
+	}
+
+	let per_client = |bytes: usize| bytes as f64 / PLAYERS as f64 / SECONDS as f64 / 1024.0;
+	println!("{} players for {} seconds at {} ticks per second, {} walking", PLAYERS, SECONDS, SIMULATION_RATE, (0..PLAYERS).filter(|i| i % 10 < 3).count());
+	println!("SetPosition:   {:>10} bytes, {:>8.1} KiB/s per client", set_position_bytes, per_client(set_position_bytes));
+	println!("WorldSnapshot: {:>10} bytes, {:>8.1} KiB/s per client", snapshot_bytes, per_client(snapshot_bytes));
+	println!("Snapshots use {:.1}% of the bandwidth", snapshot_bytes as f64 * 100.0 / set_position_bytes as f64);
+}
@@ -0,0 +1,60 @@
+use {NetworkMessage, PlayerInput, EntityDelta};
+use vecmath::Vector3;
+
+/// Implemented by everything that receives NetworkMessages
+/// Every message has its own method without a default implementation,
+/// so adding a message to NetworkMessage doesn't compile until both the server and the client handle it
+/// Messages that are never sent to one side should still be handled there, usually by returning an error
+pub trait MessageHandler {
+	/// Whatever the handler needs besides itself, e.g. the socket the message came from
+	type Context;
+	type Error;
+
+	fn handle_hello(&mut self, context: &mut Self::Context, protocol_version: u32, client_build: String, capabilities: Vec<String>) -> Result<(), Self::Error>;
+	fn handle_welcome(&mut self, context: &mut Self::Context, protocol_version: u32, capabilities: Vec<String>) -> Result<(), Self::Error>;
+	fn handle_reject(&mut self, context: &mut Self::Context, reason: String) -> Result<(), Self::Error>;
+	fn handle_disconnect(&mut self, context: &mut Self::Context, reason: String) -> Result<(), Self::Error>;
+	fn handle_ping(&mut self, context: &mut Self::Context, sequence: u32, time: f64) -> Result<(), Self::Error>;
+	fn handle_pong(&mut self, context: &mut Self::Context, sequence: u32, time: f64) -> Result<(), Self::Error>;
+	fn handle_identify(&mut self, context: &mut Self::Context, uid: u32) -> Result<(), Self::Error>;
+	fn handle_remove_entity(&mut self, context: &mut Self::Context, uid: u32) -> Result<(), Self::Error>;
+	fn handle_spawn_entity(&mut self, context: &mut Self::Context, uid: u32, position: Vector3<f32>, rotation: Vector3<f32>) -> Result<(), Self::Error>;
+	fn handle_set_position(&mut self, context: &mut Self::Context, uid: u32, position: Vector3<f32>, rotation: Vector3<f32>) -> Result<(), Self::Error>;
+	fn handle_register(&mut self, context: &mut Self::Context, username: String, password: String) -> Result<(), Self::Error>;
+	fn handle_login(&mut self, context: &mut Self::Context, username: String, password: String) -> Result<(), Self::Error>;
+	fn handle_login_result(&mut self, context: &mut Self::Context, error: Option<String>) -> Result<(), Self::Error>;
+	fn handle_server_shutdown(&mut self, context: &mut Self::Context) -> Result<(), Self::Error>;
+	fn handle_input(&mut self, context: &mut Self::Context, sequence: u32, input: PlayerInput) -> Result<(), Self::Error>;
+	fn handle_player_state(&mut self, context: &mut Self::Context, last_sequence: u32, position: Vector3<f32>, rotation: Vector3<f32>) -> Result<(), Self::Error>;
+	fn handle_world_snapshot(&mut self, context: &mut Self::Context, sequence: u32, time: f64, baseline: u32, entities: Vec<EntityDelta>) -> Result<(), Self::Error>;
+	fn handle_snapshot_ack(&mut self, context: &mut Self::Context, sequence: u32) -> Result<(), Self::Error>;
+	fn handle_session(&mut self, context: &mut Self::Context, token: String) -> Result<(), Self::Error>;
+	fn handle_resume(&mut self, context: &mut Self::Context, token: String) -> Result<(), Self::Error>;
+}
+
+/// Call the method of the handler that belongs to the message
+pub fn dispatch<H: MessageHandler>(handler: &mut H, context: &mut H::Context, message: NetworkMessage) -> Result<(), H::Error> {
+	match message {
+		NetworkMessage::Hello { protocol_version, client_build, capabilities } => handler.handle_hello(context, protocol_version, client_build, capabilities),
+		NetworkMessage::Welcome { protocol_version, capabilities } => handler.handle_welcome(context, protocol_version, capabilities),
+		NetworkMessage::Reject { reason } => handler.handle_reject(context, reason),
+		NetworkMessage::Disconnect { reason } => handler.handle_disconnect(context, reason),
+		NetworkMessage::None => Ok(()),
+		NetworkMessage::Ping { sequence, time } => handler.handle_ping(context, sequence, time),
+		NetworkMessage::Pong { sequence, time } => handler.handle_pong(context, sequence, time),
+		NetworkMessage::Identify(uid) => handler.handle_identify(context, uid),
+		NetworkMessage::RemoveEntity { uid } => handler.handle_remove_entity(context, uid),
+		NetworkMessage::SpawnEntity { uid, position, rotation } => handler.handle_spawn_entity(context, uid, position, rotation),
+		NetworkMessage::SetPosition { uid, position, rotation } => handler.handle_set_position(context, uid, position, rotation),
+		NetworkMessage::Register { username, password } => handler.handle_register(context, username, password),
+		NetworkMessage::Login { username, password } => handler.handle_login(context, username, password),
+		NetworkMessage::LoginResult { error } => handler.handle_login_result(context, error),
+		NetworkMessage::ServerShutdown => handler.handle_server_shutdown(context),
+		NetworkMessage::Input { sequence, input } => handler.handle_input(context, sequence, input),
+		NetworkMessage::PlayerState { last_sequence, position, rotation } => handler.handle_player_state(context, last_sequence, position, rotation),
+		NetworkMessage::WorldSnapshot { sequence, time, baseline, entities } => handler.handle_world_snapshot(context, sequence, time, baseline, entities),
+		NetworkMessage::SnapshotAck { sequence } => handler.handle_snapshot_ack(context, sequence),
+		NetworkMessage::Session { token } => handler.handle_session(context, token),
+		NetworkMessage::Resume { token } => handler.handle_resume(context, token),
+	}
+}
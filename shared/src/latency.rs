@@ -0,0 +1,105 @@
+use NetworkMessage;
+
+/// How much a new round trip time moves the smoothed one, the same weight TCP uses
+const RTT_GAIN: f64 = 0.125;
+
+/// How much a new round trip time moves the jitter, the same weight RTP uses
+const JITTER_GAIN: f64 = 0.0625;
+
+/// The amount of recent answers the clock offset is picked from
+pub const CLOCK_SAMPLES: usize = 8;
+
+/// Pings that weren't answered yet are remembered up to this amount, older ones are considered lost
+pub const MAX_OUTSTANDING_PINGS: usize = 32;
+
+/// Measures the connection to a peer with Pings and the Pongs it answers them with
+/// Both sides ping each other, so both know the round trip time, the client also uses it to find the clock of the server
+#[derive(Debug, Clone)]
+pub struct PingTracker {
+	next_sequence: u32,
+	// (sequence, send time) of the pings without an answer, oldest first
+	outstanding: Vec<(u32, f64)>,
+	/// The amount of pings in a row the peer didn't answer
+	pub missed: u32,
+	/// The smoothed round trip time in seconds, None until the first answer
+	pub rtt: Option<f64>,
+	/// How much the round trip time changes from one answer to the next, in seconds
+	pub jitter: f64,
+	last_sample: Option<f64>,
+	// (round trip time, clock offset) of the most recent answers
+	clock_samples: Vec<(f64, f64)>,
+}
+
+impl PingTracker {
+	pub fn new() -> PingTracker {
+		PingTracker {
+			next_sequence: 1,
+			outstanding: Vec::new(),
+			missed: 0,
+			rtt: None,
+			jitter: 0.0,
+			last_sample: None,
+			clock_samples: Vec::new(),
+		}
+	}
+
+	/// The next Ping to send, now is the time it is sent by our clock
+	pub fn ping(&mut self, now: f64) -> NetworkMessage {
+		let sequence = self.next_sequence;
+		self.next_sequence = self.next_sequence.wrapping_add(1);
+		self.outstanding.push((sequence, now));
+		if self.outstanding.len() > MAX_OUTSTANDING_PINGS {
+			self.outstanding.remove(0);
+		}
+		self.missed += 1;
+		NetworkMessage::Ping { sequence: sequence, time: now }
+	}
+
+	/// Handle the Pong to one of our pings, peer_time is when the peer answered by its own clock
+	/// Returns false when we didn't send that ping, or forgot about it, the Pong is ignored then
+	pub fn pong(&mut self, sequence: u32, peer_time: f64, now: f64) -> bool {
+		let index = match self.outstanding.iter().position(|p| p.0 == sequence) {
+			Some(index) => index,
+			None => return false
+		};
+		let sent = self.outstanding[index].1;
+		// Older pings that are still unanswered were lost, or their answers will come too late to matter
+		self.outstanding.drain(..index + 1);
+		self.missed = 0;
+
+		let rtt = (now - sent).max(0.0);
+		if let Some(last) = self.last_sample {
+			self.jitter += ((rtt - last).abs() - self.jitter) * JITTER_GAIN;
+		}
+		self.last_sample = Some(rtt);
+		self.rtt = Some(match self.rtt {
+			Some(smoothed) => smoothed + (rtt - smoothed) * RTT_GAIN,
+			None => rtt
+		});
+
+		// Like NTP, assume the ping took as long to get there as the pong took to get back
+		let offset = peer_time - (sent + now) / 2.0;
+		self.clock_samples.push((rtt, offset));
+		if self.clock_samples.len() > CLOCK_SAMPLES {
+			self.clock_samples.remove(0);
+		}
+		true
+	}
+
+	/// How far the clock of the peer is ahead of ours, None until the first answer
+	/// The answer with the shortest round trip waited the least in queues, so it is also the least lopsided one
+	pub fn clock_offset(&self) -> Option<f64> {
+		let mut best: Option<(f64, f64)> = None;
+		for &(rtt, offset) in &self.clock_samples {
+			if best.map_or(true, |b| rtt < b.0) {
+				best = Some((rtt, offset));
+			}
+		}
+		best.map(|b| b.1)
+	}
+
+	/// The time by the clock of the peer, when now is the time by ours
+	pub fn peer_time(&self, now: f64) -> f64 {
+		now + self.clock_offset().unwrap_or(0.0)
+	}
+}
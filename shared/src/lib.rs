@@ -4,16 +4,32 @@
 extern crate bincode;
 extern crate byteorder;
 extern crate rustc_serialize;
+extern crate toml;
 extern crate vecmath;
 
+mod protocol;
+mod handler;
+mod simulation;
+mod snapshot;
+mod latency;
+pub mod toml_value;
+#[cfg(test)]
+mod test;
+
+pub use protocol::*;
+pub use handler::*;
+pub use simulation::*;
+pub use snapshot::*;
+pub use latency::*;
+
 use bincode::SizeLimit;
-use bincode::rustc_serialize::{encode, decode};
+use bincode::rustc_serialize::{encode, decode_from};
 
 use byteorder::ByteOrder;
 
 use std::string;
 use std::net::TcpStream;
-use std::io::{Read, Write};
+use std::io::{Read, Write, ErrorKind};
 use std::clone::Clone;
 
 use vecmath::Vector3;
@@ -24,18 +40,71 @@ pub static NO_CONTENT_CODE: i32 = 10035;
 #[cfg(unix)]
 pub static NO_CONTENT_CODE: i32 = 35;
 
+/// The default maximum size of a single frame, without the 4 byte length prefix
+/// A peer announcing a bigger frame is considered hostile and gets disconnected
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// The default amount of bytes that can be queued for a peer before it is considered stuck
+pub const DEFAULT_MAX_OUTGOING_SIZE: usize = 1024 * 1024;
+
+// The maximum amount of bytes read from a single socket in one poll
+const MAX_READ_PER_POLL: usize = 1024 * 1024;
+
 // TODO: Move this all to it's own file
 
 #[derive(RustcEncodable, RustcDecodable, PartialEq, Debug, Clone)]
 pub enum NetworkMessage {
+	// The handshake messages are at the top so their encoding is the same in every protocol version
+	// Disconnect is one of them, because the server can send it before it knows the version of the client
+	Hello { protocol_version: u32, client_build: String, capabilities: Vec<String> },
+	Welcome { protocol_version: u32, capabilities: Vec<String> },
+	Reject { reason: String },
+	/// Sent right before the connection is closed, this can happen before the handshake is finished as well
+	Disconnect { reason: String },
+
 	None,
-	Ping,
-	PingResult(u32),
+	/// Either side can ping the other, sequence is only used to match the Pong, time is when it was sent by the clock of the sender
+	Ping { sequence: u32, time: f64 },
+	/// The answer to the Ping with the same sequence, time is when it was answered by the clock of the one answering
+	Pong { sequence: u32, time: f64 },
 	Identify(u32),
+	/// An entity went out of sight, it is also gone from every snapshot the next WorldSnapshots are made against
 	RemoveEntity { uid: u32 },
-	SetPosition { uid: u32, position: Vector3<f32>, rotation: Vector3<f32> }
+	/// An entity came close enough for the client to see it, it is updated by the WorldSnapshots from now on
+	SpawnEntity { uid: u32, position: Vector3<f32>, rotation: Vector3<f32> },
+	SetPosition { uid: u32, position: Vector3<f32>, rotation: Vector3<f32> },
+	Register { username: String, password: String },
+	Login { username: String, password: String },
+	/// The answer to Register and Login, error is None when the user is logged in
+	LoginResult { error: Option<String> },
+	/// Sent to every client right before the server closes their connection
+	ServerShutdown,
+	/// What the player did during one simulation step, sequence increases by 1 for every step
+	Input { sequence: u32, input: PlayerInput },
+	/// Where the server put the player after handling every Input up to and including last_sequence
+	PlayerState { last_sequence: u32, position: Vector3<f32>, rotation: Vector3<f32> },
+	/// The other entities the client can see, as the changes since the snapshot with sequence baseline
+	/// A baseline of 0 means every entity is in entities with all of its fields
+	/// time is when the server made the snapshot by its own clock
+	/// Once the client acknowledged a snapshot without changes, nothing is sent until something changes
+	/// Entities only appear and disappear with SpawnEntity and RemoveEntity
+	WorldSnapshot { sequence: u32, time: f64, baseline: u32, entities: Vec<EntityDelta> },
+	/// The client received the WorldSnapshot with this sequence, the next ones can be made against it
+	SnapshotAck { sequence: u32 },
+	/// Sent after logging in, a client that loses the connection can Resume its player with this token
+	Session { token: String },
+	/// Take the player of the session back after reconnecting, the answer is a LoginResult like for Login
+	Resume { token: String },
 }
 
+/// Every value NetworkMessage::name can return, for checking settings that are per kind of message
+/// In the order of the enum, test::network::every_message_name_is_listed checks it against name
+pub const MESSAGE_NAMES: &'static [&'static str] = &[
+	"Hello", "Welcome", "Reject", "Disconnect", "None", "Ping", "Pong", "Identify", "RemoveEntity", "SpawnEntity",
+	"SetPosition", "Register", "Login", "LoginResult", "ServerShutdown", "Input", "PlayerState", "WorldSnapshot",
+	"SnapshotAck", "Session", "Resume",
+];
+
 macro_rules! compare_branches {
 	( $first:ident, $second:ident, $( $x:pat ), * ) => {
 		match ($first, $second) {
@@ -48,14 +117,56 @@ macro_rules! compare_branches {
 }
 
 impl NetworkMessage {
+	/// The name of the variant, for logging and for settings that differ per kind of message
+	pub fn name(&self) -> &'static str {
+		match *self {
+			NetworkMessage::Hello { .. } => "Hello",
+			NetworkMessage::Welcome { .. } => "Welcome",
+			NetworkMessage::Reject { .. } => "Reject",
+			NetworkMessage::Disconnect { .. } => "Disconnect",
+			NetworkMessage::None => "None",
+			NetworkMessage::Ping { .. } => "Ping",
+			NetworkMessage::Pong { .. } => "Pong",
+			NetworkMessage::Identify(_) => "Identify",
+			NetworkMessage::RemoveEntity { .. } => "RemoveEntity",
+			NetworkMessage::SpawnEntity { .. } => "SpawnEntity",
+			NetworkMessage::SetPosition { .. } => "SetPosition",
+			NetworkMessage::Register { .. } => "Register",
+			NetworkMessage::Login { .. } => "Login",
+			NetworkMessage::LoginResult { .. } => "LoginResult",
+			NetworkMessage::ServerShutdown => "ServerShutdown",
+			NetworkMessage::Input { .. } => "Input",
+			NetworkMessage::PlayerState { .. } => "PlayerState",
+			NetworkMessage::WorldSnapshot { .. } => "WorldSnapshot",
+			NetworkMessage::SnapshotAck { .. } => "SnapshotAck",
+			NetworkMessage::Session { .. } => "Session",
+			NetworkMessage::Resume { .. } => "Resume",
+		}
+	}
+
 	pub fn is_same_type_as(&self, other: &NetworkMessage) -> bool{
 		compare_branches!(self, other,
+			NetworkMessage::Hello { .. },
+			NetworkMessage::Welcome { .. },
+			NetworkMessage::Reject { .. },
+			NetworkMessage::Disconnect { .. },
 			NetworkMessage::None,
-			NetworkMessage::Ping,
-			NetworkMessage::PingResult(_),
+			NetworkMessage::Ping { .. },
+			NetworkMessage::Pong { .. },
 			NetworkMessage::Identify(_),
 			NetworkMessage::RemoveEntity { .. },
-			NetworkMessage::SetPosition { .. }
+			NetworkMessage::SpawnEntity { .. },
+			NetworkMessage::SetPosition { .. },
+			NetworkMessage::Register { .. },
+			NetworkMessage::Login { .. },
+			NetworkMessage::LoginResult { .. },
+			NetworkMessage::ServerShutdown,
+			NetworkMessage::Input { .. },
+			NetworkMessage::PlayerState { .. },
+			NetworkMessage::WorldSnapshot { .. },
+			NetworkMessage::SnapshotAck { .. },
+			NetworkMessage::Session { .. },
+			NetworkMessage::Resume { .. }
 		)
 	}
 }
@@ -74,14 +185,31 @@ pub struct ClientSocket {
 	port: u16,
 	buffer: Vec<u8>,
 	buff: [u8; 1024],
+	max_frame_size: usize,
+	// Set when the peer closed the connection but we still had messages to hand out
+	peer_closed: bool,
+	outgoing: Vec<u8>,
+	max_outgoing_size: usize,
+	/// The total amount of bytes passed to send, including the length prefixes
+	pub bytes_queued: u64,
+	/// The total amount of bytes that were actually written to the socket
+	pub bytes_flushed: u64,
+	/// The id the server assigned to this connection, 0 until it is assigned
 	pub id: u32,
-	pub last_ping_time: f64
+	/// The pings we sent over this connection
+	pub pings: PingTracker,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum ClientError {
 	CouldNotConnect,
 	Disconnected,
+	/// The peer sent a frame that could not be decoded into a NetworkMessage
+	Malformed,
+	/// The peer announced a frame of the given size, which is bigger than the maximum frame size
+	FrameTooLarge(usize),
+	/// The peer is not reading, and the given amount of bytes is waiting to be sent
+	SendBufferFull(usize),
 }
 
 impl ClientError {
@@ -90,8 +218,6 @@ impl ClientError {
 	}
 }
 
-static mut LAST_ID: u32 = 0;
-
 impl Clone for ClientSocket {
 	fn clone(&self) -> ClientSocket {
 		ClientSocket {
@@ -100,37 +226,65 @@ impl Clone for ClientSocket {
 			port: self.port,
 			buffer: self.buffer.clone(),
 			buff: self.buff,
+			max_frame_size: self.max_frame_size,
+			peer_closed: false,
+			outgoing: Vec::new(),
+			max_outgoing_size: self.max_outgoing_size,
+			bytes_queued: self.bytes_queued,
+			bytes_flushed: self.bytes_flushed,
 			id: self.id,
-			last_ping_time: self.last_ping_time,
+			pings: self.pings.clone(),
 		}
 	}
 }
 impl ClientSocket {
 	pub fn create<T: string::ToString>(host: T, port: u16) -> ClientSocket {
-		unsafe { LAST_ID += 1 };
 		ClientSocket {
 			stream: None,
 			host: host.to_string(),
 			port: port,
 			buffer: Vec::new(),
 			buff: [0; 1024],
-			id: unsafe { LAST_ID },
-			last_ping_time: 0f64,
+			max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+			peer_closed: false,
+			outgoing: Vec::new(),
+			max_outgoing_size: DEFAULT_MAX_OUTGOING_SIZE,
+			bytes_queued: 0,
+			bytes_flushed: 0,
+			id: 0,
+			pings: PingTracker::new(),
 		}
 	}
 
 	pub fn from_stream(stream: TcpStream) -> ClientSocket {
-		unsafe { LAST_ID += 1 };
+		// Accepted streams don't inherit the non-blocking mode of the listener on every platform
+		if let Err(e) = stream.set_nonblocking(true) {
+			println!("Could not set stream to non-blocking mode: {:?}", e);
+		}
 		ClientSocket {
 			stream: Some(stream),
 			host: String::new(),
 			port: 0,
 			buffer: Vec::new(),
 			buff: [0; 1024],
-			id: unsafe { LAST_ID },
-			last_ping_time: 0f64,
+			max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+			peer_closed: false,
+			outgoing: Vec::new(),
+			max_outgoing_size: DEFAULT_MAX_OUTGOING_SIZE,
+			bytes_queued: 0,
+			bytes_flushed: 0,
+			id: 0,
+			pings: PingTracker::new(),
 		}
 	}
+	pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+		self.max_frame_size = max_frame_size;
+	}
+
+	pub fn set_max_outgoing_size(&mut self, max_outgoing_size: usize) {
+		self.max_outgoing_size = max_outgoing_size;
+	}
+
 	pub fn is_connected(&self) -> bool {
 		match self.stream {
 			None => false,
@@ -155,52 +309,164 @@ impl ClientSocket {
 
 	pub fn disconnect(&mut self) {
 		self.stream = None;
+		// Whatever was left over belongs to the old connection
+		self.buffer.clear();
+		self.outgoing.clear();
+		self.peer_closed = false;
+		self.pings = PingTracker::new();
 	}
+	/// Read a single message
+	/// Returns Ok(None) when no complete frame has arrived yet
 	pub fn get_message(&mut self) -> Result<Option<NetworkMessage>, ClientError> {
+		if !self.peer_closed && !try!(self.read_available()) {
+			self.peer_closed = true;
+		}
+		match try!(self.next_frame()) {
+			Some((message, _)) => Ok(Some(message)),
+			None if self.peer_closed => Err(ClientError::Disconnected),
+			None => Ok(None)
+		}
+	}
+
+	/// Read until the socket would block and return every complete frame that has arrived
+	/// If the peer closed the connection, the messages it sent before closing are still returned
+	/// and the next call returns ClientError::Disconnected
+	pub fn poll_messages(&mut self) -> Result<Vec<NetworkMessage>, ClientError> {
+		self.poll_sized_messages().map(|messages| messages.into_iter().map(|m| m.0).collect())
+	}
+
+	/// The same as poll_messages, with the size of every frame in bytes including the length prefix
+	pub fn poll_sized_messages(&mut self) -> Result<Vec<(NetworkMessage, usize)>, ClientError> {
+		if self.peer_closed {
+			return Err(ClientError::Disconnected);
+		}
+		let is_open = try!(self.read_available());
+
+		let mut messages = Vec::new();
+		while let Some(message) = try!(self.next_frame()) {
+			messages.push(message);
+		}
+
+		if !is_open {
+			if messages.is_empty() {
+				return Err(ClientError::Disconnected);
+			}
+			self.peer_closed = true;
+		}
+		Ok(messages)
+	}
+
+	// Returns false if the peer closed the connection
+	fn read_available(&mut self) -> Result<bool, ClientError> {
 		let mut stream = match self.stream {
 			None => return Err(ClientError::Disconnected),
 			Some(ref s) => s
 		};
 
-		match stream.read(&mut self.buff) {
-			Ok(size) => {
-				if size == 0 {
-					return Err(ClientError::Disconnected);
-				}
-				self.buffer.extend_from_slice(&self.buff[0..size]);
-			},
-			Err(e) => {
-				if let Some(os_error) = e.raw_os_error() {
-					if os_error == NO_CONTENT_CODE {
-						return Ok(None);
+		let mut total_size = 0;
+		// Leave the rest for the next poll, so a single client can't keep us busy forever
+		while total_size < MAX_READ_PER_POLL {
+			match stream.read(&mut self.buff) {
+				Ok(0) => return Ok(false),
+				Ok(size) => {
+					self.buffer.extend_from_slice(&self.buff[0..size]);
+					total_size += size;
+				},
+				Err(e) => {
+					match e.kind() {
+						ErrorKind::WouldBlock => break,
+						ErrorKind::Interrupted => continue,
+						_ => {
+							println!("{:?}", e);
+							return Err(ClientError::Disconnected);
+						}
 					}
 				}
-				println!("{:?}", e);
-				return Err(ClientError::Disconnected);
 			}
 		}
+		Ok(true)
+	}
+
+	// Returns the message and the size of its frame
+	fn next_frame(&mut self) -> Result<Option<(NetworkMessage, usize)>, ClientError> {
 		if self.buffer.len() < 4 {
 			return Ok(None);
 		}
 		let len = byteorder::BigEndian::read_u32(self.buffer.as_slice()) as usize;
+		// Check this before waiting for the rest of the frame, so a peer can't make us buffer gigabytes
+		if len > self.max_frame_size {
+			return Err(ClientError::FrameTooLarge(len));
+		}
 		if len + 4 <= self.buffer.len() {
 			let message: Vec<u8> = self.buffer.drain(0..4 + len).skip(4).collect();
-			let decoded: NetworkMessage = decode(&message).unwrap();// TODO: Deal with unwrap
-			return Ok(Some(decoded));
+			// Bound the decoder by the frame size, so a corrupted length inside the frame can't allocate more than that
+			return match decode_from(&mut message.as_slice(), SizeLimit::Bounded(len as u64)) {
+				Ok(decoded) => Ok(Some((decoded, len + 4))),
+				Err(e) => {
+					println!("Could not decode message: {:?}", e);
+					Err(ClientError::Malformed)
+				}
+			};
 		}
 		Ok(None)
 	}
+
+	/// Queue a message and write as much of the outgoing buffer as the socket accepts
+	/// Fails with ClientError::SendBufferFull when the peer stopped reading for too long
 	pub fn send(&mut self, message: NetworkMessage) -> Result<(), ClientError> {
+		if self.stream.is_none() {
+			return Err(ClientError::Disconnected);
+		}
+		let bytes = match encode(&message, SizeLimit::Infinite) {
+			Ok(b) => b,
+			Err(_) => return Err(ClientError::Malformed)
+		};
+		let mut len_bytes: [u8; 4] = [0; 4];
+		byteorder::BigEndian::write_u32(&mut len_bytes, bytes.len() as u32);
+
+		self.outgoing.extend_from_slice(&len_bytes);
+		self.outgoing.extend_from_slice(&bytes);
+		self.bytes_queued += (len_bytes.len() + bytes.len()) as u64;
+
+		try!(self.flush());
+		if self.outgoing.len() > self.max_outgoing_size {
+			return Err(ClientError::SendBufferFull(self.outgoing.len()));
+		}
+		Ok(())
+	}
+
+	/// Write as much of the outgoing buffer as the socket accepts without blocking
+	/// This should be called every tick, so queued messages go out even when nothing new is sent
+	pub fn flush(&mut self) -> Result<(), ClientError> {
 		let mut stream = match self.stream {
 			Some(ref s) => s,
 			None => return Err(ClientError::Disconnected)
 		};
-		let bytes = encode(&message, SizeLimit::Infinite).unwrap();// TODO: Deal with unwrap
-		let mut len_bytes: [u8; 4] = [0; 4];
-		byteorder::BigEndian::write_u32(&mut len_bytes, bytes.len() as u32);
 
-		if stream.write(&len_bytes).is_err() { return Err(ClientError::Disconnected); }
-		if stream.write(&bytes).is_err() { return Err(ClientError::Disconnected); }
+		let mut written = 0;
+		while written < self.outgoing.len() {
+			match stream.write(&self.outgoing[written..]) {
+				Ok(0) => return Err(ClientError::Disconnected),
+				Ok(size) => written += size,
+				Err(e) => {
+					match e.kind() {
+						ErrorKind::WouldBlock => break,
+						ErrorKind::Interrupted => continue,
+						_ => {
+							println!("{:?}", e);
+							return Err(ClientError::Disconnected);
+						}
+					}
+				}
+			}
+		}
+		self.outgoing.drain(0..written);
+		self.bytes_flushed += written as u64;
 		Ok(())
 	}
+
+	/// The amount of bytes that are queued but not written to the socket yet
+	pub fn pending_bytes(&self) -> usize {
+		self.outgoing.len()
+	}
 }
@@ -0,0 +1,36 @@
+use NetworkMessage;
+
+/// The version of the NetworkMessage layout
+/// This has to be increased every time a message is added, removed or changed
+pub const PROTOCOL_VERSION: u32 = 10;
+
+/// The optional features this build knows about
+/// Both sides only use the capabilities that the other side also supports
+pub const CAPABILITIES: &'static [&'static str] = &[];
+
+/// Create the Hello message that the client sends directly after connecting
+/// The build of the client is only used for logging on the server
+pub fn hello(client_build: &str) -> NetworkMessage {
+	NetworkMessage::Hello {
+		protocol_version: PROTOCOL_VERSION,
+		client_build: client_build.to_string(),
+		capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+	}
+}
+
+/// Check the first message of a client
+/// Returns the capabilities both sides support, or the reason the client should be rejected
+pub fn negotiate(message: &NetworkMessage) -> Result<Vec<String>, String> {
+	match *message {
+		NetworkMessage::Hello { protocol_version, ref client_build, ref capabilities } => {
+			if protocol_version != PROTOCOL_VERSION {
+				return Err(format!(
+					"Protocol version mismatch: client {} speaks version {}, server speaks version {}. Please update your client.",
+					client_build, protocol_version, PROTOCOL_VERSION
+				));
+			}
+			Ok(capabilities.iter().filter(|c| CAPABILITIES.contains(&c.as_str())).cloned().collect())
+		},
+		_ => Err("Expected a Hello message".to_string())
+	}
+}
@@ -0,0 +1,99 @@
+use vecmath::{Vector3, vec3_normalized, vec3_square_len};
+
+/// The amount of simulation steps per second, this is the same on the client and the server
+pub const SIMULATION_RATE: u32 = 50;
+
+/// The length of one simulation step in seconds
+pub const TIMESTEP: f64 = 1.0 / SIMULATION_RATE as f64;
+
+/// Units per second that a player walks
+pub const MOVE_SPEED: f32 = 5.0;
+
+// If the game falls this many steps behind, the rest of the time is dropped instead of being caught up
+// Otherwise a slow step causes more steps next frame, which are slow as well, until the game stops responding
+const MAX_STEPS_PER_ADVANCE: u32 = 10;
+
+/// Turns the time between frames into a whole amount of fixed length simulation steps
+/// The time that doesn't fill a whole step is kept for the next frame
+pub struct FixedTimestep {
+	accumulator: f64,
+	/// The amount of steps that were taken in total
+	pub tick: u64,
+}
+
+impl FixedTimestep {
+	pub fn new() -> FixedTimestep {
+		FixedTimestep {
+			accumulator: 0.0,
+			tick: 0,
+		}
+	}
+
+	/// Add the seconds that passed since the last call, and return how many steps should be run
+	pub fn advance(&mut self, elapsed: f64) -> u32 {
+		if elapsed > 0.0 {
+			self.accumulator += elapsed;
+		}
+		let mut steps = 0;
+		while self.accumulator >= TIMESTEP {
+			self.accumulator -= TIMESTEP;
+			steps += 1;
+			if steps == MAX_STEPS_PER_ADVANCE {
+				self.accumulator = 0.0;
+				break;
+			}
+		}
+		self.tick += steps as u64;
+		steps
+	}
+
+	/// How far we are from the last step to the next one, between 0 and 1
+	/// Rendering blends the state before and after the last step with this, so movement looks smooth at any frame rate
+	pub fn alpha(&self) -> f32 {
+		(self.accumulator / TIMESTEP) as f32
+	}
+}
+
+/// What a player wants to do during a single simulation step
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug, Clone, Copy)]
+pub struct PlayerInput {
+	/// 1 to move forward, -1 to move back
+	pub forward: i8,
+	/// 1 to move right, -1 to move left
+	pub strafe: i8,
+	/// Where the player is looking, movement is relative to this
+	pub rotation: Vector3<f32>,
+}
+
+impl PlayerInput {
+	pub fn new() -> PlayerInput {
+		PlayerInput {
+			forward: 0,
+			strafe: 0,
+			rotation: [0.0, 0.0, 0.0],
+		}
+	}
+}
+
+/// Move a player for a single simulation step
+/// This is the only place that decides how players move, so the client and server always agree
+pub fn apply_input(position: &mut Vector3<f32>, rotation: &mut Vector3<f32>, input: &PlayerInput) {
+	*rotation = input.rotation;
+
+	let mut direction = [clamp(input.strafe) as f32, 0.0, clamp(input.forward) as f32];
+	if vec3_square_len(direction) != 0.0 {
+		direction = vec3_normalized(direction);
+	}
+
+	// Turn the direction around the y axis, so forward is where the player is looking
+	let sin_angle = (-rotation[1]).sin();
+	let cos_angle = (-rotation[1]).cos();
+	let distance = MOVE_SPEED * TIMESTEP as f32;
+	position[0] += (direction[0] * cos_angle - direction[2] * sin_angle) * distance;
+	position[2] += (direction[0] * sin_angle + direction[2] * cos_angle) * distance;
+}
+
+// Anything else is a modified client trying to walk faster
+fn clamp(value: i8) -> i8 {
+	if value > 0 { 1 } else if value < 0 { -1 } else { 0 }
+}
@@ -0,0 +1,242 @@
+use vecmath::Vector3;
+use std::f32::consts::PI;
+
+/// Quantized positions are stored in steps of 1 / POSITION_SCALE units
+pub const POSITION_SCALE: f32 = 100.0;
+
+// Quantized angles cover -PI to PI in the whole range of an i16
+const ANGLE_SCALE: f32 = 32768.0 / PI;
+
+/// The amount of snapshots the server remembers for a client that didn't acknowledge them yet
+/// When the client falls further behind than this, it gets a full snapshot instead of a delta
+pub const MAX_UNACKNOWLEDGED_SNAPSHOTS: usize = 32;
+
+/// The state of an entity the way it is sent over the network
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug, Clone, Copy)]
+pub struct QuantizedState {
+	pub position: [i32; 3],
+	pub rotation: [i16; 3],
+}
+
+impl QuantizedState {
+	pub fn new(position: Vector3<f32>, rotation: Vector3<f32>) -> QuantizedState {
+		QuantizedState {
+			position: [
+				quantize_position(position[0]),
+				quantize_position(position[1]),
+				quantize_position(position[2]),
+			],
+			rotation: [
+				quantize_angle(rotation[0]),
+				quantize_angle(rotation[1]),
+				quantize_angle(rotation[2]),
+			],
+		}
+	}
+
+	pub fn position(&self) -> Vector3<f32> {
+		[
+			self.position[0] as f32 / POSITION_SCALE,
+			self.position[1] as f32 / POSITION_SCALE,
+			self.position[2] as f32 / POSITION_SCALE,
+		]
+	}
+
+	/// Every angle is between -PI and PI
+	pub fn rotation(&self) -> Vector3<f32> {
+		[
+			self.rotation[0] as f32 / ANGLE_SCALE,
+			self.rotation[1] as f32 / ANGLE_SCALE,
+			self.rotation[2] as f32 / ANGLE_SCALE,
+		]
+	}
+}
+
+fn quantize_position(value: f32) -> i32 {
+	(value * POSITION_SCALE).round() as i32
+}
+
+fn quantize_angle(angle: f32) -> i16 {
+	// Wrap to -PI..PI first, the camera can turn around as often as it likes
+	let mut angle = angle % (2.0 * PI);
+	if angle >= PI {
+		angle -= 2.0 * PI;
+	} else if angle < -PI {
+		angle += 2.0 * PI;
+	}
+	let value = (angle * ANGLE_SCALE).round();
+	// PI itself rounds to 32768, which is the same angle as -PI
+	if value >= 32768.0 { -32768 } else { value as i16 }
+}
+
+/// The fields of an entity that changed since the baseline, a field that didn't change is None
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug, Clone)]
+pub struct EntityDelta {
+	pub uid: u32,
+	pub position: Option<[i32; 3]>,
+	pub rotation: Option<[i16; 3]>,
+}
+
+/// The state of every entity a client can see, with the uid of each entity
+pub type SnapshotState = Vec<(u32, QuantizedState)>;
+
+/// The entities that changed between two states
+/// Entities that are missing from current are left alone, they only leave a state through forget
+pub fn delta(baseline: &SnapshotState, current: &SnapshotState) -> Vec<EntityDelta> {
+	let mut entities = Vec::new();
+	for &(uid, ref state) in current {
+		let old = baseline.iter().find(|e| e.0 == uid).map(|e| &e.1);
+		let position = if old.map(|o| o.position) == Some(state.position) { None } else { Some(state.position) };
+		let rotation = if old.map(|o| o.rotation) == Some(state.rotation) { None } else { Some(state.rotation) };
+		if position.is_some() || rotation.is_some() {
+			entities.push(EntityDelta {
+				uid: uid,
+				position: position,
+				rotation: rotation,
+			});
+		}
+	}
+	entities
+}
+
+/// Apply a delta to a baseline
+/// Returns None when the delta adds an entity without giving all of its fields
+pub fn apply_delta(baseline: &SnapshotState, entities: &[EntityDelta]) -> Option<SnapshotState> {
+	let mut state = baseline.clone();
+	for entity in entities {
+		if let Some(existing) = state.iter_mut().find(|e| e.0 == entity.uid) {
+			if let Some(position) = entity.position {
+				existing.1.position = position;
+			}
+			if let Some(rotation) = entity.rotation {
+				existing.1.rotation = rotation;
+			}
+			continue;
+		}
+		match (entity.position, entity.rotation) {
+			(Some(position), Some(rotation)) => state.push((entity.uid, QuantizedState {
+				position: position,
+				rotation: rotation,
+			})),
+			_ => return None
+		}
+	}
+	Some(state)
+}
+
+/// Remembers what was sent to a single client, so the next snapshot only contains what changed since the client's last acknowledgement
+pub struct SnapshotEncoder {
+	next_sequence: u32,
+	// The newest snapshot the client acknowledged, every delta is made against this
+	baseline: Option<(u32, SnapshotState)>,
+	// Whether the baseline changed anything, as long as it did the client needs a snapshot without changes to see that everything stopped
+	baseline_changed: bool,
+	// Snapshots that were sent but not acknowledged yet, oldest first, with whether they changed anything
+	sent: Vec<(u32, SnapshotState, bool)>,
+}
+
+impl SnapshotEncoder {
+	pub fn new() -> SnapshotEncoder {
+		SnapshotEncoder {
+			next_sequence: 1,
+			baseline: None,
+			baseline_changed: false,
+			sent: Vec::new(),
+		}
+	}
+
+	/// Create the snapshot for the given state
+	/// Returns (sequence, baseline, entities), a baseline of 0 means the snapshot contains everything
+	/// Returns None once the client acknowledged a snapshot without changes and nothing changed since
+	pub fn encode(&mut self, state: SnapshotState) -> Option<(u32, u32, Vec<EntityDelta>)> {
+		let sequence = self.next_sequence;
+
+		let (baseline, entities) = match self.baseline {
+			// The client only keeps MAX_UNACKNOWLEDGED_SNAPSHOTS snapshots, so an older baseline is gone there
+			Some((baseline, ref baseline_state)) if sequence - baseline < MAX_UNACKNOWLEDGED_SNAPSHOTS as u32 => {
+				(baseline, delta(baseline_state, &state))
+			},
+			_ => (0, delta(&Vec::new(), &state))
+		};
+		// Unacknowledged snapshots may have moved the entities of the client away from the baseline, so it gets one even when nothing changed
+		if entities.is_empty() && self.sent.is_empty() && !self.baseline_changed {
+			return None;
+		}
+
+		self.next_sequence += 1;
+		self.sent.push((sequence, state, !entities.is_empty()));
+		if self.sent.len() > MAX_UNACKNOWLEDGED_SNAPSHOTS {
+			self.sent.remove(0);
+		}
+		Some((sequence, baseline, entities))
+	}
+
+	/// The entity was removed on the client with RemoveEntity, so none of the snapshots it has contain it anymore
+	pub fn forget(&mut self, uid: u32) {
+		if let Some((_, ref mut state)) = self.baseline {
+			state.retain(|e| e.0 != uid);
+		}
+		for &mut (_, ref mut state, _) in &mut self.sent {
+			state.retain(|e| e.0 != uid);
+		}
+	}
+
+	/// The client received the snapshot with the given sequence, it becomes the new baseline
+	/// Acknowledgements of snapshots we don't remember anymore, or never sent, are ignored
+	pub fn acknowledge(&mut self, sequence: u32) {
+		if let Some(index) = self.sent.iter().position(|s| s.0 == sequence) {
+			let mut acknowledged: Vec<(u32, SnapshotState, bool)> = self.sent.drain(..index + 1).collect();
+			if let Some((sequence, state, changed)) = acknowledged.pop() {
+				self.baseline = Some((sequence, state));
+				self.baseline_changed = changed;
+			}
+		}
+	}
+
+	/// The sequence of the snapshot that deltas are currently made against
+	pub fn baseline(&self) -> Option<u32> {
+		self.baseline.as_ref().map(|b| b.0)
+	}
+}
+
+/// Turns the snapshots of the server back into the full state on the client
+pub struct SnapshotDecoder {
+	// Recently received states, newest last, so any of them can be used as a baseline
+	received: Vec<(u32, SnapshotState)>,
+}
+
+impl SnapshotDecoder {
+	pub fn new() -> SnapshotDecoder {
+		SnapshotDecoder {
+			received: Vec::new(),
+		}
+	}
+
+	/// Returns the full state, or None when the baseline is unknown or the snapshot doesn't fit on it
+	pub fn decode(&mut self, sequence: u32, baseline: u32, entities: &[EntityDelta]) -> Option<SnapshotState> {
+		let state = if baseline == 0 {
+			apply_delta(&Vec::new(), entities)
+		} else {
+			match self.received.iter().find(|r| r.0 == baseline) {
+				Some(&(_, ref baseline_state)) => apply_delta(baseline_state, entities),
+				None => None
+			}
+		};
+		if let Some(ref state) = state {
+			// The server won't make deltas against anything older than the baseline it just used
+			self.received.retain(|r| r.0 >= baseline);
+			self.received.push((sequence, state.clone()));
+			if self.received.len() > MAX_UNACKNOWLEDGED_SNAPSHOTS {
+				self.received.remove(0);
+			}
+		}
+		state
+	}
+
+	/// The server removed the entity with RemoveEntity, it forgot it in every snapshot we could get a delta against
+	pub fn forget(&mut self, uid: u32) {
+		for &mut (_, ref mut state) in &mut self.received {
+			state.retain(|e| e.0 != uid);
+		}
+	}
+}
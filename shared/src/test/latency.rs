@@ -0,0 +1,95 @@
+use {NetworkMessage, PingTracker, MAX_OUTSTANDING_PINGS};
+
+fn close(a: f64, b: f64) -> bool {
+	(a - b).abs() < 1e-9
+}
+
+// Send a ping at the given time and return its sequence
+fn ping(tracker: &mut PingTracker, now: f64) -> u32 {
+	match tracker.ping(now) {
+		NetworkMessage::Ping { sequence, time } => {
+			assert_eq!(time, now);
+			sequence
+		},
+		m => panic!("Expected a Ping, got {:?}", m)
+	}
+}
+
+#[test]
+fn first_answer() {
+	let mut tracker = PingTracker::new();
+	assert_eq!(tracker.rtt, None);
+	assert_eq!(tracker.clock_offset(), None);
+	assert_eq!(tracker.peer_time(5.0), 5.0);
+
+	// The peer's clock is 10 seconds ahead, it answered halfway through the round trip
+	let sequence = ping(&mut tracker, 1.0);
+	assert!(tracker.pong(sequence, 11.05, 1.1));
+	assert!(close(tracker.rtt.unwrap(), 0.1));
+	assert!(close(tracker.clock_offset().unwrap(), 10.0));
+	assert!(close(tracker.peer_time(2.0), 12.0));
+	assert_eq!(tracker.jitter, 0.0);
+}
+
+#[test]
+fn rtt_and_jitter_are_smoothed() {
+	let mut tracker = PingTracker::new();
+	let sequence = ping(&mut tracker, 0.0);
+	tracker.pong(sequence, 0.0, 0.1);
+	let sequence = ping(&mut tracker, 1.0);
+	tracker.pong(sequence, 1.0, 1.3);
+
+	assert!(close(tracker.rtt.unwrap(), 0.1 + 0.2 / 8.0));
+	assert!(close(tracker.jitter, 0.2 / 16.0));
+}
+
+#[test]
+fn offset_comes_from_the_shortest_round_trip() {
+	let mut tracker = PingTracker::new();
+	// A slow answer that was held up on the way back, which makes its offset look smaller than it is
+	let sequence = ping(&mut tracker, 0.0);
+	tracker.pong(sequence, 5.05, 0.5);
+	let sequence = ping(&mut tracker, 1.0);
+	tracker.pong(sequence, 6.05, 1.1);
+	let sequence = ping(&mut tracker, 2.0);
+	tracker.pong(sequence, 7.2, 2.4);
+
+	assert!(close(tracker.clock_offset().unwrap(), 5.0));
+}
+
+#[test]
+fn missed_pings() {
+	let mut tracker = PingTracker::new();
+	let first = ping(&mut tracker, 0.0);
+	let second = ping(&mut tracker, 1.0);
+	let third = ping(&mut tracker, 2.0);
+	assert_eq!(tracker.missed, 3);
+
+	assert!(tracker.pong(second, 1.0, 1.1));
+	assert_eq!(tracker.missed, 0);
+	// The answer to the second ping means the first one is lost
+	assert!(!tracker.pong(first, 0.0, 1.2));
+	assert!(tracker.pong(third, 2.0, 2.1));
+	// Answers only count once
+	assert!(!tracker.pong(third, 2.0, 2.1));
+}
+
+#[test]
+fn unknown_answers_are_ignored() {
+	let mut tracker = PingTracker::new();
+	let sequence = ping(&mut tracker, 0.0);
+	assert!(!tracker.pong(sequence + 1, 0.0, 0.1));
+	assert_eq!(tracker.rtt, None);
+	assert_eq!(tracker.missed, 1);
+}
+
+#[test]
+fn old_pings_are_forgotten() {
+	let mut tracker = PingTracker::new();
+	let first = ping(&mut tracker, 0.0);
+	for i in 0..MAX_OUTSTANDING_PINGS {
+		ping(&mut tracker, i as f64 + 1.0);
+	}
+	assert!(!tracker.pong(first, 0.0, 100.0));
+	assert_eq!(tracker.missed, MAX_OUTSTANDING_PINGS as u32 + 1);
+}
@@ -0,0 +1,4 @@
+pub mod latency;
+pub mod network;
+pub mod simulation;
+pub mod snapshot;
@@ -0,0 +1,288 @@
+use std::net::{TcpListener, TcpStream};
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::encode;
+use byteorder::{BigEndian, ByteOrder};
+
+use {ClientSocket, ClientError, NetworkMessage, PlayerInput, PROTOCOL_VERSION, MESSAGE_NAMES, hello, negotiate};
+
+// Returns a socket that is set up like the server sets up its clients, and the raw stream on the other side
+pub fn socket_pair() -> (ClientSocket, TcpStream) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+	let (stream, _) = listener.accept().unwrap();
+	(ClientSocket::from_stream(stream), peer)
+}
+
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+	let mut bytes = vec![0u8; 4];
+	BigEndian::write_u32(&mut bytes, payload.len() as u32);
+	bytes.extend_from_slice(payload);
+	bytes
+}
+
+// The bytes need some time to arrive, so keep polling until we get something other than Ok(None)
+pub fn wait_for_message(socket: &mut ClientSocket) -> Result<Option<NetworkMessage>, ClientError> {
+	for _ in 0..100 {
+		match socket.get_message() {
+			Ok(None) => thread::sleep(Duration::from_millis(10)),
+			x => return x
+		}
+	}
+	Ok(None)
+}
+
+fn set_position() -> NetworkMessage {
+	NetworkMessage::SetPosition { uid: 1, position: [1.0, 2.0, 3.0], rotation: [4.0, 5.0, 6.0] }
+}
+
+#[test]
+fn test_valid_frame() {
+	let (mut socket, mut peer) = socket_pair();
+	peer.write_all(&frame(&encode(&set_position(), SizeLimit::Infinite).unwrap())).unwrap();
+	assert_eq!(wait_for_message(&mut socket), Ok(Some(set_position())));
+}
+
+#[test]
+fn test_truncated_stream() {
+	let (mut socket, mut peer) = socket_pair();
+	let bytes = frame(&encode(&set_position(), SizeLimit::Infinite).unwrap());
+	peer.write_all(&bytes[0..bytes.len() - 5]).unwrap();
+	assert_eq!(wait_for_message(&mut socket), Ok(None));
+
+	// The peer goes away halfway through a frame
+	drop(peer);
+	assert_eq!(wait_for_message(&mut socket), Err(ClientError::Disconnected));
+}
+
+#[test]
+fn test_truncated_payload() {
+	let (mut socket, mut peer) = socket_pair();
+	let payload = encode(&set_position(), SizeLimit::Infinite).unwrap();
+	// The length prefix is consistent, but the message inside of it is cut off
+	peer.write_all(&frame(&payload[0..payload.len() - 4])).unwrap();
+	assert_eq!(wait_for_message(&mut socket), Err(ClientError::Malformed));
+}
+
+#[test]
+fn test_oversized_frame() {
+	let (mut socket, mut peer) = socket_pair();
+	peer.write_all(&[0xff, 0xff, 0xff, 0xff, 0x00]).unwrap();
+	assert_eq!(wait_for_message(&mut socket), Err(ClientError::FrameTooLarge(0xffff_ffff)));
+}
+
+#[test]
+fn test_configured_max_frame_size() {
+	let (mut socket, mut peer) = socket_pair();
+	socket.set_max_frame_size(8);
+	let payload = encode(&set_position(), SizeLimit::Infinite).unwrap();
+	peer.write_all(&frame(&payload)).unwrap();
+	assert_eq!(wait_for_message(&mut socket), Err(ClientError::FrameTooLarge(payload.len())));
+}
+
+#[test]
+fn test_corrupted_frame() {
+	let (mut socket, mut peer) = socket_pair();
+	// An enum variant that doesn't exist
+	peer.write_all(&frame(&[0xff, 0xff, 0xff, 0xff])).unwrap();
+	assert_eq!(wait_for_message(&mut socket), Err(ClientError::Malformed));
+}
+
+#[test]
+fn test_corrupted_length_inside_frame() {
+	let (mut socket, mut peer) = socket_pair();
+	// A Reject message claiming its reason is 2^64 - 1 bytes long
+	let mut payload = vec![0u8; 4];
+	BigEndian::write_u32(&mut payload, 2);
+	payload.extend_from_slice(&[0xff; 8]);
+	peer.write_all(&frame(&payload)).unwrap();
+	assert_eq!(wait_for_message(&mut socket), Err(ClientError::Malformed));
+}
+
+#[test]
+fn test_poll_drains_all_frames() {
+	let (mut socket, mut peer) = socket_pair();
+	let mut bytes = Vec::new();
+	for i in 0..500 {
+		let message = NetworkMessage::SetPosition { uid: i, position: [i as f32, 0.0, 0.0], rotation: [0.0, 0.0, 0.0] };
+		bytes.extend_from_slice(&frame(&encode(&message, SizeLimit::Infinite).unwrap()));
+	}
+	peer.write_all(&bytes).unwrap();
+	thread::sleep(Duration::from_millis(200));
+
+	// A single tick has to hand out every queued message, in order
+	let messages = socket.poll_messages().unwrap();
+	assert_eq!(messages.len(), 500);
+	for (i, message) in messages.iter().enumerate() {
+		assert_eq!(*message, NetworkMessage::SetPosition { uid: i as u32, position: [i as f32, 0.0, 0.0], rotation: [0.0, 0.0, 0.0] });
+	}
+	assert_eq!(socket.poll_messages(), Ok(Vec::new()));
+}
+
+#[test]
+fn test_poll_returns_messages_sent_before_close() {
+	let (mut socket, mut peer) = socket_pair();
+	let reject = NetworkMessage::Reject { reason: "Go away".to_string() };
+	peer.write_all(&frame(&encode(&reject, SizeLimit::Infinite).unwrap())).unwrap();
+	drop(peer);
+	thread::sleep(Duration::from_millis(200));
+
+	assert_eq!(socket.poll_messages(), Ok(vec![reject]));
+	assert_eq!(socket.poll_messages(), Err(ClientError::Disconnected));
+}
+
+fn large_message(i: usize) -> NetworkMessage {
+	NetworkMessage::Reject { reason: format!("{:01000}", i) }
+}
+
+#[test]
+fn test_send_statistics() {
+	let (mut socket, mut peer) = socket_pair();
+	socket.send(set_position()).unwrap();
+	let frame_size = frame(&encode(&set_position(), SizeLimit::Infinite).unwrap()).len() as u64;
+	assert_eq!(socket.bytes_queued, frame_size);
+	assert_eq!(socket.bytes_flushed, frame_size);
+	assert_eq!(socket.pending_bytes(), 0);
+
+	let mut receiver = ClientSocket::from_stream(peer);
+	assert_eq!(wait_for_message(&mut receiver), Ok(Some(set_position())));
+}
+
+#[test]
+fn test_send_queues_when_peer_is_slow() {
+	const MESSAGE_COUNT: usize = 32 * 1024;
+	let (mut sender, peer) = socket_pair();
+	sender.set_max_outgoing_size(MESSAGE_COUNT * 2048);
+
+	// Nobody is reading, so at some point the kernel buffers are full and the rest has to be queued
+	for i in 0..MESSAGE_COUNT {
+		sender.send(large_message(i)).unwrap();
+	}
+	assert!(sender.pending_bytes() > 0);
+	assert!(sender.bytes_flushed < sender.bytes_queued);
+
+	let mut receiver = ClientSocket::from_stream(peer);
+	let mut received = Vec::new();
+	for _ in 0..1000 {
+		sender.flush().unwrap();
+		received.extend(receiver.poll_messages().unwrap());
+		if received.len() == MESSAGE_COUNT {
+			break;
+		}
+		thread::sleep(Duration::from_millis(5));
+	}
+
+	// Partial writes may never corrupt the framing
+	assert_eq!(received.len(), MESSAGE_COUNT);
+	for (i, message) in received.into_iter().enumerate() {
+		assert_eq!(message, large_message(i));
+	}
+	assert_eq!(sender.pending_bytes(), 0);
+	assert_eq!(sender.bytes_flushed, sender.bytes_queued);
+}
+
+#[test]
+fn test_stuck_peer_hits_high_water_mark() {
+	let (mut sender, _peer) = socket_pair();
+	sender.set_max_outgoing_size(64 * 1024);
+
+	let mut result = Ok(());
+	for i in 0..100_000 {
+		result = sender.send(large_message(i));
+		if result.is_err() {
+			break;
+		}
+	}
+	match result {
+		Err(ClientError::SendBufferFull(size)) => assert!(size > 64 * 1024),
+		x => panic!("Expected SendBufferFull, got {:?}", x)
+	}
+}
+
+#[test]
+fn test_sized_messages() {
+	let (mut socket, mut peer) = socket_pair();
+	let bytes = frame(&encode(&set_position(), SizeLimit::Infinite).unwrap());
+	let ping = frame(&encode(&NetworkMessage::Ping { sequence: 1, time: 0.5 }, SizeLimit::Infinite).unwrap());
+	peer.write_all(&bytes).unwrap();
+	peer.write_all(&ping).unwrap();
+	thread::sleep(Duration::from_millis(50));
+	assert_eq!(socket.poll_sized_messages(), Ok(vec![(set_position(), bytes.len()), (NetworkMessage::Ping { sequence: 1, time: 0.5 }, ping.len())]));
+}
+
+// The client sends the given Hello and the server answers it like ServerSocket does, returns the answer the client got
+fn handshake(hello: NetworkMessage) -> NetworkMessage {
+	let (mut server, peer) = socket_pair();
+	let mut client = ClientSocket::from_stream(peer);
+	client.send(hello).unwrap();
+	let received = wait_for_message(&mut server).unwrap().unwrap();
+	let answer = match negotiate(&received) {
+		Ok(capabilities) => NetworkMessage::Welcome { protocol_version: PROTOCOL_VERSION, capabilities: capabilities },
+		Err(reason) => NetworkMessage::Reject { reason: reason },
+	};
+	server.send(answer).unwrap();
+	wait_for_message(&mut client).unwrap().unwrap()
+}
+
+#[test]
+fn matching_versions_are_welcomed() {
+	let answer = handshake(NetworkMessage::Hello {
+		protocol_version: PROTOCOL_VERSION,
+		client_build: "1.2.3".to_string(),
+		capabilities: vec!["teleporting".to_string()],
+	});
+	// Capabilities the server doesn't know about are left out
+	assert_eq!(answer, NetworkMessage::Welcome { protocol_version: PROTOCOL_VERSION, capabilities: Vec::new() });
+	assert_eq!(handshake(hello("1.2.3")), answer);
+}
+
+#[test]
+fn other_versions_are_rejected() {
+	let answer = handshake(NetworkMessage::Hello {
+		protocol_version: PROTOCOL_VERSION + 1,
+		client_build: "1.2.3".to_string(),
+		capabilities: Vec::new(),
+	});
+	match answer {
+		NetworkMessage::Reject { ref reason } => assert!(reason.contains("1.2.3"), "{}", reason),
+		_ => panic!("Expected a Reject, got {:?}", answer)
+	}
+	assert!(negotiate(&NetworkMessage::ServerShutdown).is_err());
+}
+
+#[test]
+fn every_message_name_is_listed() {
+	// One of every variant, in the order of the enum
+	let messages = vec![
+		hello("test"),
+		NetworkMessage::Welcome { protocol_version: PROTOCOL_VERSION, capabilities: Vec::new() },
+		NetworkMessage::Reject { reason: String::new() },
+		NetworkMessage::Disconnect { reason: String::new() },
+		NetworkMessage::None,
+		NetworkMessage::Ping { sequence: 0, time: 0.0 },
+		NetworkMessage::Pong { sequence: 0, time: 0.0 },
+		NetworkMessage::Identify(0),
+		NetworkMessage::RemoveEntity { uid: 0 },
+		NetworkMessage::SpawnEntity { uid: 0, position: [0.0; 3], rotation: [0.0; 3] },
+		NetworkMessage::SetPosition { uid: 0, position: [0.0; 3], rotation: [0.0; 3] },
+		NetworkMessage::Register { username: String::new(), password: String::new() },
+		NetworkMessage::Login { username: String::new(), password: String::new() },
+		NetworkMessage::LoginResult { error: None },
+		NetworkMessage::ServerShutdown,
+		NetworkMessage::Input { sequence: 0, input: PlayerInput::new() },
+		NetworkMessage::PlayerState { last_sequence: 0, position: [0.0; 3], rotation: [0.0; 3] },
+		NetworkMessage::WorldSnapshot { sequence: 0, time: 0.0, baseline: 0, entities: Vec::new() },
+		NetworkMessage::SnapshotAck { sequence: 0 },
+		NetworkMessage::Session { token: String::new() },
+		NetworkMessage::Resume { token: String::new() },
+	];
+	let names: Vec<&str> = messages.iter().map(|m| m.name()).collect();
+	assert_eq!(names, MESSAGE_NAMES.to_vec());
+	// No variant is in there twice
+	for (i, message) in messages.iter().enumerate() {
+		assert!(messages.iter().skip(i + 1).all(|m| !m.is_same_type_as(message)));
+	}
+}
@@ -0,0 +1,75 @@
+use {FixedTimestep, PlayerInput, apply_input, TIMESTEP, MOVE_SPEED};
+
+fn close(a: f32, b: f32) -> bool {
+	(a - b).abs() < 0.0001
+}
+
+#[test]
+fn timestep_keeps_the_remainder() {
+	let mut timestep = FixedTimestep::new();
+	assert_eq!(timestep.advance(TIMESTEP * 0.5), 0);
+	assert!(close(timestep.alpha(), 0.5));
+	assert_eq!(timestep.advance(TIMESTEP * 0.75), 1);
+	assert!(close(timestep.alpha(), 0.25));
+	assert_eq!(timestep.advance(TIMESTEP * 3.0), 3);
+	assert_eq!(timestep.tick, 4);
+}
+
+#[test]
+fn timestep_drops_time_it_cant_catch_up() {
+	let mut timestep = FixedTimestep::new();
+	assert_eq!(timestep.advance(60.0), 10);
+	assert_eq!(timestep.alpha(), 0.0);
+	assert_eq!(timestep.advance(0.0), 0);
+	assert_eq!(timestep.advance(-1.0), 0);
+}
+
+#[test]
+fn same_inputs_give_same_position() {
+	let inputs: Vec<PlayerInput> = (0..200).map(|i| PlayerInput {
+		forward: if i % 3 == 0 { 1 } else { 0 },
+		strafe: if i % 7 < 3 { -1 } else { 1 },
+		rotation: [0.0, i as f32 * 0.01, 0.0],
+	}).collect();
+
+	let run = || {
+		let mut position = [1.0, 2.0, 3.0];
+		let mut rotation = [0.0, 0.0, 0.0];
+		for input in &inputs {
+			apply_input(&mut position, &mut rotation, input);
+		}
+		(position, rotation)
+	};
+	assert_eq!(run(), run());
+}
+
+#[test]
+fn walking_forward_for_a_second() {
+	let mut position = [0.0, 0.0, 0.0];
+	let mut rotation = [0.0, 0.0, 0.0];
+	let input = PlayerInput { forward: 1, strafe: 0, rotation: [0.0, 0.0, 0.0] };
+	for _ in 0..(1.0 / TIMESTEP).round() as u32 {
+		apply_input(&mut position, &mut rotation, &input);
+	}
+	assert!(close(position[0], 0.0));
+	assert!(close(position[2], MOVE_SPEED));
+}
+
+#[test]
+fn diagonal_is_not_faster() {
+	let mut position = [0.0, 0.0, 0.0];
+	let mut rotation = [0.0, 0.0, 0.0];
+	apply_input(&mut position, &mut rotation, &PlayerInput { forward: 1, strafe: 1, rotation: [0.0, 0.0, 0.0] });
+	let distance = (position[0] * position[0] + position[2] * position[2]).sqrt();
+	assert!(close(distance, MOVE_SPEED * TIMESTEP as f32));
+}
+
+#[test]
+fn oversized_inputs_are_clamped() {
+	let mut normal = [0.0, 0.0, 0.0];
+	let mut cheating = [0.0, 0.0, 0.0];
+	let mut rotation = [0.0, 0.0, 0.0];
+	apply_input(&mut normal, &mut rotation, &PlayerInput { forward: 1, strafe: 0, rotation: [0.0, 0.0, 0.0] });
+	apply_input(&mut cheating, &mut rotation, &PlayerInput { forward: 127, strafe: 0, rotation: [0.0, 0.0, 0.0] });
+	assert_eq!(normal, cheating);
+}
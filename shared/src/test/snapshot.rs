@@ -0,0 +1,198 @@
+use bincode::SizeLimit;
+use bincode::rustc_serialize::encode;
+use std::f32::consts::PI;
+
+use {NetworkMessage, QuantizedState, EntityDelta, SnapshotEncoder, SnapshotDecoder, SnapshotState, POSITION_SCALE, MAX_UNACKNOWLEDGED_SNAPSHOTS, delta, apply_delta};
+
+fn state(x: f32, yaw: f32) -> QuantizedState {
+	QuantizedState::new([x, 1.0, -2.0], [0.0, yaw, 0.0])
+}
+
+fn close(a: f32, b: f32, precision: f32) -> bool {
+	(a - b).abs() <= precision
+}
+
+#[test]
+fn quantization_keeps_enough_precision() {
+	let quantized = QuantizedState::new([1.234, -500.5, 10000.0], [0.5, -3.0, 3.1]);
+	let position = quantized.position();
+	let rotation = quantized.rotation();
+	assert!(close(position[0], 1.234, 0.5 / POSITION_SCALE));
+	assert!(close(position[1], -500.5, 0.5 / POSITION_SCALE));
+	assert!(close(position[2], 10000.0, 0.5 / POSITION_SCALE));
+	assert!(close(rotation[0], 0.5, 0.0001));
+	assert!(close(rotation[1], -3.0, 0.0001));
+	assert!(close(rotation[2], 3.1, 0.0001));
+}
+
+#[test]
+fn angles_are_wrapped() {
+	assert_eq!(QuantizedState::new([0.0; 3], [2.0 * PI + 1.0, 0.0, 0.0]), QuantizedState::new([0.0; 3], [1.0, 0.0, 0.0]));
+	assert_eq!(QuantizedState::new([0.0; 3], [-1.0 - 4.0 * PI, 0.0, 0.0]), QuantizedState::new([0.0; 3], [-1.0, 0.0, 0.0]));
+	assert_eq!(QuantizedState::new([0.0; 3], [PI, 0.0, 0.0]), QuantizedState::new([0.0; 3], [-PI, 0.0, 0.0]));
+}
+
+#[test]
+fn delta_only_contains_changed_fields() {
+	let baseline: SnapshotState = vec![(1, state(0.0, 0.0)), (2, state(5.0, 1.0)), (3, state(9.0, 0.0))];
+	let current: SnapshotState = vec![(1, state(0.0, 0.0)), (2, state(6.0, 1.0)), (4, state(1.0, 2.0))];
+	let entities = delta(&baseline, &current);
+
+	assert_eq!(entities, vec![
+		EntityDelta { uid: 2, position: Some(state(6.0, 1.0).position), rotation: None },
+		EntityDelta { uid: 4, position: Some(state(1.0, 2.0).position), rotation: Some(state(1.0, 2.0).rotation) },
+	]);
+	// Entity 3 is only gone once it is forgotten
+	let mut expected = current.clone();
+	expected.insert(2, (3, state(9.0, 0.0)));
+	assert_eq!(apply_delta(&baseline, &entities), Some(expected));
+}
+
+#[test]
+fn new_entities_need_every_field() {
+	let entities = vec![EntityDelta { uid: 1, position: Some([0, 0, 0]), rotation: None }];
+	assert_eq!(apply_delta(&Vec::new(), &entities), None);
+}
+
+#[test]
+fn encoder_uses_the_acknowledged_baseline() {
+	let mut encoder = SnapshotEncoder::new();
+	let first = encoder.encode(vec![(1, state(0.0, 0.0)), (2, state(0.0, 0.0))]).unwrap();
+	assert_eq!((first.0, first.1, first.2.len()), (1, 0, 2));
+
+	// Not acknowledged yet, so everything is sent again
+	let second = encoder.encode(vec![(1, state(1.0, 0.0)), (2, state(0.0, 0.0))]).unwrap();
+	assert_eq!((second.0, second.1, second.2.len()), (2, 0, 2));
+
+	encoder.acknowledge(1);
+	assert_eq!(encoder.baseline(), Some(1));
+	let third = encoder.encode(vec![(1, state(1.0, 0.0)), (2, state(0.0, 0.0))]).unwrap();
+	assert_eq!((third.0, third.1, third.2.len()), (3, 1, 1));
+
+	// An older or unknown acknowledgement doesn't move the baseline back
+	encoder.acknowledge(1);
+	encoder.acknowledge(100);
+	assert_eq!(encoder.baseline(), Some(1));
+	encoder.acknowledge(3);
+	assert_eq!(encoder.baseline(), Some(3));
+	let fourth = encoder.encode(vec![(1, state(1.0, 0.0)), (2, state(0.0, 0.0))]).unwrap();
+	assert_eq!((fourth.1, fourth.2.len()), (3, 0));
+
+	// The client knows that nothing changed, so it doesn't need any more snapshots until something does
+	encoder.acknowledge(4);
+	assert_eq!(encoder.encode(vec![(1, state(1.0, 0.0)), (2, state(0.0, 0.0))]), None);
+	let fifth = encoder.encode(vec![(1, state(2.0, 0.0)), (2, state(0.0, 0.0))]).unwrap();
+	assert_eq!((fifth.0, fifth.1, fifth.2.len()), (5, 4, 1));
+}
+
+#[test]
+fn unacknowledged_changes_are_sent_until_they_are_acknowledged() {
+	let mut encoder = SnapshotEncoder::new();
+	let (sequence, _, _) = encoder.encode(vec![(1, state(0.0, 0.0))]).unwrap();
+	encoder.acknowledge(sequence);
+
+	// The entity moves and comes back before the client acknowledged the move
+	encoder.encode(vec![(1, state(1.0, 0.0))]).unwrap();
+	let (sequence, baseline, entities) = encoder.encode(vec![(1, state(0.0, 0.0))]).unwrap();
+	assert_eq!((baseline, entities.len()), (1, 0));
+	encoder.acknowledge(sequence);
+	assert_eq!(encoder.encode(vec![(1, state(0.0, 0.0))]), None);
+}
+
+#[test]
+fn forgotten_entities_are_sent_in_full_when_they_come_back() {
+	let mut encoder = SnapshotEncoder::new();
+	let mut decoder = SnapshotDecoder::new();
+	for _ in 0..2 {
+		let (sequence, baseline, entities) = encoder.encode(vec![(1, state(0.0, 0.0)), (2, state(5.0, 0.0))]).unwrap();
+		decoder.decode(sequence, baseline, &entities).unwrap();
+		encoder.acknowledge(sequence);
+	}
+
+	// Entity 2 goes out of sight, both sides forget it with the RemoveEntity
+	encoder.forget(2);
+	decoder.forget(2);
+	assert_eq!(encoder.encode(vec![(1, state(0.0, 0.0))]), None);
+
+	let expected = vec![(1, state(0.0, 0.0)), (2, state(5.0, 0.0))];
+	let (sequence, baseline, entities) = encoder.encode(expected.clone()).unwrap();
+	assert_eq!(baseline, 2);
+	assert_eq!(entities, vec![EntityDelta { uid: 2, position: Some(state(5.0, 0.0).position), rotation: Some(state(5.0, 0.0).rotation) }]);
+	assert_eq!(decoder.decode(sequence, baseline, &entities), Some(expected));
+}
+
+#[test]
+fn decoder_follows_the_encoder() {
+	let mut encoder = SnapshotEncoder::new();
+	let mut decoder = SnapshotDecoder::new();
+	let states: Vec<SnapshotState> = (0..20).map(|i| {
+		let mut state: SnapshotState = vec![(1, state(i as f32, 0.0)), (2, state(0.0, i as f32 * 0.1))];
+		if i % 5 != 0 {
+			state.push((3, QuantizedState::new([1.0, 2.0, 3.0], [0.0, 0.0, 0.0])));
+		}
+		state
+	}).collect();
+
+	let mut visible = Vec::new();
+	for (i, expected) in states.into_iter().enumerate() {
+		// Like the server, an entity that goes out of sight is forgotten before the next snapshot
+		for &uid in visible.iter().filter(|uid| !expected.iter().any(|e: &(u32, QuantizedState)| e.0 == **uid)) {
+			encoder.forget(uid);
+			decoder.forget(uid);
+		}
+		visible = expected.iter().map(|e| e.0).collect();
+		let (sequence, baseline, entities) = encoder.encode(expected.clone()).unwrap();
+		assert_eq!(decoder.decode(sequence, baseline, &entities), Some(expected));
+		// Acknowledge a few ticks late, like a client with some latency
+		if i >= 3 {
+			encoder.acknowledge(sequence - 3);
+		}
+	}
+}
+
+#[test]
+fn clients_that_stop_acknowledging_get_full_snapshots() {
+	let mut encoder = SnapshotEncoder::new();
+	let mut decoder = SnapshotDecoder::new();
+	let (sequence, baseline, entities) = encoder.encode(vec![(1, state(0.0, 0.0))]).unwrap();
+	decoder.decode(sequence, baseline, &entities).unwrap();
+	encoder.acknowledge(sequence);
+
+	// The acknowledgements get lost for a while
+	for i in 1..MAX_UNACKNOWLEDGED_SNAPSHOTS + 8 {
+		let expected = vec![(1, state(i as f32, 0.0))];
+		let (sequence, baseline, entities) = encoder.encode(expected.clone()).unwrap();
+		assert_eq!(decoder.decode(sequence, baseline, &entities), Some(expected));
+		if sequence > MAX_UNACKNOWLEDGED_SNAPSHOTS as u32 {
+			assert_eq!(baseline, 0);
+		}
+	}
+}
+
+#[test]
+fn decoder_rejects_unknown_baselines() {
+	let mut decoder = SnapshotDecoder::new();
+	assert_eq!(decoder.decode(5, 4, &[]), None);
+	assert_eq!(decoder.decode(6, 0, &[]), Some(Vec::new()));
+	assert_eq!(decoder.decode(7, 6, &[]), Some(Vec::new()));
+}
+
+// A smaller version of examples/snapshot_bandwidth.rs
+#[test]
+fn snapshots_are_smaller_than_set_positions() {
+	let size = |message: &NetworkMessage| encode(message, SizeLimit::Infinite).unwrap().len();
+	let mut encoder = SnapshotEncoder::new();
+	let mut set_position_bytes = 0;
+	let mut snapshot_bytes = 0;
+	for tick in 0..100 {
+		// One out of ten entities moves
+		let state: SnapshotState = (0..100).map(|i| (i, state(if i % 10 == 0 { tick as f32 } else { 0.0 }, 0.0))).collect();
+		for &(uid, ref entity) in &state {
+			set_position_bytes += size(&NetworkMessage::SetPosition { uid: uid, position: entity.position(), rotation: entity.rotation() });
+		}
+		let (sequence, baseline, entities) = encoder.encode(state).unwrap();
+		snapshot_bytes += size(&NetworkMessage::WorldSnapshot { sequence: sequence, time: 0.0, baseline: baseline, entities: entities });
+		encoder.acknowledge(sequence);
+	}
+	assert!(snapshot_bytes * 5 < set_position_bytes, "{} bytes of snapshots against {} bytes of SetPosition", snapshot_bytes, set_position_bytes);
+}
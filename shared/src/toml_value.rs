@@ -0,0 +1,49 @@
+// Reading values from the toml files of the client and the server
+// The errors are meant for people, name is the setting as it is written in the file, like network.port
+
+use toml::Value;
+
+pub fn string(name: &str, value: &Value) -> Result<String, String> {
+	match *value {
+		Value::String(ref s) => Ok(s.clone()),
+		_ => Err(format!("{} should be a string, not {}", name, value.type_str()))
+	}
+}
+
+pub fn integer(name: &str, value: &Value, min: i64, max: i64) -> Result<i64, String> {
+	match *value {
+		Value::Integer(i) if i >= min && i <= max => Ok(i),
+		Value::Integer(i) => Err(format!("{} has to be between {} and {}, not {}", name, min, max, i)),
+		_ => Err(format!("{} should be an integer, not {}", name, value.type_str()))
+	}
+}
+
+// Integers are accepted as well, nobody wants to write 60.0 for a minute
+pub fn float(name: &str, value: &Value) -> Result<f64, String> {
+	match *value {
+		Value::Float(f) => Ok(f),
+		Value::Integer(i) => Ok(i as f64),
+		_ => Err(format!("{} should be a number, not {}", name, value.type_str()))
+	}
+}
+
+pub fn float_between(name: &str, value: &Value, min: f64, max: f64) -> Result<f64, String> {
+	match try!(float(name, value)) {
+		f if f >= min && f <= max => Ok(f),
+		f => Err(format!("{} has to be between {} and {}, not {}", name, min, max, f))
+	}
+}
+
+pub fn positive(name: &str, value: &Value) -> Result<f64, String> {
+	match try!(float(name, value)) {
+		f if f > 0.0 => Ok(f),
+		f => Err(format!("{} has to be more than 0, not {}", name, f))
+	}
+}
+
+pub fn boolean(name: &str, value: &Value) -> Result<bool, String> {
+	match *value {
+		Value::Boolean(b) => Ok(b),
+		_ => Err(format!("{} should be true or false, not {}", name, value.type_str()))
+	}
+}